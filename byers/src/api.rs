@@ -0,0 +1,727 @@
+//! The public JSON API of our website, served next to the OAuth2 flow.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_sessions::extractors::ReadableSession;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use fred::pool::RedisPool;
+use judeharley::{
+    communication::{LiquidsoapClient, LiquidsoapConnection},
+    db::{DbPlaylist, DbRequestPolicy, DbSong, SongRating},
+    requests::{RequestDenied, RequestKind, RequestOutcome, RequestService},
+    JudeHarleyError, PgPool,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 100;
+
+/// The guild whose request policy applies to the web API, the default policy if `None`
+#[derive(Debug, Clone, Copy)]
+pub struct RequestGuild(pub Option<i64>);
+
+/// Discord users who may manage playlists
+#[derive(Debug, Clone, Default)]
+pub struct ApiAdmins(pub Arc<Vec<i64>>);
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub enum ApiResponse<T> {
+    Success { data: T },
+    Error { error: String },
+}
+
+impl<T> ApiResponse<T> {
+    fn error(error: &str) -> Self {
+        Self::Error {
+            error: error.to_string(),
+        }
+    }
+}
+
+impl<T> IntoResponse for ApiResponse<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> axum::response::Response {
+        (axum::http::StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    items: Vec<T>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Song {
+    id: String,
+    title: String,
+    artist: String,
+    album: String,
+    duration: f64,
+}
+
+impl From<DbSong> for Song {
+    fn from(value: DbSong) -> Self {
+        Self {
+            id: value.file_hash,
+            title: value.title,
+            artist: value.artist,
+            album: value.album,
+            duration: value.duration,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Tag {
+    tag: String,
+    value: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Rating {
+    likes: i64,
+    dislikes: i64,
+}
+
+impl From<SongRating> for Rating {
+    fn from(value: SongRating) -> Self {
+        Self {
+            likes: value.likes,
+            dislikes: value.dislikes,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Cooldown {
+    on_cooldown: bool,
+    /// When the song can be requested again
+    until: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SongDetails {
+    #[serde(flatten)]
+    song: Song,
+    bitrate: i32,
+    tags: Vec<Tag>,
+    played: i64,
+    requested: i64,
+    rating: Rating,
+    cooldown: Cooldown,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SongListParams {
+    q: Option<String>,
+    album: Option<String>,
+    artist: Option<String>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// Empty parameters mean "don't filter", `?q=` is what a cleared search box sends
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// `GET /api/songs?q=&album=&artist=&page=&per_page=`, pages start at 1
+pub async fn song_list(
+    State(db): State<PgPool>,
+    Query(params): Query<SongListParams>,
+) -> ApiResponse<Page<Song>> {
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    let result = DbSong::search_paginated(
+        &db,
+        non_empty(&params.q),
+        non_empty(&params.album),
+        non_empty(&params.artist),
+        per_page,
+        (page - 1).saturating_mul(per_page),
+    )
+    .await;
+    let (songs, total) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Failed to fetch songs: {}", e);
+            return ApiResponse::error("Failed to fetch songs");
+        }
+    };
+
+    ApiResponse::Success {
+        data: Page {
+            items: songs.into_iter().map(Into::into).collect(),
+            page,
+            per_page,
+            total,
+        },
+    }
+}
+
+async fn song_details(
+    db: &PgPool,
+    song: DbSong,
+    policy: &DbRequestPolicy,
+) -> Result<SongDetails, JudeHarleyError> {
+    let tags = song.tags(db).await?;
+    let played = song.played(db).await?;
+    let requested = song.requested(db).await?;
+    let rating = song.rating(db).await?;
+    let cooldown_until = song.cooldown_until(db, policy).await?;
+
+    Ok(SongDetails {
+        bitrate: song.bitrate,
+        tags: tags
+            .into_iter()
+            .map(|(tag, value)| Tag { tag, value })
+            .collect(),
+        played,
+        requested,
+        rating: rating.into(),
+        cooldown: Cooldown {
+            on_cooldown: cooldown_until.is_some(),
+            until: cooldown_until.map(utc),
+        },
+        song: song.into(),
+    })
+}
+
+/// `GET /api/songs/:hash`
+pub async fn song(
+    State(db): State<PgPool>,
+    State(RequestGuild(guild_id)): State<RequestGuild>,
+    Path(hash): Path<String>,
+) -> (StatusCode, ApiResponse<SongDetails>) {
+    let song = match DbSong::fetch_from_hash(&db, &hash).await {
+        Ok(Some(song)) => song,
+        Ok(None) => return (StatusCode::NOT_FOUND, ApiResponse::error("Song not found")),
+        Err(e) => return failed("Failed to fetch song", e),
+    };
+
+    let details = match DbRequestPolicy::fetch_for(&db, guild_id).await {
+        Ok(policy) => song_details(&db, song, &policy).await,
+        Err(e) => Err(e),
+    };
+    match details {
+        Ok(details) => (StatusCode::OK, ApiResponse::Success { data: details }),
+        Err(e) => failed("Failed to fetch song", e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SongRequestBody {
+    /// Hash of the song
+    song: String,
+    /// `regular`, `bypass_cooldown` or `premium`
+    #[serde(default)]
+    kind: RequestKind,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SongRequestResponse {
+    song: Song,
+    /// When the user can request another song
+    next_request_at: DateTime<Utc>,
+    /// Boondollars the user paid
+    price: i32,
+}
+
+fn utc(time: NaiveDateTime) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&time)
+}
+
+/// `POST /api/requests`, requests a song as the user logged in through `/oauth2/login`
+pub async fn request_song(
+    State(db): State<PgPool>,
+    State(redis): State<RedisPool>,
+    State(comms): State<LiquidsoapClient<LiquidsoapConnection>>,
+    State(RequestGuild(guild_id)): State<RequestGuild>,
+    session: ReadableSession,
+    Json(body): Json<SongRequestBody>,
+) -> (StatusCode, ApiResponse<SongRequestResponse>) {
+    let Some(user_id) = session.get::<i64>("user_id") else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            ApiResponse::error("You need to log in to request songs"),
+        );
+    };
+
+    let result = try_request_song(
+        &db, &redis, &comms, guild_id, user_id, &body.song, body.kind,
+    )
+    .await;
+    match result {
+        Ok(response) => response,
+        Err(e) => {
+            error!(
+                "Failed to request song {} for {}: {}",
+                body.song, user_id, e
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ApiResponse::error("Failed to request song"),
+            )
+        }
+    }
+}
+
+async fn try_request_song(
+    db: &PgPool,
+    redis: &RedisPool,
+    comms: &LiquidsoapClient<LiquidsoapConnection>,
+    guild_id: Option<i64>,
+    user_id: i64,
+    hash: &str,
+    kind: RequestKind,
+) -> Result<(StatusCode, ApiResponse<SongRequestResponse>), JudeHarleyError> {
+    let policy = DbRequestPolicy::fetch_for(db, guild_id).await?;
+    let service = RequestService::new(db, redis, comms).with_policy(policy);
+    let denied = match service.request_with(user_id as u64, hash, kind).await? {
+        RequestOutcome::Requested {
+            song,
+            next_request_at,
+            price,
+            ..
+        } => {
+            info!("{} requested {} through the web API", user_id, song);
+            return Ok((
+                StatusCode::OK,
+                ApiResponse::Success {
+                    data: SongRequestResponse {
+                        song: song.into(),
+                        next_request_at: utc(next_request_at),
+                        price,
+                    },
+                },
+            ));
+        }
+        RequestOutcome::Unconfirmed {
+            song,
+            next_request_at,
+            price,
+        } => {
+            info!(
+                "{} requested {} through the web API, unconfirmed",
+                user_id, song
+            );
+            // it will most likely play, but we can't promise it
+            return Ok((
+                StatusCode::ACCEPTED,
+                ApiResponse::Success {
+                    data: SongRequestResponse {
+                        song: song.into(),
+                        next_request_at: utc(next_request_at),
+                        price,
+                    },
+                },
+            ));
+        }
+        RequestOutcome::Denied(denied) => denied,
+    };
+
+    let (status, error) = match denied {
+        RequestDenied::RequestsClosed => (
+            StatusCode::FORBIDDEN,
+            "Song requests are closed right now".to_string(),
+        ),
+        RequestDenied::NotForSale => (
+            StatusCode::FORBIDDEN,
+            "This kind of request can't be bought".to_string(),
+        ),
+        RequestDenied::TooManyPending(max) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("You can only have {} requests in the queue at once", max),
+        ),
+        RequestDenied::QueueFull(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The request queue is full".to_string(),
+        ),
+        RequestDenied::UserCooldown(over) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("You can request a song again at {}", utc(over).to_rfc3339()),
+        ),
+        RequestDenied::SongNotFound => (StatusCode::NOT_FOUND, "Song not found".to_string()),
+        RequestDenied::NothingPlaying => (
+            StatusCode::CONFLICT,
+            "Nothing is currently playing".to_string(),
+        ),
+        RequestDenied::CurrentlyPlaying => (
+            StatusCode::CONFLICT,
+            "This song is currently playing".to_string(),
+        ),
+        RequestDenied::SongCooldown(over) => (
+            StatusCode::CONFLICT,
+            format!(
+                "This song has been requested recently, it can be requested again at {}",
+                utc(over).to_rfc3339()
+            ),
+        ),
+        RequestDenied::InsufficientFunds { price, balance } => (
+            StatusCode::PAYMENT_REQUIRED,
+            format!(
+                "This request costs {} Boondollars, but you only have {}",
+                price, balance
+            ),
+        ),
+    };
+
+    Ok((status, ApiResponse::error(&error)))
+}
+
+/// Checks that the user logged in through `/oauth2/login` is one of the [`ApiAdmins`]
+fn require_admin<T>(
+    session: &ReadableSession,
+    admins: &ApiAdmins,
+) -> Result<(), (StatusCode, ApiResponse<T>)> {
+    let Some(user_id) = session.get::<i64>("user_id") else {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            ApiResponse::error("You need to log in to manage playlists"),
+        ));
+    };
+    if !admins.0.contains(&user_id) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ApiResponse::error("You are not allowed to manage playlists"),
+        ));
+    }
+
+    Ok(())
+}
+
+fn failed<T>(error: &str, e: JudeHarleyError) -> (StatusCode, ApiResponse<T>) {
+    error!("{}: {}", error, e);
+    (StatusCode::INTERNAL_SERVER_ERROR, ApiResponse::error(error))
+}
+
+#[derive(Serialize, Debug)]
+pub struct Playlist {
+    id: i32,
+    name: String,
+    description: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<DbPlaylist> for Playlist {
+    fn from(value: DbPlaylist) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            description: value.description,
+            updated_at: utc(value.updated_at),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlaylistSong {
+    /// Starts at 1
+    position: i32,
+    #[serde(flatten)]
+    song: Song,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlaylistDetails {
+    #[serde(flatten)]
+    playlist: Playlist,
+    songs: Vec<PlaylistSong>,
+    /// Seconds
+    duration: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreatePlaylistBody {
+    name: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpdatePlaylistBody {
+    name: Option<String>,
+    /// An empty description removes it
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlaylistSongBody {
+    /// Hash of the song
+    song: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlaylistSongAdded {
+    position: i32,
+}
+
+/// `GET /api/playlists`
+pub async fn playlist_list(State(db): State<PgPool>) -> ApiResponse<Vec<Playlist>> {
+    match DbPlaylist::fetch_all(&db).await {
+        Ok(playlists) => ApiResponse::Success {
+            data: playlists.into_iter().map(Into::into).collect(),
+        },
+        Err(e) => {
+            error!("Failed to fetch playlists: {}", e);
+            ApiResponse::error("Failed to fetch playlists")
+        }
+    }
+}
+
+/// `GET /api/playlists/:id`, with the songs in order
+pub async fn playlist(
+    State(db): State<PgPool>,
+    Path(id): Path<i32>,
+) -> (StatusCode, ApiResponse<PlaylistDetails>) {
+    try_playlist(&db, id)
+        .await
+        .unwrap_or_else(|e| failed("Failed to fetch playlist", e))
+}
+
+async fn try_playlist(
+    db: &PgPool,
+    id: i32,
+) -> Result<(StatusCode, ApiResponse<PlaylistDetails>), JudeHarleyError> {
+    let Some(playlist) = DbPlaylist::fetch(db, id).await? else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            ApiResponse::error("Playlist not found"),
+        ));
+    };
+    let entries = DbPlaylist::entries(db, id).await?;
+
+    Ok((
+        StatusCode::OK,
+        ApiResponse::Success {
+            data: PlaylistDetails {
+                playlist: playlist.into(),
+                duration: entries.iter().map(|entry| entry.song.duration).sum(),
+                songs: entries
+                    .into_iter()
+                    .map(|entry| PlaylistSong {
+                        position: entry.position,
+                        song: entry.song.into(),
+                    })
+                    .collect(),
+            },
+        },
+    ))
+}
+
+/// `POST /api/playlists`, for [`ApiAdmins`] only
+pub async fn create_playlist(
+    State(db): State<PgPool>,
+    State(admins): State<ApiAdmins>,
+    session: ReadableSession,
+    Json(body): Json<CreatePlaylistBody>,
+) -> (StatusCode, ApiResponse<Playlist>) {
+    if let Err(denied) = require_admin(&session, &admins) {
+        return denied;
+    }
+
+    try_create_playlist(&db, body)
+        .await
+        .unwrap_or_else(|e| failed("Failed to create playlist", e))
+}
+
+async fn try_create_playlist(
+    db: &PgPool,
+    body: CreatePlaylistBody,
+) -> Result<(StatusCode, ApiResponse<Playlist>), JudeHarleyError> {
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            ApiResponse::error("The name can't be empty"),
+        ));
+    }
+    if DbPlaylist::fetch_by_name(db, name).await?.is_some() {
+        return Ok((
+            StatusCode::CONFLICT,
+            ApiResponse::error("There already is a playlist with this name"),
+        ));
+    }
+
+    let description = non_empty(&body.description);
+    let playlist = DbPlaylist::create(db, name, description).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        ApiResponse::Success {
+            data: playlist.into(),
+        },
+    ))
+}
+
+/// `PUT /api/playlists/:id`, for [`ApiAdmins`] only
+pub async fn update_playlist(
+    State(db): State<PgPool>,
+    State(admins): State<ApiAdmins>,
+    session: ReadableSession,
+    Path(id): Path<i32>,
+    Json(body): Json<UpdatePlaylistBody>,
+) -> (StatusCode, ApiResponse<Playlist>) {
+    if let Err(denied) = require_admin(&session, &admins) {
+        return denied;
+    }
+
+    try_update_playlist(&db, id, body)
+        .await
+        .unwrap_or_else(|e| failed("Failed to update playlist", e))
+}
+
+async fn try_update_playlist(
+    db: &PgPool,
+    id: i32,
+    body: UpdatePlaylistBody,
+) -> Result<(StatusCode, ApiResponse<Playlist>), JudeHarleyError> {
+    let Some(mut playlist) = DbPlaylist::fetch(db, id).await? else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            ApiResponse::error("Playlist not found"),
+        ));
+    };
+
+    if let Some(name) = &body.name {
+        let name = name.trim();
+        if name.is_empty() {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                ApiResponse::error("The name can't be empty"),
+            ));
+        }
+        if let Some(existing) = DbPlaylist::fetch_by_name(db, name).await? {
+            if existing.id != id {
+                return Ok((
+                    StatusCode::CONFLICT,
+                    ApiResponse::error("There already is a playlist with this name"),
+                ));
+            }
+        }
+        playlist.name = name.to_string();
+    }
+    if body.description.is_some() {
+        playlist.description = non_empty(&body.description).map(ToString::to_string);
+    }
+    playlist.update(db).await?;
+
+    Ok((
+        StatusCode::OK,
+        ApiResponse::Success {
+            data: playlist.into(),
+        },
+    ))
+}
+
+/// `DELETE /api/playlists/:id`, for [`ApiAdmins`] only. Schedule slots playing the playlist
+/// are deleted with it.
+pub async fn delete_playlist(
+    State(db): State<PgPool>,
+    State(admins): State<ApiAdmins>,
+    session: ReadableSession,
+    Path(id): Path<i32>,
+) -> (StatusCode, ApiResponse<()>) {
+    if let Err(denied) = require_admin(&session, &admins) {
+        return denied;
+    }
+
+    match DbPlaylist::delete(&db, id).await {
+        Ok(true) => (StatusCode::OK, ApiResponse::Success { data: () }),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            ApiResponse::error("Playlist not found"),
+        ),
+        Err(e) => failed("Failed to delete playlist", e),
+    }
+}
+
+/// `POST /api/playlists/:id/songs`, appends a song, for [`ApiAdmins`] only
+pub async fn add_playlist_song(
+    State(db): State<PgPool>,
+    State(admins): State<ApiAdmins>,
+    session: ReadableSession,
+    Path(id): Path<i32>,
+    Json(body): Json<PlaylistSongBody>,
+) -> (StatusCode, ApiResponse<PlaylistSongAdded>) {
+    if let Err(denied) = require_admin(&session, &admins) {
+        return denied;
+    }
+
+    try_add_playlist_song(&db, id, &body.song)
+        .await
+        .unwrap_or_else(|e| failed("Failed to add song to playlist", e))
+}
+
+async fn try_add_playlist_song(
+    db: &PgPool,
+    id: i32,
+    hash: &str,
+) -> Result<(StatusCode, ApiResponse<PlaylistSongAdded>), JudeHarleyError> {
+    let Some(playlist) = DbPlaylist::fetch(db, id).await? else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            ApiResponse::error("Playlist not found"),
+        ));
+    };
+    let Some(song) = DbSong::fetch_from_hash(db, hash).await? else {
+        return Ok((StatusCode::NOT_FOUND, ApiResponse::error("Song not found")));
+    };
+
+    let position = playlist.add_song(db, &song.file_hash).await?;
+
+    Ok((
+        StatusCode::OK,
+        ApiResponse::Success {
+            data: PlaylistSongAdded { position },
+        },
+    ))
+}
+
+/// `DELETE /api/playlists/:id/songs/:position`, for [`ApiAdmins`] only. The songs after it
+/// move up.
+pub async fn remove_playlist_song(
+    State(db): State<PgPool>,
+    State(admins): State<ApiAdmins>,
+    session: ReadableSession,
+    Path((id, position)): Path<(i32, i32)>,
+) -> (StatusCode, ApiResponse<()>) {
+    if let Err(denied) = require_admin(&session, &admins) {
+        return denied;
+    }
+
+    let result = match DbPlaylist::fetch(&db, id).await {
+        Ok(Some(playlist)) => playlist.remove_song(&db, position).await,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                ApiResponse::error("Playlist not found"),
+            )
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(true) => (StatusCode::OK, ApiResponse::Success { data: () }),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            ApiResponse::error("There is no song at this position"),
+        ),
+        Err(e) => failed("Failed to remove song from playlist", e),
+    }
+}
@@ -1,3 +1,12 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use judeharley::{
+    autodj::AutoDjOptions,
+    communication::{
+        ByersTcpStream, ByersUnixStream, ClientOptions, ConnectOptions, LiquidsoapConnection,
+    },
+    JudeHarleyError,
+};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
@@ -11,12 +20,93 @@ pub struct AppConfig {
     pub google: GoogleConfig,
     pub discord: DiscordConfig,
     pub secret: String,
+
+    /// Address the OAuth2 and API web server listens on
+    #[serde(default = "judeharley::health::default_bind_address")]
+    pub bind_address: SocketAddr,
+    /// Address Prometheus metrics are served on, e.g. `METRICS_ADDRESS=127.0.0.1:9000`. They
+    /// aren't served at all without it, so they don't end up on the public web server.
+    pub metrics_address: Option<SocketAddr>,
+    /// Guild whose `/config requests` policy applies to requests made through the web API
+    pub request_guild_id: Option<i64>,
+    /// Discord users who may manage playlists through the web API, e.g. `API_ADMINS=123,456`
+    #[serde(default)]
+    pub api_admins: Vec<i64>,
+
+    #[serde(default)]
+    pub autodj: AutoDjConfig,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LiquidsoapTransport {
+    #[default]
+    Unix,
+    Telnet,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct LiquidsoapConfig {
+    #[serde(default)]
+    pub transport: LiquidsoapTransport,
+    #[serde(default = "default_socket_path")]
+    pub socket_path: PathBuf,
     pub host: String,
     pub port: u16,
+    /// Seconds to wait for a single connection attempt.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    #[serde(default = "default_connect_attempts")]
+    pub connect_attempts: u32,
+    /// Seconds a command may wait in the queue and for Liquidsoap's response.
+    #[serde(default = "default_command_timeout")]
+    pub command_timeout: u64,
+}
+
+fn default_socket_path() -> PathBuf {
+    PathBuf::from("/usr/src/app/ls/lumiradio.sock")
+}
+
+fn default_connect_timeout() -> u64 {
+    5
+}
+
+fn default_connect_attempts() -> u32 {
+    10
+}
+
+fn default_command_timeout() -> u64 {
+    10
+}
+
+impl LiquidsoapConfig {
+    pub fn connect_options(&self) -> ConnectOptions {
+        ConnectOptions {
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            max_attempts: self.connect_attempts.max(1),
+            ..Default::default()
+        }
+    }
+
+    pub fn client_options(&self) -> ClientOptions {
+        ClientOptions {
+            command_timeout: Duration::from_secs(self.command_timeout),
+            ..Default::default()
+        }
+    }
+
+    pub async fn connect(&self) -> Result<LiquidsoapConnection, JudeHarleyError> {
+        let options = self.connect_options();
+
+        Ok(match self.transport {
+            LiquidsoapTransport::Unix => {
+                LiquidsoapConnection::Unix(ByersUnixStream::new(&self.socket_path, options).await?)
+            }
+            LiquidsoapTransport::Telnet => LiquidsoapConnection::Telnet(
+                ByersTcpStream::new(&self.host, self.port, options).await?,
+            ),
+        })
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -31,10 +121,60 @@ pub struct DiscordConfig {
     pub client_secret: String,
 }
 
+/// The auto-DJ, which only runs with `AUTODJ__ENABLED=true`. Anything left out uses the
+/// defaults of [`AutoDjOptions`].
+#[derive(Deserialize, Debug, Default)]
+pub struct AutoDjConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Minutes before the same song may play again
+    pub song_separation: Option<i64>,
+    /// Minutes before a song by the same artist may play again
+    pub artist_separation: Option<i64>,
+    /// Minutes before a song from the same album may play again
+    pub album_separation: Option<i64>,
+    /// Seconds a song may be long at most
+    pub max_duration: Option<f64>,
+    /// Seconds of music to keep queued
+    pub lookahead: Option<f64>,
+    /// How much every like raises the chance of a song to be picked, 0 ignores ratings
+    pub rating_weight: Option<f64>,
+}
+
+impl AutoDjConfig {
+    pub fn options(&self) -> AutoDjOptions {
+        let defaults = AutoDjOptions::default();
+
+        AutoDjOptions {
+            song_separation: self
+                .song_separation
+                .map(chrono::Duration::minutes)
+                .unwrap_or(defaults.song_separation),
+            artist_separation: self
+                .artist_separation
+                .map(chrono::Duration::minutes)
+                .unwrap_or(defaults.artist_separation),
+            album_separation: self
+                .album_separation
+                .map(chrono::Duration::minutes)
+                .unwrap_or(defaults.album_separation),
+            max_duration: self.max_duration.or(defaults.max_duration),
+            lookahead: self.lookahead.unwrap_or(defaults.lookahead),
+            rating_weight: self.rating_weight.unwrap_or(defaults.rating_weight),
+            ..defaults
+        }
+    }
+}
+
 impl AppConfig {
     pub fn from_env() -> Self {
         let config = config::Config::builder()
-            .add_source(config::Environment::default().separator("__"))
+            .add_source(
+                config::Environment::default()
+                    .separator("__")
+                    .list_separator(",")
+                    .with_list_parse_key("api_admins"),
+            )
             .build()
             .unwrap();
 
@@ -2,7 +2,9 @@ use poise::serenity_prelude::{Channel, ChannelId, Role, UserId};
 use serde::de;
 
 use crate::prelude::*;
-use judeharley::db::{DbCan, DbServerChannelConfig, DbServerConfig, DbServerRoleConfig, DbUser};
+use judeharley::db::{
+    DbCan, DbRequestPolicy, DbServerChannelConfig, DbServerConfig, DbServerRoleConfig, DbUser,
+};
 
 /// Configuration-related commands
 #[poise::command(
@@ -11,6 +13,8 @@ use judeharley::db::{DbCan, DbServerChannelConfig, DbServerConfig, DbServerRoleC
     ephemeral,
     subcommands(
         "manage_channel",
+        "announcements",
+        "requests",
         "set_can_count",
         "set_quest_roll",
         "manage_role",
@@ -179,3 +183,170 @@ pub async fn manage_channel(
 
     Ok(())
 }
+
+/// Announces song requests in a channel when they start playing
+#[poise::command(slash_command, owners_only, ephemeral, guild_only)]
+pub async fn announcements(
+    ctx: ApplicationContext<'_>,
+    #[description = "Channel to announce in, leave empty to turn announcements off"]
+    channel: Option<Channel>,
+    #[description = "Mention the user who requested the song"] ping_requester: Option<bool>,
+) -> Result<(), Error> {
+    let data = ctx.data;
+    let ping_requester = ping_requester.unwrap_or(false);
+
+    DbServerChannelConfig::set_announcement_channel(
+        &data.db,
+        ctx.guild_id().unwrap().0 as i64,
+        channel.as_ref().map(|c| c.id().0 as i64),
+        ping_requester,
+    )
+    .await?;
+
+    ctx.send(|m| {
+        m.embed(|e| match &channel {
+            Some(channel) => e
+                .title("Announcements Configured")
+                .field("Channel", format!("<#{}>", channel.id()), true)
+                .field("Ping requester", ping_requester.to_string(), true),
+            None => e
+                .title("Announcements Disabled")
+                .description("Song requests will no longer be announced"),
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+fn format_minutes(seconds: i32) -> String {
+    format!("{} minutes", seconds / 60)
+}
+
+fn format_limit(limit: Option<i32>) -> String {
+    limit
+        .map(|l| l.to_string())
+        .unwrap_or_else(|| "None".to_string())
+}
+
+fn format_price(price: Option<i32>) -> String {
+    price
+        .map(|p| format!("{} Boondollars", p))
+        .unwrap_or_else(|| "Not offered".to_string())
+}
+
+/// Sets the song request rules, options left empty stay as they are
+#[poise::command(slash_command, owners_only, ephemeral, guild_only)]
+#[allow(clippy::too_many_arguments)]
+pub async fn requests(
+    ctx: ApplicationContext<'_>,
+    #[description = "Whether songs can be requested"] open: Option<bool>,
+    #[description = "Minutes users have to wait between requests"]
+    #[min = 0]
+    #[max = 10080]
+    user_cooldown: Option<i32>,
+    #[description = "Minutes before a song shorter than 5 minutes can be requested again"]
+    #[min = 0]
+    #[max = 10080]
+    short_song_cooldown: Option<i32>,
+    #[description = "Minutes before a song shorter than 10 minutes can be requested again"]
+    #[min = 0]
+    #[max = 10080]
+    medium_song_cooldown: Option<i32>,
+    #[description = "Minutes before a longer song can be requested again"]
+    #[min = 0]
+    #[max = 10080]
+    long_song_cooldown: Option<i32>,
+    #[description = "Requests a user may have waiting in the queue, 0 for no limit"]
+    #[min = 0]
+    max_pending: Option<i32>,
+    #[description = "Requests the queue may hold, 0 for no limit"]
+    #[min = 0]
+    max_queue_length: Option<i32>,
+    #[description = "Boondollars a request costs"]
+    #[min = 0]
+    price: Option<i32>,
+    #[description = "Boondollars it costs to skip the request cooldown, 0 to not offer it"]
+    #[min = 0]
+    cooldown_bypass_price: Option<i32>,
+    #[description = "Boondollars it costs to have a request played next, 0 to not offer it"]
+    #[min = 0]
+    premium_price: Option<i32>,
+) -> Result<(), Error> {
+    let data = ctx.data;
+    let guild_id = ctx.guild_id().unwrap().0 as i64;
+
+    let mut policy = DbRequestPolicy::fetch(&data.db, guild_id).await?;
+    if let Some(open) = open {
+        policy.requests_open = open;
+    }
+    if let Some(minutes) = user_cooldown {
+        policy.user_cooldown = minutes * 60;
+    }
+    if let Some(minutes) = short_song_cooldown {
+        policy.short_song_cooldown = minutes * 60;
+    }
+    if let Some(minutes) = medium_song_cooldown {
+        policy.medium_song_cooldown = minutes * 60;
+    }
+    if let Some(minutes) = long_song_cooldown {
+        policy.long_song_cooldown = minutes * 60;
+    }
+    if let Some(max) = max_pending {
+        policy.max_pending = Some(max).filter(|max| *max > 0);
+    }
+    if let Some(max) = max_queue_length {
+        policy.max_queue_length = Some(max).filter(|max| *max > 0);
+    }
+    if let Some(price) = price {
+        policy.price = price;
+    }
+    if let Some(price) = cooldown_bypass_price {
+        policy.cooldown_bypass_price = Some(price).filter(|price| *price > 0);
+    }
+    if let Some(price) = premium_price {
+        policy.premium_price = Some(price).filter(|price| *price > 0);
+    }
+    policy.update(&data.db, guild_id).await?;
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Song Requests Configured")
+                .field("Open", policy.requests_open.to_string(), true)
+                .field("User cooldown", format_minutes(policy.user_cooldown), true)
+                .field(
+                    "Song cooldowns",
+                    format!(
+                        "Under 5 minutes: {}\nUnder 10 minutes: {}\nLonger: {}",
+                        format_minutes(policy.short_song_cooldown),
+                        format_minutes(policy.medium_song_cooldown),
+                        format_minutes(policy.long_song_cooldown)
+                    ),
+                    false,
+                )
+                .field(
+                    "Max pending per user",
+                    format_limit(policy.max_pending),
+                    true,
+                )
+                .field(
+                    "Max queue length",
+                    format_limit(policy.max_queue_length),
+                    true,
+                )
+                .field(
+                    "Prices",
+                    format!(
+                        "Request: {}\nSkip cooldown: {}\nPlay next: {}",
+                        format_price(Some(policy.price)),
+                        format_price(policy.cooldown_bypass_price),
+                        format_price(policy.premium_price)
+                    ),
+                    false,
+                )
+        })
+    })
+    .await?;
+
+    Ok(())
+}
@@ -1,29 +1,70 @@
 use crate::commands::autocomplete_songs;
 use crate::prelude::*;
-use judeharley::{communication::LiquidsoapCommunication, db::DbSong};
+use judeharley::{
+    communication::{RequestQueue, SkipTarget},
+    db::DbSong,
+};
 
 /// Reconnects the Liquidsoap command socket
 #[poise::command(slash_command, ephemeral, owners_only)]
 pub async fn reconnect(ctx: ApplicationContext<'_>) -> Result<(), Error> {
-    let mut comms = ctx.data.comms.lock().await;
-
-    comms.reconnect().await?;
+    ctx.data.comms.reconnect().await?;
     ctx.send(|m| m.content("Reconnected to Liquidsoap")).await?;
 
     Ok(())
 }
 
+/// Shows the state of the Liquidsoap command queue
+#[poise::command(slash_command, ephemeral, owners_only)]
+pub async fn liquidsoap_status(ctx: ApplicationContext<'_>) -> Result<(), Error> {
+    let stats = ctx.data.comms.stats();
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Liquidsoap Status")
+                .field("Queued Commands", stats.queue_depth, true)
+                .field("Commands Sent", stats.commands, true)
+                .field("Failures", stats.failures, true)
+                .field("Timeouts", stats.timeouts, true)
+                .field("Reconnects", stats.reconnects, true)
+                .field(
+                    "Latency",
+                    format!(
+                        "{}ms (average {}ms)",
+                        stats.last_latency.as_millis(),
+                        stats.average_latency.as_millis()
+                    ),
+                    true,
+                )
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
 /// Reindexes the song database
 #[poise::command(slash_command, ephemeral, owners_only)]
 pub async fn reindex(ctx: ApplicationContext<'_>) -> Result<(), Error> {
     let data = ctx.data;
-    let mut comms = ctx.data.comms.lock().await;
 
     ctx.defer_ephemeral().await?;
-    judeharley::maintenance::indexing::index(data.db.clone(), "/music".into()).await?;
-    comms.send("music.reload").await?;
-    ctx.send(|m| m.content("Reindexed the song database."))
-        .await?;
+    let report =
+        judeharley::maintenance::indexing::index(data.db.clone(), "/music".into(), false).await?;
+    data.comms.send("music.reload").await?;
+    ctx.send(|m| {
+        m.content(format!(
+            "Reindexed the song database: {} added, {} updated, {} moved, {} removed, {} unchanged, {} failed, {} unreadable directory entries.",
+            report.added,
+            report.updated,
+            report.moved,
+            report.removed,
+            report.unchanged,
+            report.failed.len(),
+            report.unwalkable
+        ))
+    })
+    .await?;
 
     Ok(())
 }
@@ -34,9 +75,7 @@ pub async fn control_cmd(
     ctx: ApplicationContext<'_>,
     #[description = "Command to send"] command: String,
 ) -> Result<(), Error> {
-    let mut comms = ctx.data.comms.lock().await;
-
-    let mut response = comms.send_wait(&command).await?.trim().to_string();
+    let mut response = ctx.data.comms.send_wait(&command).await?.trim().to_string();
     response.truncate(2000);
     ctx.send(|m| {
         m.embed(|e| {
@@ -78,6 +117,7 @@ pub async fn song_info(
     } else {
         tags_str
     };
+    let rating = song.rating(&data.db).await?;
 
     ctx.send(|m| {
         m.embed(|e| {
@@ -93,6 +133,11 @@ pub async fn song_info(
                 .field("File Path", &song.file_path, true)
                 .field("ID", &song.file_hash, true)
                 .field("Tags", &tags_str, true)
+                .field(
+                    "Rating",
+                    format!("👍 {} / 👎 {}", rating.likes, rating.dislikes),
+                    true,
+                )
         })
     })
     .await?;
@@ -109,11 +154,10 @@ pub async fn volume(
     #[max = 100]
     volume: Option<i32>,
 ) -> Result<(), Error> {
-    let mut comms = ctx.data.comms.lock().await;
+    let comms = &ctx.data.comms;
 
     let Some(volume) = volume else {
-        let set_volume = comms.send_wait("var.get volume").await?;
-        let set_volume = set_volume.trim().parse::<f32>().unwrap_or(0.0);
+        let set_volume = comms.volume().await?;
         ctx.send(|m| {
             m.embed(|e| {
                 e.title("Volume")
@@ -124,9 +168,7 @@ pub async fn volume(
         return Ok(());
     };
 
-    let _ = comms
-        .send_wait(&format!("var.set volume {}", volume as f32 / 100.0))
-        .await?;
+    comms.set_volume(volume as f32 / 100.0).await?;
 
     ctx.send(|m| {
         m.embed(|e| {
@@ -142,9 +184,7 @@ pub async fn volume(
 /// Pauses the radio
 #[poise::command(slash_command, ephemeral, owners_only)]
 pub async fn pause(ctx: ApplicationContext<'_>) -> Result<(), Error> {
-    let mut comms = ctx.data.comms.lock().await;
-
-    let _ = comms.send_wait("lumiradio.pause").await?;
+    ctx.data.comms.pause().await?;
 
     ctx.send(|m| m.embed(|e| e.title("Paused").description("Paused the radio")))
         .await?;
@@ -152,6 +192,17 @@ pub async fn pause(ctx: ApplicationContext<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Resumes the radio after it has been paused
+#[poise::command(slash_command, ephemeral, owners_only)]
+pub async fn resume(ctx: ApplicationContext<'_>) -> Result<(), Error> {
+    ctx.data.comms.resume().await?;
+
+    ctx.send(|m| m.embed(|e| e.title("Resumed").description("Resumed the radio")))
+        .await?;
+
+    Ok(())
+}
+
 /// Queues a song to be played immediately after the current song
 #[poise::command(slash_command, ephemeral, owners_only)]
 pub async fn queue(
@@ -167,11 +218,17 @@ pub async fn queue(
         return Ok(());
     };
 
-    {
-        let mut comms = data.comms.lock().await;
-        comms.priority_request(&song.file_path).await?;
-    }
-    song.request(&data.db, ctx.author().id.0).await?;
+    let request_id = data.comms.priority_request(&song.file_path).await?;
+    song.request(
+        &data.db,
+        ctx.author().id.0,
+        ctx.guild_id().map(|g| g.0 as i64),
+        RequestQueue::PriorityRequests,
+        request_id,
+        0,
+        None,
+    )
+    .await?;
 
     ctx.send(|m| m.embed(|e| e.title("Song Queued").description(format!("Queued {song}"))))
         .await?;
@@ -195,15 +252,13 @@ pub async fn skip(
     ctx: ApplicationContext<'_>,
     #[description = "What to skip"] skip_type: SkipType,
 ) -> Result<(), Error> {
-    let mut comms = ctx.data.comms.lock().await;
-
-    let command = match skip_type {
-        SkipType::Radio => "lumiradio.skip",
-        SkipType::SongRequest => "srq.skip",
-        SkipType::PriorityRequest => "prioq.skip",
+    let target = match skip_type {
+        SkipType::Radio => SkipTarget::Current,
+        SkipType::SongRequest => SkipTarget::Queue(RequestQueue::SongRequests),
+        SkipType::PriorityRequest => SkipTarget::Queue(RequestQueue::PriorityRequests),
     };
 
-    let _ = comms.send_wait(command).await?;
+    ctx.data.comms.skip(target).await?;
 
     ctx.send(|m| {
         m.embed(|e| {
@@ -1,13 +1,18 @@
 use crate::commands::admin::control::{
-    control_cmd, queue, reconnect, reindex, skip, song_info, volume,
+    control_cmd, liquidsoap_status, pause, queue, reconnect, reindex, resume, skip, song_info,
+    volume,
 };
 
 use crate::commands::admin::import::import_manually;
+use crate::commands::admin::playlist::playlist_admin;
+use crate::commands::admin::schedule::schedule_admin;
 use crate::prelude::*;
 
 pub mod config;
 pub mod control;
 pub mod import;
+pub mod playlist;
+pub mod schedule;
 pub mod user;
 
 /// Admin commands
@@ -19,11 +24,16 @@ pub mod user;
         "volume",
         "control_cmd",
         "skip",
+        "pause",
+        "resume",
         "queue",
         "reconnect",
+        "liquidsoap_status",
         "song_info",
         "import_manually",
-        "reindex"
+        "reindex",
+        "playlist_admin",
+        "schedule_admin"
     ),
     subcommand_required
 )]
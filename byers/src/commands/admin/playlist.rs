@@ -0,0 +1,303 @@
+use std::fmt::Write;
+
+use tracing_unwrap::ResultExt;
+
+use crate::commands::autocomplete_songs;
+use crate::prelude::*;
+use judeharley::db::{DbPlaylist, DbSong};
+
+/// Manages curated playlists
+#[poise::command(
+    slash_command,
+    owners_only,
+    ephemeral,
+    rename = "playlist",
+    subcommands("create", "edit", "delete", "add", "remove", "show"),
+    subcommand_required
+)]
+pub async fn playlist_admin(_: ApplicationContext<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+pub async fn autocomplete_playlists(
+    ctx: Context<'_>,
+    partial: &str,
+) -> impl Iterator<Item = poise::AutocompleteChoice<String>> {
+    let data = ctx.data();
+    let partial = partial.to_lowercase();
+
+    let playlists = DbPlaylist::fetch_all(&data.db)
+        .await
+        .expect_or_log("Failed to query database");
+
+    playlists
+        .into_iter()
+        .filter(move |playlist| playlist.name.to_lowercase().contains(&partial))
+        .take(20)
+        .map(|playlist| poise::AutocompleteChoice {
+            name: playlist.name.clone(),
+            value: playlist.name,
+        })
+}
+
+/// Replies that the playlist doesn't exist if it doesn't
+async fn fetch_playlist(
+    ctx: ApplicationContext<'_>,
+    name: &str,
+) -> Result<Option<DbPlaylist>, Error> {
+    let playlist = DbPlaylist::fetch_by_name(&ctx.data.db, name).await?;
+    if playlist.is_none() {
+        ctx.send(|m| m.content(format!("There is no playlist named {}.", name)))
+            .await?;
+    }
+
+    Ok(playlist)
+}
+
+/// Creates an empty playlist
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn create(
+    ctx: ApplicationContext<'_>,
+    #[description = "Name of the playlist"] name: String,
+    #[description = "What the playlist is for"] description: Option<String>,
+) -> Result<(), Error> {
+    let data = ctx.data;
+
+    let name = name.trim();
+    if name.is_empty() {
+        ctx.send(|m| m.content("The name can't be empty.")).await?;
+        return Ok(());
+    }
+    if DbPlaylist::fetch_by_name(&data.db, name).await?.is_some() {
+        ctx.send(|m| m.content(format!("There already is a playlist named {}.", name)))
+            .await?;
+        return Ok(());
+    }
+
+    let description = description
+        .as_deref()
+        .map(str::trim)
+        .filter(|d| !d.is_empty());
+    let playlist = DbPlaylist::create(&data.db, name, description).await?;
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Playlist Created").description(format!(
+                "Add songs to {} with `/admin playlist add`",
+                playlist.name
+            ))
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Renames a playlist or changes its description
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn edit(
+    ctx: ApplicationContext<'_>,
+    #[description = "The playlist to edit"]
+    #[autocomplete = "autocomplete_playlists"]
+    playlist: String,
+    #[description = "New name"] name: Option<String>,
+    #[description = "New description"] description: Option<String>,
+) -> Result<(), Error> {
+    let data = ctx.data;
+    let Some(mut playlist) = fetch_playlist(ctx, &playlist).await? else {
+        return Ok(());
+    };
+
+    if let Some(name) = name {
+        let name = name.trim();
+        if name.is_empty() {
+            ctx.send(|m| m.content("The name can't be empty.")).await?;
+            return Ok(());
+        }
+        if let Some(existing) = DbPlaylist::fetch_by_name(&data.db, name).await? {
+            if existing.id != playlist.id {
+                ctx.send(|m| m.content(format!("There already is a playlist named {}.", name)))
+                    .await?;
+                return Ok(());
+            }
+        }
+        playlist.name = name.to_string();
+    }
+    if let Some(description) = description {
+        let description = description.trim();
+        playlist.description = Some(description.to_string()).filter(|d| !d.is_empty());
+    }
+    playlist.update(&data.db).await?;
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Playlist Updated")
+                .field("Name", &playlist.name, true)
+                .field(
+                    "Description",
+                    playlist.description.as_deref().unwrap_or("None"),
+                    true,
+                )
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes a playlist and any schedule slots playing it
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn delete(
+    ctx: ApplicationContext<'_>,
+    #[description = "The playlist to delete"]
+    #[autocomplete = "autocomplete_playlists"]
+    playlist: String,
+) -> Result<(), Error> {
+    let data = ctx.data;
+    let Some(playlist) = fetch_playlist(ctx, &playlist).await? else {
+        return Ok(());
+    };
+
+    DbPlaylist::delete(&data.db, playlist.id).await?;
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Playlist Deleted")
+                .description(format!("{} has been deleted", playlist.name))
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Adds a song to the end of a playlist
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn add(
+    ctx: ApplicationContext<'_>,
+    #[description = "The playlist to add to"]
+    #[autocomplete = "autocomplete_playlists"]
+    playlist: String,
+    #[description = "The song to add"]
+    #[autocomplete = "autocomplete_songs"]
+    song: String,
+) -> Result<(), Error> {
+    let data = ctx.data;
+    let Some(playlist) = fetch_playlist(ctx, &playlist).await? else {
+        return Ok(());
+    };
+    let Some(song) = DbSong::fetch_from_hash(&data.db, &song).await? else {
+        ctx.send(|m| m.content("Song not found.")).await?;
+        return Ok(());
+    };
+
+    let position = playlist.add_song(&data.db, &song.file_hash).await?;
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Song Added").description(format!(
+                "Added {} to {} at #{}",
+                song, playlist.name, position
+            ))
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Removes a song from a playlist
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn remove(
+    ctx: ApplicationContext<'_>,
+    #[description = "The playlist to remove from"]
+    #[autocomplete = "autocomplete_playlists"]
+    playlist: String,
+    #[description = "Position of the song, as shown by /admin playlist show"]
+    #[min = 1]
+    position: i32,
+) -> Result<(), Error> {
+    let data = ctx.data;
+    let Some(playlist) = fetch_playlist(ctx, &playlist).await? else {
+        return Ok(());
+    };
+
+    if !playlist.remove_song(&data.db, position).await? {
+        ctx.send(|m| m.content(format!("{} has no song #{}.", playlist.name, position)))
+            .await?;
+        return Ok(());
+    }
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Song Removed")
+                .description(format!("Removed #{} from {}", position, playlist.name))
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Shows the songs of a playlist, or all playlists
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn show(
+    ctx: ApplicationContext<'_>,
+    #[description = "The playlist to show"]
+    #[autocomplete = "autocomplete_playlists"]
+    playlist: Option<String>,
+) -> Result<(), Error> {
+    let data = ctx.data;
+
+    let Some(playlist) = playlist else {
+        let playlists = DbPlaylist::fetch_all(&data.db).await?;
+        let description = if playlists.is_empty() {
+            "There are no playlists yet.".to_string()
+        } else {
+            playlists
+                .iter()
+                .map(|playlist| match &playlist.description {
+                    Some(description) => format!("**{}**: {}", playlist.name, description),
+                    None => format!("**{}**", playlist.name),
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        ctx.send(|m| m.embed(|e| e.title("Playlists").description(description)))
+            .await?;
+        return Ok(());
+    };
+
+    let Some(playlist) = fetch_playlist(ctx, &playlist).await? else {
+        return Ok(());
+    };
+    let entries = DbPlaylist::entries(&data.db, playlist.id).await?;
+    let duration = entries.iter().map(|entry| entry.song.duration).sum::<f64>();
+
+    let mut description = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let line = format!("{}. {}\n", entry.position, entry.song);
+        // leave room for the note about the songs that don't fit
+        if description.len() + line.len() > 4000 {
+            write!(description, "... and {} more", entries.len() - i)?;
+            break;
+        }
+        description.push_str(&line);
+    }
+    if entries.is_empty() {
+        description.push_str("This playlist is empty.");
+    }
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title(&playlist.name)
+                .description(description)
+                .field("Songs", entries.len(), true)
+                .field(
+                    "Length",
+                    format!("{} minutes", (duration / 60.0).round() as i64),
+                    true,
+                )
+        })
+    })
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use chrono::NaiveDateTime;
+
+use crate::commands::admin::playlist::autocomplete_playlists;
+use crate::prelude::*;
+use judeharley::{
+    db::{DbPlaylist, DbScheduleSlot, ScheduleSlotKind},
+    schedule::{overlaps, upcoming_occurrence},
+    DiscordTimestamp,
+};
+
+/// Manages scheduled programming
+#[poise::command(
+    slash_command,
+    owners_only,
+    ephemeral,
+    rename = "schedule",
+    subcommands("album", "tag", "playlist", "remove"),
+    subcommand_required
+)]
+pub async fn schedule_admin(_: ApplicationContext<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+fn parse_start(starts_at: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(starts_at.trim(), "%Y-%m-%d %H:%M").ok()
+}
+
+async fn add_slot(ctx: ApplicationContext<'_>, slot: DbScheduleSlot) -> Result<(), Error> {
+    let data = ctx.data;
+
+    if slot.songs(&data.db).await?.is_empty() {
+        ctx.send(|m| m.content(format!("There are no songs to play {}.", slot.describe())))
+            .await?;
+        return Ok(());
+    }
+
+    let slots = DbScheduleSlot::fetch_all(&data.db).await?;
+    if let Some(other) = slots.iter().find(|other| overlaps(&slot, other)) {
+        ctx.send(|m| {
+            m.content(format!(
+                "{} would overlap {} (ID {}).",
+                slot.name, other.name, other.id
+            ))
+        })
+        .await?;
+        return Ok(());
+    }
+
+    let slot = slot.insert(&data.db).await?;
+    let starts_at =
+        upcoming_occurrence(&slot, chrono::Utc::now().naive_utc()).unwrap_or(slot.starts_at);
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Slot Scheduled")
+                .description(format!("{} will play {}", slot.name, slot.describe()))
+                .field("Starts", starts_at.long_date_short_time(), true)
+                .field("Length", format!("{} minutes", slot.length / 60), true)
+                .field(
+                    "Repeats",
+                    if slot.repeats_weekly {
+                        "Weekly"
+                    } else {
+                        "Never"
+                    },
+                    true,
+                )
+                .field("ID", slot.id, true)
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Schedules a slot that plays an album in track order
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn album(
+    ctx: ApplicationContext<'_>,
+    #[description = "Name of the slot"] name: String,
+    #[description = "Album to play"] album: String,
+    #[description = "Start in UTC, as YYYY-MM-DD HH:MM"] starts_at: String,
+    #[description = "Minutes of music to play"]
+    #[min = 1]
+    #[max = 1440]
+    length: i32,
+    #[description = "Whether the slot repeats every week"] weekly: Option<bool>,
+) -> Result<(), Error> {
+    let Some(starts_at) = parse_start(&starts_at) else {
+        ctx.send(|m| m.content("The start has to look like 2023-11-25 20:00."))
+            .await?;
+        return Ok(());
+    };
+
+    add_slot(
+        ctx,
+        DbScheduleSlot {
+            id: 0,
+            name,
+            kind: ScheduleSlotKind::Album,
+            album: Some(album),
+            tag: None,
+            tag_value: None,
+            playlist_id: None,
+            playlist_name: None,
+            starts_at,
+            length: length * 60,
+            repeats_weekly: weekly.unwrap_or(false),
+            last_played_at: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        },
+    )
+    .await
+}
+
+/// Schedules a slot that plays songs with a tag, e.g. genre = Ambient
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn tag(
+    ctx: ApplicationContext<'_>,
+    #[description = "Name of the slot"] name: String,
+    #[description = "Tag to filter by, e.g. genre"] tag: String,
+    #[description = "Value the tag must have"] value: String,
+    #[description = "Start in UTC, as YYYY-MM-DD HH:MM"] starts_at: String,
+    #[description = "Minutes of music to play"]
+    #[min = 1]
+    #[max = 1440]
+    length: i32,
+    #[description = "Whether the slot repeats every week"] weekly: Option<bool>,
+) -> Result<(), Error> {
+    let Some(starts_at) = parse_start(&starts_at) else {
+        ctx.send(|m| m.content("The start has to look like 2023-11-25 20:00."))
+            .await?;
+        return Ok(());
+    };
+
+    add_slot(
+        ctx,
+        DbScheduleSlot {
+            id: 0,
+            name,
+            kind: ScheduleSlotKind::Tag,
+            album: None,
+            tag: Some(tag),
+            tag_value: Some(value),
+            playlist_id: None,
+            playlist_name: None,
+            starts_at,
+            length: length * 60,
+            repeats_weekly: weekly.unwrap_or(false),
+            last_played_at: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        },
+    )
+    .await
+}
+
+/// Schedules a slot that plays a playlist in order
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn playlist(
+    ctx: ApplicationContext<'_>,
+    #[description = "Name of the slot"] name: String,
+    #[description = "Playlist to play"]
+    #[autocomplete = "autocomplete_playlists"]
+    playlist: String,
+    #[description = "Start in UTC, as YYYY-MM-DD HH:MM"] starts_at: String,
+    #[description = "Minutes of music to play"]
+    #[min = 1]
+    #[max = 1440]
+    length: i32,
+    #[description = "Whether the slot repeats every week"] weekly: Option<bool>,
+) -> Result<(), Error> {
+    let Some(starts_at) = parse_start(&starts_at) else {
+        ctx.send(|m| m.content("The start has to look like 2023-11-25 20:00."))
+            .await?;
+        return Ok(());
+    };
+    let Some(playlist) = DbPlaylist::fetch_by_name(&ctx.data.db, &playlist).await? else {
+        ctx.send(|m| m.content(format!("There is no playlist named {}.", playlist)))
+            .await?;
+        return Ok(());
+    };
+
+    add_slot(
+        ctx,
+        DbScheduleSlot {
+            id: 0,
+            name,
+            kind: ScheduleSlotKind::Playlist,
+            album: None,
+            tag: None,
+            tag_value: None,
+            playlist_id: Some(playlist.id),
+            playlist_name: Some(playlist.name),
+            starts_at,
+            length: length * 60,
+            repeats_weekly: weekly.unwrap_or(false),
+            last_played_at: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        },
+    )
+    .await
+}
+
+/// Removes a scheduled slot
+#[poise::command(slash_command, owners_only, ephemeral)]
+pub async fn remove(
+    ctx: ApplicationContext<'_>,
+    #[description = "ID of the slot, as shown by /schedule"] id: i32,
+) -> Result<(), Error> {
+    let data = ctx.data;
+
+    let Some(slot) = DbScheduleSlot::delete(&data.db, id).await? else {
+        ctx.send(|m| m.content("Slot not found.")).await?;
+        return Ok(());
+    };
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Slot Removed")
+                .description(format!("{} won't play anymore", slot.name))
+        })
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_start() {
+        assert_eq!(
+            parse_start(" 2023-11-25 20:00 "),
+            chrono::NaiveDate::from_ymd_opt(2023, 11, 25)
+                .unwrap()
+                .and_hms_opt(20, 0, 0)
+        );
+        assert_eq!(parse_start("25.11.2023 20:00"), None);
+        assert_eq!(parse_start("2023-11-25"), None);
+    }
+}
@@ -8,7 +8,10 @@ use poise::{
 };
 
 use crate::prelude::*;
-use judeharley::communication::ByersUnixStream;
+use judeharley::{
+    communication::LiquidsoapConnection,
+    metrics::{BYERS_MINIGAME_PAYOUTS, BYERS_MINIGAME_PLAYS},
+};
 
 pub mod new_slots;
 pub mod pvp;
@@ -28,10 +31,22 @@ pub trait Minigame {
         e.title(Self::NAME).timestamp(Utc::now())
     }
 
-    fn command() -> Vec<poise::Command<Data<ByersUnixStream>, anyhow::Error>>;
+    fn command() -> Vec<poise::Command<Data<LiquidsoapConnection>, anyhow::Error>>;
+
+    /// Counts a game in the metrics
+    fn record_play() {
+        BYERS_MINIGAME_PLAYS.with_label_values(&[Self::NAME]).inc();
+    }
+
+    /// Counts Boondollars won in the metrics
+    fn record_payout(amount: i32) {
+        BYERS_MINIGAME_PAYOUTS
+            .with_label_values(&[Self::NAME])
+            .inc_by(amount.max(0) as u64);
+    }
 }
 
-pub fn commands() -> Vec<poise::Command<Data<ByersUnixStream>, anyhow::Error>> {
+pub fn commands() -> Vec<poise::Command<Data<LiquidsoapConnection>, anyhow::Error>> {
     let mut commands = Vec::new();
     commands.extend(pvp::PvP::command());
     commands.extend(roll_dice::DiceRoll::command());
@@ -40,7 +55,7 @@ pub fn commands() -> Vec<poise::Command<Data<ByersUnixStream>, anyhow::Error>> {
     commands
 }
 
-pub fn command() -> Command<Data<ByersUnixStream>, Error> {
+pub fn command() -> Command<Data<LiquidsoapConnection>, Error> {
     Command {
         name: "minigames".to_string(),
         description: Some("Play a minigame".to_string()),
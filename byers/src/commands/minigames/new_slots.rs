@@ -7,7 +7,7 @@ use strum::{EnumIter, IntoEnumIterator};
 use crate::prelude::*;
 use crate::{commands::minigames::Minigame, event_handlers::message::update_activity};
 use judeharley::{
-    communication::ByersUnixStream,
+    communication::LiquidsoapConnection,
     cooldowns::{is_on_cooldown, set_cooldown, UserCooldownKey},
     db::DbUser,
     prelude::DiscordTimestamp,
@@ -216,7 +216,7 @@ impl Minigame for NewSlots {
         Ok(SLOT_MACHINE.spin())
     }
 
-    fn command() -> Vec<poise::Command<Data<ByersUnixStream>, anyhow::Error>> {
+    fn command() -> Vec<poise::Command<Data<LiquidsoapConnection>, anyhow::Error>> {
         vec![slots(), slots_info()]
     }
 }
@@ -306,6 +306,7 @@ pub async fn slots(
     }
     user.boonbucks -= bet;
     user.update(&data.db).await?;
+    NewSlots::record_play();
     // server_config.slot_jackpot += 5;
     // server_config.update(&data.db).await?;
 
@@ -396,6 +397,7 @@ pub async fn slots(
 
     user.boonbucks += payout as i32 * bet;
     user.update(&data.db).await?;
+    NewSlots::record_payout(payout as i32 * bet);
 
     set_cooldown(&data.redis_pool, user_cooldown, 5 * 60).await?;
 
@@ -7,7 +7,7 @@ use rand::{distributions::Standard, prelude::Distribution};
 use crate::prelude::*;
 use crate::{commands::minigames::Minigame, event_handlers::message::update_activity};
 use judeharley::{
-    communication::ByersUnixStream,
+    communication::LiquidsoapConnection,
     cooldowns::{is_on_cooldown, set_cooldown, UserCooldownKey},
     db::{DbServerConfig, DbUser},
     prelude::DiscordTimestamp,
@@ -39,7 +39,7 @@ impl Minigame for PvP {
         Ok(rand::random())
     }
 
-    fn command() -> Vec<poise::Command<Data<ByersUnixStream>, anyhow::Error>> {
+    fn command() -> Vec<poise::Command<Data<LiquidsoapConnection>, anyhow::Error>> {
         vec![pvp()]
     }
 }
@@ -73,6 +73,7 @@ async fn pvp_action(ctx: ApplicationContext<'_>, user: User) -> Result<(), Error
 
     if user.id == ctx.framework.bot_id {
         let bot_won = rand::random::<f64>() < 0.9;
+        PvP::record_play();
 
         if bot_won {
             ctx.send(|m| {
@@ -100,6 +101,7 @@ async fn pvp_action(ctx: ApplicationContext<'_>, user: User) -> Result<(), Error
             set_cooldown(&data.redis_pool, challenger_key, 5 * 60).await?;
 
             challenged.boonbucks += server_config.slot_jackpot;
+            PvP::record_payout(server_config.slot_jackpot);
             server_config.slot_jackpot = 10;
         }
 
@@ -243,6 +245,7 @@ async fn pvp_action(ctx: ApplicationContext<'_>, user: User) -> Result<(), Error
 
     let game = PvP;
     let result = game.play().await?;
+    PvP::record_play();
 
     tokio::time::sleep(Duration::from_secs(5)).await;
 
@@ -252,6 +255,7 @@ async fn pvp_action(ctx: ApplicationContext<'_>, user: User) -> Result<(), Error
             challenged.boonbucks -= 10;
             challenger.update(&data.db).await?;
             challenged.update(&data.db).await?;
+            PvP::record_payout(10);
 
             handle
                 .edit(poise::Context::Application(ctx), |m| {
@@ -269,6 +273,7 @@ async fn pvp_action(ctx: ApplicationContext<'_>, user: User) -> Result<(), Error
             challenger.boonbucks -= 10;
             challenged.update(&data.db).await?;
             challenger.update(&data.db).await?;
+            PvP::record_payout(10);
 
             handle
                 .edit(poise::Context::Application(ctx), |m| {
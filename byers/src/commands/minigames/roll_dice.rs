@@ -4,7 +4,7 @@ use rand::Rng;
 use crate::prelude::*;
 use crate::{commands::minigames::Minigame, event_handlers::message::update_activity};
 use judeharley::{
-    communication::ByersUnixStream,
+    communication::LiquidsoapConnection,
     cooldowns::{is_on_cooldown, set_cooldown, UserCooldownKey},
     db::{DbServerConfig, DbUser},
     prelude::DiscordTimestamp,
@@ -72,7 +72,7 @@ impl Minigame for DiceRoll {
         }
     }
 
-    fn command() -> Vec<poise::Command<Data<ByersUnixStream>, anyhow::Error>> {
+    fn command() -> Vec<poise::Command<Data<LiquidsoapConnection>, anyhow::Error>> {
         vec![roll_dice()]
     }
 }
@@ -179,6 +179,7 @@ pub async fn roll_dice(ctx: ApplicationContext<'_>) -> Result<(), Error> {
 
     user.boonbucks -= 5;
     user.update(&data.db).await?;
+    DiceRoll::record_play();
 
     let game = DiceRoll::new(guild_config.dice_roll);
     let result = game.play().await?;
@@ -190,6 +191,7 @@ pub async fn roll_dice(ctx: ApplicationContext<'_>) -> Result<(), Error> {
             guild_config.update(&data.db).await?;
             user.boonbucks += total_winnings;
             user.update(&data.db).await?;
+            DiceRoll::record_payout(total_winnings);
 
             ctx.send(|m| {
                 m.embed(|x| {
@@ -208,6 +210,7 @@ pub async fn roll_dice(ctx: ApplicationContext<'_>) -> Result<(), Error> {
         DiceRollResult::Win(total_winnings) => {
             user.boonbucks += total_winnings;
             user.update(&data.db).await?;
+            DiceRoll::record_payout(total_winnings);
 
             ctx.send(|m| {
                 m.embed(|x| {
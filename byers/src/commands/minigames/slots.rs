@@ -6,7 +6,7 @@ use strum::IntoEnumIterator;
 use crate::prelude::*;
 use crate::{commands::minigames::Minigame, event_handlers::message::update_activity};
 use judeharley::{
-    communication::ByersUnixStream,
+    communication::LiquidsoapConnection,
     cooldowns::{is_on_cooldown, set_cooldown, UserCooldownKey},
     db::{DbServerConfig, DbUser},
     prelude::*,
@@ -121,7 +121,7 @@ impl Minigame for SlotMachine {
         Ok(self.rolls.determine_payout())
     }
 
-    fn command() -> Vec<poise::Command<Data<ByersUnixStream>, anyhow::Error>> {
+    fn command() -> Vec<poise::Command<Data<LiquidsoapConnection>, anyhow::Error>> {
         vec![slots()]
     }
 }
@@ -11,7 +11,7 @@ use tracing::info;
 use crate::prelude::*;
 use crate::{commands::minigames::Minigame, event_handlers::message::update_activity};
 use judeharley::cooldowns::{is_on_cooldown, set_cooldown, GlobalCooldownKey};
-use judeharley::{communication::ByersUnixStream, db::DbUser, prelude::DiscordTimestamp};
+use judeharley::{communication::LiquidsoapConnection, db::DbUser, prelude::DiscordTimestamp};
 
 static STRIFE_ENEMIES_BY_PLAYER_COUNT: Lazy<HashMap<i32, StrifeEnemyType>> = Lazy::new(|| {
     vec![
@@ -252,7 +252,7 @@ impl Minigame for Strife {
         }
     }
 
-    fn command() -> Vec<poise::Command<Data<ByersUnixStream>, anyhow::Error>> {
+    fn command() -> Vec<poise::Command<Data<LiquidsoapConnection>, anyhow::Error>> {
         vec![strife()]
     }
 }
@@ -265,6 +265,7 @@ async fn payout_multiple(
     for winner in players {
         let mut winner_user = DbUser::fetch_or_insert(db, winner.user.id.0 as i64).await?;
         winner_user.boonbucks += result.boonbucks_per_player.unwrap();
+        Strife::record_payout(result.boonbucks_per_player.unwrap());
 
         match result.grist_type.unwrap() {
             EnemyGristVariant::Amber => winner_user.amber += result.grist_per_player.unwrap(),
@@ -437,6 +438,7 @@ pub async fn strife(ctx: ApplicationContext<'_>) -> Result<(), Error> {
         return Ok(());
     };
     let result = game.play().await?;
+    Strife::record_play();
     match result.result {
         StrifeResult::Wipeout => {
             handle
@@ -11,6 +11,7 @@ pub mod context;
 pub mod currency;
 pub mod help;
 pub mod minigames;
+pub mod schedule;
 pub mod songs;
 pub mod version;
 pub mod youtube;
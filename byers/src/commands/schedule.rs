@@ -0,0 +1,61 @@
+use crate::event_handlers::message::update_activity;
+use crate::prelude::*;
+use judeharley::{db::DbScheduleSlot, schedule::upcoming_occurrence, DiscordTimestamp};
+
+/// Shows what's coming up on the radio
+#[poise::command(slash_command)]
+pub async fn schedule(ctx: ApplicationContext<'_>) -> Result<(), Error> {
+    let data = ctx.data;
+    if let Some(guild_id) = ctx.guild_id() {
+        update_activity(data, ctx.author().id, ctx.channel_id(), guild_id).await?;
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut upcoming = DbScheduleSlot::fetch_all(&data.db)
+        .await?
+        .into_iter()
+        .filter_map(|slot| upcoming_occurrence(&slot, now).map(|starts_at| (starts_at, slot)))
+        .collect::<Vec<_>>();
+    upcoming.sort_by_key(|(starts_at, _)| *starts_at);
+
+    let description = if upcoming.is_empty() {
+        "Nothing is scheduled right now.".to_string()
+    } else {
+        upcoming
+            .iter()
+            .take(10)
+            .map(|(starts_at, slot)| {
+                let when = if *starts_at <= now {
+                    "On air now".to_string()
+                } else {
+                    format!(
+                        "{} ({})",
+                        starts_at.long_date_short_time(),
+                        starts_at.relative_time()
+                    )
+                };
+                let weekly = if slot.repeats_weekly {
+                    ", every week"
+                } else {
+                    ""
+                };
+
+                format!(
+                    "**{}** (#{}) - {}{}\nPlays {} for {} minutes",
+                    slot.name,
+                    slot.id,
+                    when,
+                    weekly,
+                    slot.describe(),
+                    slot.length / 60
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+
+    ctx.send(|m| m.embed(|e| e.title("Schedule").description(description)))
+        .await?;
+
+    Ok(())
+}
@@ -1,22 +1,33 @@
+use chrono::NaiveDateTime;
 use std::time::Duration;
 
 use poise::serenity_prelude::{CreateSelectMenuOption, InteractionResponseType};
-use tracing_unwrap::{OptionExt, ResultExt};
 
 use crate::commands::autocomplete_songs;
 use crate::event_handlers::message::update_activity;
 use crate::prelude::*;
 use judeharley::{
-    communication::LiquidsoapCommunication,
-    cooldowns::{is_on_cooldown, set_cooldown, UserCooldownKey},
-    db::DbSong,
-    DiscordTimestamp,
+    db::{DbPlayedSong, DbRequestPolicy, DbSong, DbSongVote},
+    requests::{
+        cancel_request, queued_requests, RequestDenied, RequestKind, RequestOutcome, RequestService,
+    },
+    DiscordTimestamp, PgPool,
 };
 
 /// Song-related commands
 #[poise::command(
     slash_command,
-    subcommands("request", "playing", "history", "queue", "search"),
+    subcommands(
+        "request",
+        "playing",
+        "history",
+        "queue",
+        "search",
+        "myrequests",
+        "cancel",
+        "like",
+        "dislike"
+    ),
     subcommand_required
 )]
 pub async fn song(_: ApplicationContext<'_>) -> Result<(), Error> {
@@ -95,8 +106,7 @@ pub async fn queue(ctx: ApplicationContext<'_>) -> Result<(), Error> {
         update_activity(data, ctx.author().id, ctx.channel_id(), guild_id).await?;
     }
 
-    let mut comms = data.comms.lock().await;
-    let requests = comms.song_requests().await?;
+    let requests = data.comms.song_requests().await?;
 
     if requests.is_empty() {
         ctx.send(|m| {
@@ -134,6 +144,149 @@ pub async fn queue(ctx: ApplicationContext<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Shows where your song requests are in the queue
+#[poise::command(slash_command, ephemeral)]
+pub async fn myrequests(ctx: ApplicationContext<'_>) -> Result<(), Error> {
+    let data = ctx.data;
+
+    if let Some(guild_id) = ctx.guild_id() {
+        update_activity(data, ctx.author().id, ctx.channel_id(), guild_id).await?;
+    }
+
+    let queued = queued_requests(&data.db, &data.comms).await?;
+    let total = queued.len();
+    let now = chrono::Utc::now();
+    let mine = queued
+        .into_iter()
+        .filter(|q| {
+            q.request
+                .as_ref()
+                .map(|r| r.user_id == ctx.author().id.0 as i64)
+                .unwrap_or(false)
+        })
+        .map(|q| {
+            let song = q
+                .song
+                .map(|s| format!("{} - {}", s.album, s.title))
+                .unwrap_or("<unknown song>".to_string());
+            format!(
+                "#{} of {}: {} (plays {})",
+                q.position,
+                total,
+                song,
+                (now + q.starts_in).relative_time()
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let description = if mine.is_empty() {
+        "You have no songs waiting in the queue.".to_string()
+    } else {
+        format!(
+            "{}\n\nTimes are estimates based on the length of the songs ahead of yours.",
+            mine.join("\n")
+        )
+    };
+
+    ctx.send(|m| m.embed(|e| e.title("Your Song Requests").description(description)))
+        .await?;
+
+    Ok(())
+}
+
+/// Cancels your song request if it hasn't started playing yet
+#[poise::command(slash_command, ephemeral)]
+pub async fn cancel(ctx: ApplicationContext<'_>) -> Result<(), Error> {
+    let data = ctx.data;
+
+    if let Some(guild_id) = ctx.guild_id() {
+        update_activity(data, ctx.author().id, ctx.channel_id(), guild_id).await?;
+    }
+
+    let cancelled =
+        cancel_request(&data.db, &data.redis_pool, &data.comms, ctx.author().id.0).await?;
+
+    let description = match cancelled {
+        Some(cancelled) => {
+            let song = cancelled
+                .song
+                .map(|s| format!("{} - {}", s.album, s.title))
+                .unwrap_or("<unknown song>".to_string());
+            let refund = match cancelled.request.map(|r| r.boonbucks_paid) {
+                Some(paid) if paid > 0 => format!(" Your {} Boondollars were refunded.", paid),
+                _ => String::new(),
+            };
+            format!(
+                r#""{}" was removed from the queue. You can request a song again right away.{}"#,
+                song, refund
+            )
+        }
+        None => "You have no songs waiting in the queue.".to_string(),
+    };
+
+    ctx.send(|m| m.embed(|e| e.title("Song Requests").description(description)))
+        .await?;
+
+    Ok(())
+}
+
+/// Records the user's vote for a play and describes how the song is rated now.
+pub async fn record_vote(
+    db: &PgPool,
+    user_id: u64,
+    played_song: &DbPlayedSong,
+    liked: bool,
+) -> Result<String, Error> {
+    DbSongVote::upsert(db, played_song.id, user_id as i64, liked).await?;
+
+    let Some(song) = DbSong::fetch_from_hash(db, &played_song.song_id).await? else {
+        return Ok("Thanks for voting!".to_string());
+    };
+    let rating = song.rating(db).await?;
+
+    Ok(format!(
+        r#"You {} "{} - {}". It has {} likes and {} dislikes."#,
+        if liked { "liked" } else { "disliked" },
+        song.album,
+        song.title,
+        rating.likes,
+        rating.dislikes
+    ))
+}
+
+async fn vote_current(ctx: ApplicationContext<'_>, liked: bool) -> Result<(), Error> {
+    let data = ctx.data;
+
+    if let Some(guild_id) = ctx.guild_id() {
+        update_activity(data, ctx.author().id, ctx.channel_id(), guild_id).await?;
+    }
+
+    let description = match DbPlayedSong::fetch_history(&data.db, 1, None)
+        .await?
+        .first()
+    {
+        Some(played_song) => record_vote(&data.db, ctx.author().id.0, played_song, liked).await?,
+        None => "Nothing is currently playing!".to_string(),
+    };
+
+    ctx.send(|m| m.embed(|e| e.title("Song Rating").description(description)))
+        .await?;
+
+    Ok(())
+}
+
+/// Likes the song that is currently playing
+#[poise::command(slash_command, ephemeral)]
+pub async fn like(ctx: ApplicationContext<'_>) -> Result<(), Error> {
+    vote_current(ctx, true).await
+}
+
+/// Dislikes the song that is currently playing
+#[poise::command(slash_command, ephemeral)]
+pub async fn dislike(ctx: ApplicationContext<'_>) -> Result<(), Error> {
+    vote_current(ctx, false).await
+}
+
 /// Lets you search for a song and then request it
 #[poise::command(slash_command)]
 pub async fn search(
@@ -171,11 +324,15 @@ pub async fn search(
         .join("\n");
     let results = suggestions.len();
 
-    let user_cooldown = UserCooldownKey::new(ctx.author().id.0 as i64, "song_request");
-    let has_cooldown = is_on_cooldown(&data.redis_pool, user_cooldown).await?;
+    let guild_id = ctx.guild_id().map(|g| g.0 as i64);
+    let policy = DbRequestPolicy::fetch_for(&data.db, guild_id).await?;
+    let service = RequestService::new(&data.db, &data.redis_pool, &data.comms)
+        .with_policy(policy)
+        .in_guild(guild_id);
+    let has_cooldown = service.user_cooldown(ctx.author().id.0).await?;
     let mut song_selection = vec![];
     for song in &suggestions {
-        if !song.is_on_cooldown(&data.db).await? {
+        if !song.is_on_cooldown(&data.db, service.policy()).await? {
             let mut option = CreateSelectMenuOption::default();
             option.label(format!("{} - {}", song.album, song.title));
             option.value(song.file_hash.clone());
@@ -233,41 +390,60 @@ pub async fn search(
             return Ok(());
         };
 
-    let song = suggestions
-        .into_iter()
-        .find(|song| song.file_hash == mci.data.values[0])
-        .expect_or_log("Failed to find song");
-
-    let _ = {
-        let mut comms = data.comms.lock().await;
-        comms
-            .request_song(&song.file_path)
-            .await
-            .expect_or_log("Failed to request song")
+    let description = match service
+        .request(ctx.author().id.0, &mci.data.values[0])
+        .await?
+    {
+        RequestOutcome::Requested {
+            song,
+            next_request_at,
+            price,
+            ..
+        } => format!(
+            r#""{} - {}" requested{}! You can request again {}."#,
+            &song.album,
+            &song.title,
+            paid_for(price),
+            next_request_at.relative_time()
+        ),
+        RequestOutcome::Unconfirmed {
+            song,
+            next_request_at,
+            price,
+        } => unconfirmed_message(&song, next_request_at, price),
+        // the song may have been requested by someone else while the menu was open
+        RequestOutcome::Denied(denied) => denied_message(denied),
     };
 
-    song.request(&data.db, ctx.author().id.0).await?;
-
-    let cooldown_time = chrono::Duration::seconds(5400);
-    let over = chrono::Utc::now() + cooldown_time;
-    let discord_relative = over.relative_time();
-
     mci.create_interaction_response(ctx.serenity_context(), |r| {
         r.kind(InteractionResponseType::UpdateMessage)
             .interaction_response_data(|b| {
-                b.embed(|e| {
-                    e.title("Song Requests")
-                    .description(format!(r#""{} - {}" requested! You can request again in 1 and 1/2 hours ({discord_relative})."#, &song.album, &song.title))
-                })
-                .components(|c| c)
+                b.embed(|e| e.title("Song Requests").description(description))
+                    .components(|c| c)
             })
-    }).await?;
-
-    set_cooldown(&data.redis_pool, user_cooldown, 90 * 60).await?;
+    })
+    .await?;
 
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum RequestBoost {
+    #[name = "Skip my cooldown"]
+    BypassCooldown,
+    #[name = "Play it next"]
+    Premium,
+}
+
+impl From<RequestBoost> for RequestKind {
+    fn from(value: RequestBoost) -> Self {
+        match value {
+            RequestBoost::BypassCooldown => RequestKind::BypassCooldown,
+            RequestBoost::Premium => RequestKind::Premium,
+        }
+    }
+}
+
 /// Requests a song for the radio
 #[poise::command(slash_command)]
 pub async fn request(
@@ -276,6 +452,9 @@ pub async fn request(
     #[rest]
     #[autocomplete = "autocomplete_songs"]
     song: String,
+    #[description = "Pay Boondollars to skip your cooldown or the queue"] boost: Option<
+        RequestBoost,
+    >,
 ) -> Result<(), Error> {
     let data = ctx.data();
 
@@ -283,101 +462,99 @@ pub async fn request(
         update_activity(data, ctx.author().id, ctx.channel_id(), guild_id).await?;
     }
 
-    let user_cooldown = UserCooldownKey::new(ctx.author().id.0 as i64, "song_request");
-    if let Some(over) = is_on_cooldown(&data.redis_pool, user_cooldown).await? {
-        ctx.send(|m| {
-            m.embed(|e| {
-                e.title("Song Requests").description(format!(
-                    "You can request a song again {}.",
-                    over.relative_time(),
-                ))
+    let guild_id = ctx.guild_id().map(|g| g.0 as i64);
+    let policy = DbRequestPolicy::fetch_for(&data.db, guild_id).await?;
+    let service = RequestService::new(&data.db, &data.redis_pool, &data.comms)
+        .with_policy(policy)
+        .in_guild(guild_id);
+    let kind = boost.map(Into::into).unwrap_or_default();
+    let outcome = service.request_with(ctx.author().id.0, &song, kind).await?;
+    let description = match outcome {
+        RequestOutcome::Requested {
+            song,
+            next_request_at,
+            price,
+            ..
+        } => format!(
+            r#""{} - {}" requested{}! You can request again {}."#,
+            &song.album,
+            &song.title,
+            paid_for(price),
+            next_request_at.relative_time()
+        ),
+        RequestOutcome::Unconfirmed {
+            song,
+            next_request_at,
+            price,
+        } => unconfirmed_message(&song, next_request_at, price),
+        RequestOutcome::Denied(denied) => {
+            ctx.send(|m| {
+                m.embed(|e| e.title("Song Requests").description(denied_message(denied)))
+                    .ephemeral(true)
             })
-        })
-        .await?;
-        return Ok(());
-    }
-
-    let song = DbSong::fetch_from_hash(&data.db, &song).await?;
-
-    let Some(song) = song else {
-        ctx.send(|m| m.content("Song not found.").ephemeral(true))
             .await?;
-        return Ok(());
-    };
-
-    let Some(currently_playing) = DbSong::last_played_song(&data.db).await? else {
-        ctx.send(|m| {
-            m.embed(|e| {
-                e.title("Song Requests")
-                    .description("Nothing is currently playing!")
-            })
-            .ephemeral(true)
-        })
-        .await?;
-        return Ok(());
-    };
-    if currently_playing.file_hash == song.file_hash {
-        ctx.send(|b| {
-            b.embed(|e| {
-                e.title("Song Requests")
-                    .description("This song is currently playing!")
-            })
-            .ephemeral(true)
-        })
-        .await?;
-        return Ok(());
-    }
-
-    let last_played = song.last_requested(&data.db).await?;
-    let cooldown_time = if song.duration < 300.0 {
-        chrono::Duration::seconds(1800)
-    } else if song.duration < 600.0 {
-        chrono::Duration::seconds(3600)
-    } else {
-        chrono::Duration::seconds(5413)
-    };
-
-    let over = last_played + cooldown_time;
-
-    if over > chrono::Utc::now().naive_utc() {
-        ctx.send(|b| {
-            b.embed(|e| {
-                e.title("Song Requests").description(format!(
-                    "This song has been requested recently. You can request this song again {}",
-                    over.relative_time()
-                ))
-            })
-        })
-        .await?;
-        return Ok(());
-    }
-
-    let _ = {
-        let mut comms = data.comms.lock().await;
-        comms
-            .request_song(&song.file_path)
-            .await
-            .expect_or_log("Failed to request song")
+            return Ok(());
+        }
     };
 
-    song.request(&data.db, ctx.author().id.0).await?;
-
-    let cooldown_time = chrono::Duration::seconds(5400);
-    let over = chrono::Utc::now() + cooldown_time;
-    let discord_relative = over.relative_time();
-    ctx.send(|b| {
-        b.embed(|e| {
-            e.title("Song Requests")
-            .description(format!(r#""{} - {}" requested! You can request again in 1 and 1/2 hours ({discord_relative})."#, &song.album, &song.title))
-        })
-    })
+    ctx.send(|b| b.embed(|e| e.title("Song Requests").description(description)))
         .await
         .map_err(|e| {
             tracing::error!("Failed to send message: {}", e);
             e
         })?;
 
-    set_cooldown(&data.redis_pool, user_cooldown, 90 * 60).await?;
-
     Ok(())
 }
+
+fn paid_for(price: i32) -> String {
+    if price > 0 {
+        format!(" for {} Boondollars", price)
+    } else {
+        String::new()
+    }
+}
+
+/// For requests Liquidsoap got but didn't answer in time
+fn unconfirmed_message(song: &DbSong, next_request_at: NaiveDateTime, price: i32) -> String {
+    format!(
+        r#""{} - {}" requested{}, but the radio didn't confirm it. It will most likely play anyway, but it can't be cancelled. You can request again {}."#,
+        &song.album,
+        &song.title,
+        paid_for(price),
+        next_request_at.relative_time()
+    )
+}
+
+/// Explains to the user why their request was turned down
+pub fn denied_message(denied: RequestDenied) -> String {
+    match denied {
+        RequestDenied::RequestsClosed => "Song requests are closed right now.".to_string(),
+        RequestDenied::NotForSale => "That kind of request can't be bought here.".to_string(),
+        RequestDenied::UserCooldown(over) => {
+            format!("You can request a song again {}.", over.relative_time())
+        }
+        RequestDenied::SongNotFound => "Song not found.".to_string(),
+        RequestDenied::TooManyPending(max) => format!(
+            "You already have {} in the queue. Wait for them to play before requesting more.",
+            if max == 1 {
+                "a request".to_string()
+            } else {
+                format!("{} requests", max)
+            }
+        ),
+        RequestDenied::QueueFull(_) => {
+            "The request queue is full, try again once a few songs have played.".to_string()
+        }
+        RequestDenied::NothingPlaying => "Nothing is currently playing!".to_string(),
+        RequestDenied::CurrentlyPlaying => "This song is currently playing!".to_string(),
+        RequestDenied::SongCooldown(over) => format!(
+            "This song has been requested recently. You can request this song again {}",
+            over.relative_time()
+        ),
+        RequestDenied::InsufficientFunds { price, balance } => format!(
+            "This request costs {} Boondollars, but you only have {}.",
+            price, balance
+        ),
+    }
+}
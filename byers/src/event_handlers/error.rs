@@ -2,9 +2,9 @@ use chrono::Utc;
 use tracing::error;
 
 use crate::prelude::*;
-use judeharley::{communication::ByersUnixStream, prelude::DiscordTimestamp};
+use judeharley::{communication::LiquidsoapConnection, prelude::DiscordTimestamp};
 
-type FrameworkError<'a> = poise::FrameworkError<'a, Data<ByersUnixStream>, Error>;
+type FrameworkError<'a> = poise::FrameworkError<'a, Data<LiquidsoapConnection>, Error>;
 
 async fn send_cooldown_embed(
     ctx: Context<'_>,
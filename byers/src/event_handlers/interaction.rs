@@ -0,0 +1,87 @@
+use poise::serenity_prelude::{Context, Interaction, InteractionResponseType};
+use tracing::warn;
+
+use crate::commands::songs::record_vote;
+use crate::prelude::*;
+use judeharley::{communication::LiquidsoapConnection, db::DbPlayedSong};
+
+const VOTE_BUTTON_PREFIX: &str = "song_vote_";
+
+/// Custom ID of the like or dislike button of a play on a now playing post
+pub fn vote_button_id(played_song_id: i32, liked: bool) -> String {
+    format!(
+        "{}{}_{}",
+        VOTE_BUTTON_PREFIX,
+        if liked { "like" } else { "dislike" },
+        played_song_id
+    )
+}
+
+fn parse_vote_button_id(custom_id: &str) -> Option<(i32, bool)> {
+    let (vote, played_song_id) = custom_id
+        .strip_prefix(VOTE_BUTTON_PREFIX)?
+        .split_once('_')?;
+    let liked = match vote {
+        "like" => true,
+        "dislike" => false,
+        _ => return None,
+    };
+
+    Some((played_song_id.parse().ok()?, liked))
+}
+
+/// Handles buttons on messages that outlive the command that sent them, like the vote buttons
+/// on now playing posts. Everything else is awaited by its command.
+pub async fn interaction_handler(
+    ctx: &Context,
+    interaction: &Interaction,
+    data: &Data<LiquidsoapConnection>,
+) -> Result<(), Error> {
+    let Interaction::MessageComponent(component) = interaction else {
+        return Ok(());
+    };
+    let Some((played_song_id, liked)) = parse_vote_button_id(&component.data.custom_id) else {
+        return Ok(());
+    };
+
+    let description = match DbPlayedSong::fetch(&data.db, played_song_id).await? {
+        Some(played_song) => {
+            record_vote(&data.db, component.user.id.0, &played_song, liked).await?
+        }
+        None => {
+            warn!("Vote for unknown play {}", played_song_id);
+            "This song can't be rated anymore.".to_string()
+        }
+    };
+
+    component
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| {
+                    d.ephemeral(true)
+                        .embed(|e| e.title("Song Rating").description(description))
+                })
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vote_button_id() {
+        assert_eq!(
+            parse_vote_button_id(&vote_button_id(42, true)),
+            Some((42, true))
+        );
+        assert_eq!(
+            parse_vote_button_id(&vote_button_id(7, false)),
+            Some((7, false))
+        );
+        assert_eq!(parse_vote_button_id("pvp_accept_42"), None);
+        assert_eq!(parse_vote_button_id("song_vote_meh_42"), None);
+    }
+}
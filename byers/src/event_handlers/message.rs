@@ -8,7 +8,7 @@ use tracing::info;
 
 use crate::prelude::*;
 use judeharley::{
-    communication::ByersUnixStream,
+    communication::LiquidsoapConnection,
     db::{DbServerChannelConfig, DbUser},
     BigDecimal, PgPool,
 };
@@ -101,7 +101,7 @@ impl UserMessageHandlerExt for DbUser {
 }
 
 pub async fn update_activity(
-    data: &Data<ByersUnixStream>,
+    data: &Data<LiquidsoapConnection>,
     author: UserId,
     channel_id: ChannelId,
     guild_id: GuildId,
@@ -122,7 +122,10 @@ pub async fn update_activity(
     Ok(())
 }
 
-pub async fn message_handler(message: &Message, data: &Data<ByersUnixStream>) -> Result<(), Error> {
+pub async fn message_handler(
+    message: &Message,
+    data: &Data<LiquidsoapConnection>,
+) -> Result<(), Error> {
     if message.author.bot {
         return Ok(());
     }
@@ -1,3 +1,4 @@
 pub mod error;
+pub mod interaction;
 pub mod message;
 pub mod ready;
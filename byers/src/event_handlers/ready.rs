@@ -1,21 +1,83 @@
 use fred::{prelude::PubsubInterface, types::RedisValue};
-use poise::serenity_prelude::{Activity, ChannelId};
-use tracing::{debug, info};
+use poise::serenity_prelude::{Activity, ButtonStyle, ChannelId, Mentionable, UserId};
+use tracing::{debug, error, info};
 use tracing_unwrap::ResultExt;
 
+use crate::event_handlers::interaction::vote_button_id;
 use crate::prelude::*;
 use judeharley::{
-    communication::ByersUnixStream,
+    communication::LiquidsoapConnection,
     db::{DbServerChannelConfig, DbSong},
+    events::{self, Event, SongRequestStarted, TrackStarted},
+    PgPool,
 };
 
+async fn announce_song_request(
+    db: &PgPool,
+    ctx: &poise::serenity_prelude::Context,
+    event: &SongRequestStarted,
+) -> Result<(), Error> {
+    let channels = DbServerChannelConfig::fetch_announcement_channels(db).await?;
+    let requester = UserId(event.requested_by as u64);
+
+    for channel in channels {
+        let discord_channel_id = ChannelId(channel.id as u64);
+        let result = discord_channel_id
+            .send_message(&ctx.http, |m| {
+                // only ping them where they made the request
+                if channel.ping_requester && event.guild_id == Some(channel.server_id) {
+                    m.content(requester.mention())
+                        .allowed_mentions(|am| am.users([requester]));
+                }
+
+                m.embed(|e| {
+                    e.title("Now playing")
+                        .description(format!(
+                            "**{}** by {}\nfrom *{}*",
+                            event.title, event.artist, event.album
+                        ))
+                        .field("Requested by", requester.mention(), true)
+                });
+
+                if let Some(played_song_id) = event.played_song_id {
+                    m.components(|c| {
+                        c.create_action_row(|ar| {
+                            ar.create_button(|b| {
+                                b.label("Like")
+                                    .style(ButtonStyle::Success)
+                                    .emoji('👍')
+                                    .custom_id(vote_button_id(played_song_id, true))
+                            })
+                            .create_button(|b| {
+                                b.label("Dislike")
+                                    .style(ButtonStyle::Danger)
+                                    .emoji('👎')
+                                    .custom_id(vote_button_id(played_song_id, false))
+                            })
+                        })
+                    });
+                }
+
+                m
+            })
+            .await;
+
+        if let Err(e) = result {
+            error!("Failed to announce song request in {}: {}", channel.id, e);
+        }
+    }
+
+    Ok(())
+}
+
 async fn spawn_subscriber_handler(
-    data: &Data<ByersUnixStream>,
+    data: &Data<LiquidsoapConnection>,
     ctx: &poise::serenity_prelude::Context,
 ) -> Result<(), Error> {
     info!("Spawning Redis subscriber message handler...");
     let mut message_rx = data.redis_subscriber.on_message();
     let context = ctx.clone();
+    let db = data.db.clone();
     tokio::spawn(async move {
         while let Ok(message) = message_rx.recv().await {
             debug!(
@@ -24,9 +86,35 @@ async fn spawn_subscriber_handler(
             );
 
             match message.channel.to_string().as_str() {
-                "byers:status" => {
-                    if let RedisValue::String(song) = message.value {
-                        context.set_activity(Activity::listening(song)).await;
+                TrackStarted::CHANNEL => {
+                    let RedisValue::String(payload) = message.value else {
+                        continue;
+                    };
+
+                    match events::from_json::<TrackStarted>(&payload) {
+                        Ok(track) => {
+                            context
+                                .set_activity(Activity::listening(format!(
+                                    "{} - {} - {}",
+                                    track.album, track.artist, track.title
+                                )))
+                                .await;
+                        }
+                        Err(e) => error!("Invalid track event: {}", e),
+                    }
+                }
+                SongRequestStarted::CHANNEL => {
+                    let RedisValue::String(payload) = message.value else {
+                        continue;
+                    };
+
+                    match events::from_json::<SongRequestStarted>(&payload) {
+                        Ok(event) => {
+                            if let Err(e) = announce_song_request(&db, &context, &event).await {
+                                error!("Failed to announce song request: {}", e);
+                            }
+                        }
+                        Err(e) => error!("Invalid song request event: {}", e),
                     }
                 }
                 "moo" => {}
@@ -41,7 +129,7 @@ async fn spawn_subscriber_handler(
 pub async fn on_ready(
     ctx: &poise::serenity_prelude::Context,
     data_about_bot: &poise::serenity_prelude::Ready,
-    data: &Data<ByersUnixStream>,
+    data: &Data<LiquidsoapConnection>,
 ) -> Result<(), Error> {
     info!("Connected as {}", data_about_bot.user.name);
 
@@ -62,7 +150,7 @@ pub async fn on_ready(
 }
 
 async fn spawn_hydration_reminder(
-    data: &Data<ByersUnixStream>,
+    data: &Data<LiquidsoapConnection>,
     ctx: &poise::serenity_prelude::Context,
 ) -> Result<(), Error> {
     let db = data.db.clone();
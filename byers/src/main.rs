@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use fred::prelude::{ClientLike, PubsubInterface};
 use poise::PrefixFrameworkOptions;
 use tracing::{debug, info};
@@ -12,15 +14,23 @@ use crate::{
         help::*,
         listen, minigames,
         minigames::pvp::pvp_context,
+        schedule::schedule,
         songs::*,
         version::*,
         youtube::*,
     },
-    oauth2::oauth2_server,
+    oauth2::{metrics_server, oauth2_server},
     prelude::*,
 };
-use judeharley::communication::ByersUnixStream;
+use judeharley::{
+    autodj::AutoDj,
+    communication::LiquidsoapClient,
+    events::{Event, SongRequestStarted, TrackStarted},
+    metrics::BYERS_COMMANDS,
+    schedule::Scheduler,
+};
 
+mod api;
 mod app_config;
 mod commands;
 mod event_handlers;
@@ -52,6 +62,7 @@ async fn main() {
         addcan(),
         addbear(),
         what_song(),
+        schedule(),
     ];
 
     info!("Loading {} commands...", commands.len());
@@ -112,15 +123,42 @@ async fn main() {
 
     let manage_handle = subscriber_client.manage_subscriptions();
     subscriber_client
-        .subscribe::<(), _>("byers:status")
+        .subscribe::<(), _>(TrackStarted::CHANNEL)
+        .await
+        .expect_or_log("failed to subscribe");
+    subscriber_client
+        .subscribe::<(), _>(SongRequestStarted::CHANNEL)
         .await
         .expect_or_log("failed to subscribe");
 
+    let comms = LiquidsoapClient::spawn(
+        config
+            .liquidsoap
+            .connect()
+            .await
+            .expect_or_log("failed to connect to Liquidsoap"),
+        config.liquidsoap.client_options(),
+    );
+    if config.autodj.enabled {
+        let options = config.autodj.options();
+        let db = db.clone();
+        let comms = comms.clone();
+        tokio::spawn(async move {
+            AutoDj::new(&db, &comms, options).run().await;
+        });
+    }
+    {
+        let db = db.clone();
+        let comms = comms.clone();
+        tokio::spawn(async move {
+            Scheduler::new(&db, &comms, Duration::from_secs(30))
+                .run()
+                .await;
+        });
+    }
     let context = Data {
         db: db.clone(),
-        comms: std::sync::Arc::new(tokio::sync::Mutex::new(
-            ByersUnixStream::new().await.unwrap(),
-        )),
+        comms: comms.clone(),
         google_config: config.google,
         redis_pool: redis_pool.clone(),
         redis_subscriber: subscriber_client.clone(),
@@ -143,9 +181,25 @@ async fn main() {
                         crate::event_handlers::ready::on_ready(ctx, data_about_bot, data).await?;
                     }
 
+                    if let poise::Event::InteractionCreate { interaction } = event {
+                        crate::event_handlers::interaction::interaction_handler(
+                            ctx,
+                            interaction,
+                            data,
+                        )
+                        .await?;
+                    }
+
                     Ok(())
                 })
             },
+            pre_command: |ctx| {
+                Box::pin(async move {
+                    BYERS_COMMANDS
+                        .with_label_values(&[&ctx.command().qualified_name])
+                        .inc();
+                })
+            },
             on_error: |error| {
                 Box::pin(async move {
                     crate::event_handlers::error::on_error(error)
@@ -174,11 +228,23 @@ async fn main() {
 
     let framework = framework_builder.build().await.unwrap_or_log();
 
+    if let Some(address) = config.metrics_address {
+        tokio::spawn(async move {
+            if let Err(e) = metrics_server(address).await {
+                tracing::error!("Metrics server failed: {}", e);
+            }
+        });
+    }
+
     let (tx, rx) = tokio::sync::oneshot::channel::<()>();
     let webserver_handle = tokio::spawn(oauth2_server(
+        config.bind_address,
         config.secret.clone(),
         db,
         redis_pool.clone(),
+        comms,
+        config.request_guild_id,
+        config.api_admins,
         config.discord,
         rx,
     ));
@@ -1,36 +1,43 @@
+use std::{net::SocketAddr, sync::Arc};
+
 use async_fred_session::RedisSessionStore;
 use axum::{
     extract::{FromRef, Query, State},
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{Html, IntoResponse, Redirect},
-    routing::get,
-    Json, Router,
+    routing::{delete, get, post},
+    Router,
 };
 use axum_sessions::{extractors::WritableSession, SessionLayer};
 use fred::pool::RedisPool;
 use judeharley::{
-    db::{DbSong, DbUser},
+    communication::{LiquidsoapClient, LiquidsoapConnection},
+    db::DbUser,
     discord::{DiscordConnection, MinimalDiscordUser},
-    PgPool,
+    health, metrics, PgPool,
 };
 use oauth2::{
     basic::BasicClient, reqwest::async_http_client, AuthUrl, AuthorizationCode, ClientId,
     ClientSecret, CsrfToken, Scope, TokenResponse, TokenUrl,
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use tokio::sync::oneshot::Receiver;
-use tracing::error;
+use tracing::{error, info};
 use tracing_unwrap::ResultExt;
 
-use crate::{app_config::DiscordConfig, commands::songs, prelude::Error};
+use crate::{api, app_config::DiscordConfig, commands::songs, prelude::Error};
 
 static OAUTH2_SUCCESS_HTML: &str = include_str!("static/oauth2_success.html");
 static OAUTH2_FAILED_CSRF_HTML: &str = include_str!("static/oauth2_csrf.html");
 static OAUTH2_FAILED_DISCORD_HTML: &str = include_str!("static/oauth2_discord.html");
 
-#[derive(FromRef, Debug, Clone)]
+#[derive(FromRef, Clone)]
 struct AppState {
     db: PgPool,
+    redis: RedisPool,
+    comms: LiquidsoapClient<LiquidsoapConnection>,
+    request_guild: api::RequestGuild,
+    api_admins: api::ApiAdmins,
     discord_config: DiscordConfig,
 }
 
@@ -140,6 +147,9 @@ async fn oauth2_callback(
     user.add_linked_channels(&db, youtube_connections)
         .await
         .expect_or_log("Failed to add linked channels");
+    session
+        .insert("user_id", user.id)
+        .expect_or_log("Failed to insert user id");
 
     let next = session.get::<String>("next");
     if let Some(next) = next {
@@ -150,78 +160,85 @@ async fn oauth2_callback(
     (StatusCode::OK, Html(OAUTH2_SUCCESS_HTML.to_string())).into_response()
 }
 
-#[derive(Serialize, Debug)]
-#[serde(tag = "type")]
-enum ApiResponse<T> {
-    Success { data: T },
-    Error { error: String },
-}
-
-impl<T> IntoResponse for ApiResponse<T>
-where
-    T: Serialize,
-{
-    fn into_response(self) -> axum::response::Response {
-        (axum::http::StatusCode::OK, Json(self)).into_response()
-    }
+/// `GET /metrics`, in the Prometheus text format
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        metrics::render(&metrics::BYERS_METRICS),
+    )
 }
 
-#[derive(Serialize, Debug)]
-struct Song {
-    id: String,
-    title: String,
-    artist: String,
-    album: String,
-    duration: f64,
-}
+/// Serves `/metrics` on its own address, away from the public routes of [`oauth2_server`].
+pub async fn metrics_server(address: SocketAddr) -> Result<(), Error> {
+    let app = Router::new().route("/metrics", get(metrics));
 
-impl From<DbSong> for Song {
-    fn from(value: DbSong) -> Self {
-        Self {
-            id: value.file_hash,
-            title: value.title,
-            artist: value.artist,
-            album: value.album,
-            duration: value.duration,
-        }
-    }
-}
+    let server = axum::Server::try_bind(&address).map_err(|e| {
+        error!("Failed to listen on {}: {}", address, e);
+        e
+    })?;
 
-async fn song_list(State(app_state): State<AppState>) -> ApiResponse<Vec<Song>> {
-    let songs = match DbSong::fetch_all(&app_state.db).await {
-        Ok(songs) => songs,
-        Err(e) => {
-            error!("Failed to fetch songs: {}", e);
-            return ApiResponse::Error {
-                error: "Failed to fetch songs".to_string(),
-            };
-        }
-    };
+    info!("Serving metrics on {}", address);
+    server.serve(app.into_make_service()).await?;
 
-    ApiResponse::Success {
-        data: songs.into_iter().map(Into::into).collect(),
-    }
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn oauth2_server(
+    bind_address: SocketAddr,
     secret: String,
     db: PgPool,
     redis: RedisPool,
+    comms: LiquidsoapClient<LiquidsoapConnection>,
+    request_guild_id: Option<i64>,
+    api_admins: Vec<i64>,
     discord_config: DiscordConfig,
     ctrl_c: Receiver<()>,
 ) -> Result<(), Error> {
-    let cookie_store = RedisSessionStore::from_pool(redis, Some("byers-session/".into()));
+    let cookie_store = RedisSessionStore::from_pool(redis.clone(), Some("byers-session/".into()));
     let session_layer = SessionLayer::new(cookie_store, secret.as_bytes())
         .with_same_site_policy(axum_sessions::SameSite::Lax);
 
     let app = Router::new()
         .route("/oauth2/callback", get(oauth2_callback))
         .route("/oauth2/login", get(oauth2_login))
-        .route("/api/songs", get(song_list))
-        .with_state(AppState { db, discord_config })
+        .route("/api/songs", get(api::song_list))
+        .route("/api/songs/:hash", get(api::song))
+        .route("/api/requests", post(api::request_song))
+        .route(
+            "/api/playlists",
+            get(api::playlist_list).post(api::create_playlist),
+        )
+        .route(
+            "/api/playlists/:id",
+            get(api::playlist)
+                .put(api::update_playlist)
+                .delete(api::delete_playlist),
+        )
+        .route("/api/playlists/:id/songs", post(api::add_playlist_song))
+        .route(
+            "/api/playlists/:id/songs/:position",
+            delete(api::remove_playlist_song),
+        )
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .with_state(AppState {
+            db,
+            redis,
+            comms,
+            request_guild: api::RequestGuild(request_guild_id),
+            api_admins: api::ApiAdmins(Arc::new(api_admins)),
+            discord_config,
+        })
         .layer(session_layer);
 
-    axum::Server::bind(&"0.0.0.0:8000".parse()?)
+    let server = axum::Server::try_bind(&bind_address).map_err(|e| {
+        error!("Failed to listen on {}: {}", bind_address, e);
+        e
+    })?;
+
+    info!("Web server listening on {}", bind_address);
+    server
         .serve(app.into_make_service())
         .with_graceful_shutdown(async {
             ctrl_c.await.ok();
@@ -1,13 +1,10 @@
 use poise::serenity_prelude as serenity;
 use serenity::GatewayIntents;
 
-use std::sync::Arc;
-
 use lazy_static::lazy_static;
-use tokio::sync::Mutex;
 
 use crate::app_config::GoogleConfig;
-use judeharley::communication::{ByersUnixStream, LiquidsoapCommunication};
+use judeharley::communication::{LiquidsoapClient, LiquidsoapCommunication, LiquidsoapConnection};
 
 lazy_static! {
     pub static ref INTENTS: GatewayIntents = GatewayIntents::non_privileged()
@@ -15,8 +12,8 @@ lazy_static! {
         | GatewayIntents::GUILD_MEMBERS;
 }
 
-pub type Context<'a, C = ByersUnixStream> = poise::Context<'a, Data<C>, Error>;
-pub type ApplicationContext<'a, C = ByersUnixStream> =
+pub type Context<'a, C = LiquidsoapConnection> = poise::Context<'a, Data<C>, Error>;
+pub type ApplicationContext<'a, C = LiquidsoapConnection> =
     poise::ApplicationContext<'a, Data<C>, Error>;
 pub type Error = anyhow::Error;
 
@@ -25,7 +22,7 @@ where
     C: LiquidsoapCommunication,
 {
     pub db: judeharley::PgPool,
-    pub comms: Arc<Mutex<C>>,
+    pub comms: LiquidsoapClient<C>,
     pub google_config: GoogleConfig,
     pub redis_pool: fred::pool::RedisPool,
     pub redis_subscriber: fred::clients::SubscriberClient,
@@ -1,10 +1,22 @@
 use std::{
+    collections::HashMap,
+    net::SocketAddr,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
+use axum::{http::header, response::IntoResponse, routing::get, Router};
 use clap::{Parser, Subcommand};
-use judeharley::{db::DbSong, PgPool};
+use judeharley::{
+    db::{DbPlaylist, DbSong},
+    maintenance::{
+        import::ImportReport,
+        indexing::IndexReport,
+        playlists::{self, PlaylistImportReport},
+    },
+    metrics, PgPool,
+};
 use notify::Watcher;
 use tokio::sync::{mpsc::Receiver, Mutex};
 use tracing::{debug, error, info};
@@ -21,6 +33,7 @@ enum SubCommand {
     HouseKeeping(HouseKeeping),
     Indexing(Indexing),
     Import(Import),
+    Playlist(Playlist),
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -29,6 +42,9 @@ struct HouseKeeping {
     dry_run: bool,
     #[clap(short = 'D', long)]
     database_url: String,
+    /// Serve Prometheus metrics on this address, e.g. 0.0.0.0:8000
+    #[clap(short, long)]
+    metrics_address: Option<SocketAddr>,
 
     music_path: PathBuf,
 }
@@ -66,6 +82,41 @@ struct StreamlabsImport {
     path: PathBuf,
 }
 
+#[derive(Parser, Debug, Clone)]
+struct Playlist {
+    #[clap(short = 'D', long)]
+    database_url: String,
+
+    #[clap(subcommand)]
+    subcmd: PlaylistSubCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum PlaylistSubCommand {
+    /// Replaces the songs of a playlist, creating it if needed, with an M3U, M3U8 or PLS file
+    Import(PlaylistImport),
+    /// Writes a playlist to an M3U, M3U8 or PLS file
+    Export(PlaylistExport),
+}
+
+#[derive(Parser, Debug, Clone)]
+struct PlaylistImport {
+    #[clap(short, long)]
+    dry_run: bool,
+    /// The music folder Liquidsoap sees as /music, to match absolute paths inside it
+    #[clap(short, long)]
+    music_path: Option<PathBuf>,
+
+    name: String,
+    path: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct PlaylistExport {
+    name: String,
+    path: PathBuf,
+}
+
 fn async_watcher(
     handle: tokio::runtime::Handle,
 ) -> anyhow::Result<(
@@ -91,12 +142,36 @@ fn async_watcher(
     Ok((watcher, rx))
 }
 
+/// How long a rename may wait for its To event before the path counts as moved out of the
+/// library. Both events of a rename arrive together, so this is generous.
+const RENAME_TIMEOUT: Duration = Duration::from_secs(5);
+
 async fn async_watch<P: AsRef<Path>>(path: P, watcher_pool: PgPool) -> anyhow::Result<()> {
     let tokio_rt = tokio::runtime::Handle::current();
     let (mut watcher, mut rx) = async_watcher(tokio_rt)?;
     watcher.watch(path.as_ref(), notify::RecursiveMode::Recursive)?;
+    // paths renamed away from and when, by their rename's tracker
+    let mut renames = HashMap::<usize, (PathBuf, Instant)>::new();
+    let mut evictions = tokio::time::interval(RENAME_TIMEOUT);
 
-    while let Some(res) = rx.recv().await {
+    loop {
+        let res = tokio::select! {
+            res = rx.recv() => match res {
+                Some(res) => res,
+                None => break,
+            },
+            _ = evictions.tick() => {
+                // renames without a To event left the library
+                let (stale, pending): (Vec<_>, Vec<_>) = renames
+                    .drain()
+                    .partition(|(_, (_, at))| at.elapsed() >= RENAME_TIMEOUT);
+                renames.extend(pending);
+                for (_, (from, _)) in stale {
+                    drop_moved_away(&watcher_pool, &from, path.as_ref()).await;
+                }
+                continue;
+            }
+        };
         let event: notify::event::Event = match res {
             Ok(event) => event,
             Err(e) => {
@@ -122,34 +197,34 @@ async fn async_watch<P: AsRef<Path>>(path: P, watcher_pool: PgPool) -> anyhow::R
             notify::event::EventKind::Modify(notify::event::ModifyKind::Name(
                 notify::event::RenameMode::From,
             )) => {
-                debug!("file modified: {:?}", event.paths);
+                debug!("file renamed from: {:?}", event.paths);
+                // the path is gone already, the matching To event moves its index
                 let file_path = event.paths.first().unwrap();
-
-                if file_path.is_file() {
-                    judeharley::maintenance::indexing::drop_index(
-                        watcher_pool.clone(),
-                        file_path,
-                        path.as_ref(),
-                    )
-                    .await
-                    .unwrap();
-                } else if file_path.is_dir() {
-                    judeharley::maintenance::indexing::drop_index_folder(
-                        watcher_pool.clone(),
-                        file_path,
-                        path.as_ref(),
-                    )
-                    .await
-                    .unwrap();
+                match event.tracker() {
+                    Some(tracker) => {
+                        renames.insert(tracker, (file_path.clone(), Instant::now()));
+                    }
+                    None => drop_moved_away(&watcher_pool, file_path, path.as_ref()).await,
                 }
             }
             notify::event::EventKind::Modify(notify::event::ModifyKind::Name(
                 notify::event::RenameMode::To,
             )) => {
-                debug!("file modified: {:?}", event.paths);
+                debug!("file renamed to: {:?}", event.paths);
                 let file_path = event.paths.first().unwrap();
 
-                if file_path.is_file() {
+                if let Some((from, _)) =
+                    event.tracker().and_then(|tracker| renames.remove(&tracker))
+                {
+                    judeharley::maintenance::indexing::move_index(
+                        watcher_pool.clone(),
+                        &from,
+                        file_path,
+                        path.as_ref(),
+                    )
+                    .await
+                    .unwrap();
+                } else if file_path.is_file() {
                     judeharley::maintenance::indexing::index_file(
                         watcher_pool.clone(),
                         file_path,
@@ -201,6 +276,116 @@ async fn async_watch<P: AsRef<Path>>(path: P, watcher_pool: PgPool) -> anyhow::R
     Ok(())
 }
 
+/// Drops the index of a path that was renamed out of the library.
+async fn drop_moved_away(db: &PgPool, from: &Path, music_path: &Path) {
+    if let Err(e) =
+        judeharley::maintenance::indexing::drop_moved_away(db.clone(), from, music_path).await
+    {
+        error!("failed to drop the index of {}: {}", from.display(), e);
+    }
+}
+
+async fn serve_metrics(address: SocketAddr) -> anyhow::Result<()> {
+    async fn metrics() -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+            metrics::render(&metrics::FROHIKE_METRICS),
+        )
+    }
+
+    let app = Router::new().route("/metrics", get(metrics));
+
+    info!("Serving metrics on {}", address);
+    axum::Server::try_bind(&address)?
+        .serve(app.into_make_service())
+        .await?;
+
+    Ok(())
+}
+
+fn print_import_report(report: &ImportReport, dry_run: bool) {
+    for row in &report.new {
+        println!(
+            "+ {}: points {}, hours {}",
+            row.username, row.points, row.hours
+        );
+    }
+    for (existing, row) in &report.changed {
+        let name = if existing.username != row.username {
+            format!("{} -> {}", existing.username, row.username)
+        } else {
+            row.username.clone()
+        };
+        println!(
+            "~ {}: points {} -> {}, hours {} -> {}",
+            name, existing.points, row.points, existing.hours, row.hours
+        );
+    }
+    for (row, reason) in &report.conflicts {
+        println!("! {}: {}", row.username, reason);
+    }
+
+    println!(
+        "{} new, {} changed, {} unchanged, {} conflicting",
+        report.new.len(),
+        report.changed.len(),
+        report.unchanged,
+        report.conflicts.len()
+    );
+    if dry_run {
+        println!("Dry run, nothing was written to the database");
+    } else if !report.conflicts.is_empty() {
+        println!("Conflicting rows were skipped and need to be fixed by hand");
+    }
+}
+
+fn print_index_report(report: &IndexReport, dry_run: bool) {
+    for path in &report.failed {
+        println!("! {}: failed to read", path.display());
+    }
+
+    for file_path in &report.duplicates {
+        println!("= {}: duplicate, skipped", file_path);
+    }
+
+    println!(
+        "{} added, {} updated, {} moved, {} removed, {} unchanged, {} duplicates, {} failed",
+        report.added,
+        report.updated,
+        report.moved,
+        report.removed,
+        report.unchanged,
+        report.duplicates.len(),
+        report.failed.len()
+    );
+    if report.unwalkable > 0 {
+        println!(
+            "{} directory entries couldn't be read, songs whose files weren't found were kept",
+            report.unwalkable
+        );
+    }
+    if dry_run {
+        println!("Dry run, nothing was written to the database");
+    }
+}
+
+fn print_playlist_import_report(report: &PlaylistImportReport, dry_run: bool) {
+    for entry in &report.missing {
+        println!("! {}: not indexed", entry);
+    }
+
+    println!(
+        "{} songs, {} not indexed",
+        report.added,
+        report.missing.len()
+    );
+    if dry_run {
+        println!("Dry run, nothing was written to the database");
+    } else if !report.missing.is_empty() {
+        println!("Songs that aren't indexed were left out");
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
@@ -211,7 +396,13 @@ async fn main() -> anyhow::Result<()> {
             debug!("indexing");
             let pool = judeharley::connect_database(&indexing.database_url).await?;
 
-            judeharley::maintenance::indexing::index(pool.clone(), indexing.path).await?;
+            let report = judeharley::maintenance::indexing::index(
+                pool.clone(),
+                indexing.path,
+                indexing.dry_run,
+            )
+            .await?;
+            print_index_report(&report, indexing.dry_run);
 
             if let Some(playlist) = indexing.playlist {
                 info!("generating playlist");
@@ -229,6 +420,15 @@ async fn main() -> anyhow::Result<()> {
             let tasks = vec![async_watch(house_keeping.music_path.clone(), pool.clone())];
 
             let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+            if let Some(address) = house_keeping.metrics_address {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_metrics(address).await {
+                        error!("metrics server failed: {}", e);
+                    }
+                    tx.send(()).await.unwrap();
+                });
+            }
             for task in tasks {
                 let tx = tx.clone();
                 debug!("spawning task");
@@ -247,7 +447,52 @@ async fn main() -> anyhow::Result<()> {
                 debug!("received");
             }
         }
-        SubCommand::Import(_) => {}
+        SubCommand::Import(import) => {
+            debug!("import");
+            let pool = judeharley::connect_database(&import.database_url).await?;
+
+            match import.subcmd {
+                ImportSubCommand::Streamlabs(streamlabs) => {
+                    let report = judeharley::maintenance::import::import_streamlabs(
+                        &pool,
+                        &streamlabs.path,
+                        import.dry_run,
+                    )
+                    .await?;
+
+                    print_import_report(&report, import.dry_run);
+                }
+            }
+        }
+        SubCommand::Playlist(playlist) => {
+            debug!("playlist");
+            let pool = judeharley::connect_database(&playlist.database_url).await?;
+
+            match playlist.subcmd {
+                PlaylistSubCommand::Import(import) => {
+                    let report = playlists::import_playlist(
+                        &pool,
+                        &import.name,
+                        &import.path,
+                        import.music_path.as_deref(),
+                        import.dry_run,
+                    )
+                    .await?;
+
+                    print_playlist_import_report(&report, import.dry_run);
+                }
+                PlaylistSubCommand::Export(export) => {
+                    let Some(db_playlist) = DbPlaylist::fetch_by_name(&pool, &export.name).await?
+                    else {
+                        anyhow::bail!("there is no playlist named {}", export.name);
+                    };
+
+                    let count =
+                        playlists::export_playlist(&pool, &db_playlist, &export.path).await?;
+                    println!("Wrote {} songs to {}", count, export.path.display());
+                }
+            }
+        }
     }
 
     Ok(())
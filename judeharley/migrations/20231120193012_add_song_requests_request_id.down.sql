@@ -0,0 +1 @@
+ALTER TABLE song_requests DROP COLUMN request_id;
\ No newline at end of file
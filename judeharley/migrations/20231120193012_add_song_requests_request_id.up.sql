@@ -0,0 +1,2 @@
+ALTER TABLE song_requests
+ADD COLUMN request_id INTEGER;
\ No newline at end of file
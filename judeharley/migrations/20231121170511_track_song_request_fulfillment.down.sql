@@ -0,0 +1,8 @@
+ALTER TABLE song_requests DROP CONSTRAINT fk_song_requests_played_song_id,
+    DROP CONSTRAINT song_requests_status_check,
+    DROP COLUMN played_song_id,
+    DROP COLUMN status,
+    DROP COLUMN queue;
+
+ALTER TABLE played_songs DROP COLUMN request_id,
+    DROP COLUMN source;
\ No newline at end of file
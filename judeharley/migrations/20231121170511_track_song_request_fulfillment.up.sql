@@ -0,0 +1,21 @@
+ALTER TABLE played_songs
+ADD COLUMN source VARCHAR(16),
+    ADD COLUMN request_id INTEGER;
+
+ALTER TABLE song_requests
+ADD COLUMN queue VARCHAR(16),
+    ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    ADD COLUMN played_song_id INTEGER,
+    ADD CONSTRAINT song_requests_status_check CHECK (status IN ('pending', 'fulfilled', 'dropped')),
+    ADD CONSTRAINT fk_song_requests_played_song_id FOREIGN KEY (played_song_id) REFERENCES played_songs (id) ON DELETE SET NULL;
+
+-- best guess for requests made before we kept track: played at some point after being requested
+UPDATE song_requests
+SET status = CASE
+        WHEN EXISTS (
+            SELECT 1 FROM played_songs
+            WHERE played_songs.song_id = song_requests.song_id
+                AND played_songs.played_at >= song_requests.created_at
+        ) THEN 'fulfilled'
+        ELSE 'dropped'
+    END;
\ No newline at end of file
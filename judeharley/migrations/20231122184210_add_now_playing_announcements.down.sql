@@ -0,0 +1,3 @@
+ALTER TABLE server_channel_config
+DROP COLUMN now_playing_announcements,
+DROP COLUMN ping_requester;
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE server_channel_config
+ADD COLUMN now_playing_announcements BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN ping_requester BOOLEAN NOT NULL DEFAULT FALSE;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE unknown_tracks;
\ No newline at end of file
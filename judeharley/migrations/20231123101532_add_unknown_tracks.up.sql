@@ -0,0 +1,9 @@
+CREATE TABLE unknown_tracks (
+    file_path TEXT NOT NULL PRIMARY KEY,
+    title TEXT NOT NULL,
+    artist TEXT NOT NULL,
+    album TEXT NOT NULL,
+    first_played_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_played_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    play_count INTEGER NOT NULL DEFAULT 1
+);
\ No newline at end of file
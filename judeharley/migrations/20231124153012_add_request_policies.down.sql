@@ -0,0 +1,8 @@
+ALTER TABLE server_config
+DROP COLUMN requests_open,
+DROP COLUMN request_user_cooldown,
+DROP COLUMN request_short_song_cooldown,
+DROP COLUMN request_medium_song_cooldown,
+DROP COLUMN request_long_song_cooldown,
+DROP COLUMN request_max_pending,
+DROP COLUMN request_max_queue_length;
\ No newline at end of file
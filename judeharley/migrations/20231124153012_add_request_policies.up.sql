@@ -0,0 +1,8 @@
+ALTER TABLE server_config
+ADD COLUMN requests_open BOOLEAN NOT NULL DEFAULT TRUE,
+ADD COLUMN request_user_cooldown INTEGER NOT NULL DEFAULT 5400,
+ADD COLUMN request_short_song_cooldown INTEGER NOT NULL DEFAULT 1800,
+ADD COLUMN request_medium_song_cooldown INTEGER NOT NULL DEFAULT 3600,
+ADD COLUMN request_long_song_cooldown INTEGER NOT NULL DEFAULT 5413,
+ADD COLUMN request_max_pending INTEGER,
+ADD COLUMN request_max_queue_length INTEGER;
\ No newline at end of file
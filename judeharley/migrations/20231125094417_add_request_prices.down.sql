@@ -0,0 +1,7 @@
+ALTER TABLE song_requests
+DROP COLUMN boonbucks_paid;
+
+ALTER TABLE server_config
+DROP COLUMN request_price,
+DROP COLUMN request_cooldown_bypass_price,
+DROP COLUMN request_premium_price;
\ No newline at end of file
@@ -0,0 +1,7 @@
+ALTER TABLE server_config
+ADD COLUMN request_price INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN request_cooldown_bypass_price INTEGER,
+ADD COLUMN request_premium_price INTEGER;
+
+ALTER TABLE song_requests
+ADD COLUMN boonbucks_paid INTEGER NOT NULL DEFAULT 0;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE song_votes;
\ No newline at end of file
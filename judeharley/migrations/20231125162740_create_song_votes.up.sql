@@ -0,0 +1,9 @@
+CREATE TABLE song_votes (
+    played_song_id INTEGER NOT NULL,
+    user_id BIGINT NOT NULL,
+    liked BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (played_song_id, user_id),
+    CONSTRAINT fk_song_votes_played_song_id FOREIGN KEY (played_song_id) REFERENCES played_songs (id) ON DELETE CASCADE,
+    CONSTRAINT fk_song_votes_user_id FOREIGN KEY (user_id) REFERENCES users (id)
+);
\ No newline at end of file
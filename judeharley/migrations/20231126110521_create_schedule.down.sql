@@ -0,0 +1 @@
+DROP TABLE schedule;
\ No newline at end of file
@@ -0,0 +1,14 @@
+-- a slot either plays an album in track order or songs with a tag, e.g. genre = Ambient
+CREATE TABLE schedule (
+    id SERIAL PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    album TEXT,
+    tag TEXT,
+    tag_value TEXT,
+    starts_at TIMESTAMP NOT NULL,
+    length INTEGER NOT NULL,
+    repeats_weekly BOOLEAN NOT NULL DEFAULT FALSE,
+    last_played_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
\ No newline at end of file
@@ -0,0 +1,19 @@
+CREATE TABLE playlists (
+    id SERIAL PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- song_id isn't a foreign key for the same reason as in played_songs: reindexing prunes songs
+CREATE TABLE playlist_songs (
+    id SERIAL PRIMARY KEY NOT NULL,
+    playlist_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    song_id VARCHAR(64) NOT NULL,
+    added_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_playlist_songs_playlist_id FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE
+);
+
+CREATE INDEX playlist_songs_playlist_id_position ON playlist_songs (playlist_id, position);
\ No newline at end of file
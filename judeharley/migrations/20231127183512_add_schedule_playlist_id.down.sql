@@ -0,0 +1,2 @@
+ALTER TABLE schedule
+DROP COLUMN playlist_id;
\ No newline at end of file
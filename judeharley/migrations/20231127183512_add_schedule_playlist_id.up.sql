@@ -0,0 +1,3 @@
+ALTER TABLE schedule
+ADD COLUMN playlist_id INTEGER,
+ADD CONSTRAINT fk_schedule_playlist_id FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE;
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE songs
+DROP COLUMN file_size,
+DROP COLUMN file_modified_at;
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- lets the indexer skip files that haven't changed, NULL for songs indexed before
+ALTER TABLE songs
+ADD COLUMN file_size BIGINT,
+ADD COLUMN file_modified_at TIMESTAMP;
\ No newline at end of file
@@ -0,0 +1,8 @@
+-- the path hashes depended on where the files were on the indexing machine, so they can't be
+-- brought back. The indexer from before would replace the audio hashes with new path hashes and
+-- drop the history of every song.
+DO $$
+BEGIN
+    RAISE EXCEPTION 'songs can''t be identified by their path again, restore a backup from before this migration instead';
+END
+$$;
\ No newline at end of file
@@ -0,0 +1,13 @@
+-- songs are identified by a hash of their audio instead of their path now, forget the file stats
+-- so the next index rehashes every file and moves the history of each song over to its new hash.
+-- Only the songs marked as path hashed here have their history moved, songs indexed later keep
+-- theirs when their file is overwritten.
+ALTER TABLE songs
+ADD COLUMN path_hashed BOOLEAN NOT NULL DEFAULT false;
+
+UPDATE songs
+SET file_size = NULL, file_modified_at = NULL, path_hashed = true;
+
+-- the history is moved by song
+CREATE INDEX played_songs_song_id ON played_songs (song_id);
+CREATE INDEX song_requests_song_id ON song_requests (song_id);
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE song_requests
+DROP COLUMN bypassed_cooldown_until;
\ No newline at end of file
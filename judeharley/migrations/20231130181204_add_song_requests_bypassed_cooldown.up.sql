@@ -0,0 +1,2 @@
+ALTER TABLE song_requests
+ADD COLUMN bypassed_cooldown_until TIMESTAMP;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE song_requests
+DROP COLUMN guild_id;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE song_requests
+ADD COLUMN guild_id BIGINT;
\ No newline at end of file
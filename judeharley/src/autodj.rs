@@ -0,0 +1,310 @@
+//! The auto-DJ picks what plays when nobody requested anything and feeds it to Liquidsoap's
+//! `autodj` queue.
+//!
+//! Liquidsoap falls back to shuffling the music folder when the queue runs dry, so the radio
+//! keeps playing while the auto-DJ isn't running.
+
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use rand::{seq::SliceRandom, Rng};
+use sqlx::PgPool;
+use tracing::{debug, error, info};
+
+use crate::{
+    communication::{LiquidsoapClient, LiquidsoapCommunication, RequestQueue},
+    db::{DbSong, SongRating},
+    prelude::*,
+};
+
+/// A song needs at least this much weight to be picked, however disliked it is
+const MIN_WEIGHT: f64 = 0.05;
+
+#[derive(Debug, Clone)]
+pub struct AutoDjOptions {
+    /// How long before the same song may play again
+    pub song_separation: chrono::Duration,
+    /// How long before a song by the same artist may play again
+    pub artist_separation: chrono::Duration,
+    /// How long before a song from the same album may play again
+    pub album_separation: chrono::Duration,
+    /// Longer songs, in seconds, are never picked
+    pub max_duration: Option<f64>,
+    /// Seconds of music to keep queued
+    pub lookahead: f64,
+    /// How much every like above the dislikes raises the chance of a song to be picked, and
+    /// every dislike above the likes lowers it. `0.0` ignores ratings.
+    pub rating_weight: f64,
+    /// How often the queue is topped up
+    pub interval: Duration,
+}
+
+impl Default for AutoDjOptions {
+    fn default() -> Self {
+        Self {
+            song_separation: chrono::Duration::hours(6),
+            artist_separation: chrono::Duration::minutes(30),
+            album_separation: chrono::Duration::hours(1),
+            max_duration: None,
+            lookahead: 300.0,
+            rating_weight: 0.0,
+            interval: Duration::from_secs(30),
+        }
+    }
+}
+
+fn weight(rating: &SongRating, rating_weight: f64) -> f64 {
+    (1.0 + rating.score() as f64 * rating_weight).max(MIN_WEIGHT)
+}
+
+/// Picks a song out of `candidates` that isn't queued already, weighted by rating. Songs by
+/// the artists or from the albums that are queued are only picked if there's nothing else.
+fn pick<'a, R: Rng>(
+    candidates: &'a [(DbSong, SongRating)],
+    queued: &[DbSong],
+    rating_weight: f64,
+    rng: &mut R,
+) -> Option<&'a DbSong> {
+    let not_queued = candidates
+        .iter()
+        .filter(|(song, _)| !queued.iter().any(|q| q.file_hash == song.file_hash))
+        .collect::<Vec<_>>();
+    let separated = not_queued
+        .iter()
+        .copied()
+        .filter(|(song, _)| {
+            !queued
+                .iter()
+                .any(|q| q.artist == song.artist || q.album == song.album)
+        })
+        .collect::<Vec<_>>();
+
+    let pool = if separated.is_empty() {
+        not_queued
+    } else {
+        separated
+    };
+    pool.choose_weighted(rng, |(_, rating)| weight(rating, rating_weight))
+        .ok()
+        .map(|(song, _)| song)
+}
+
+pub struct AutoDj<'a, C> {
+    db: &'a PgPool,
+    comms: &'a LiquidsoapClient<C>,
+    options: AutoDjOptions,
+}
+
+impl<'a, C> AutoDj<'a, C>
+where
+    C: LiquidsoapCommunication<Error = JudeHarleyError> + Send + 'static,
+{
+    pub fn new(db: &'a PgPool, comms: &'a LiquidsoapClient<C>, options: AutoDjOptions) -> Self {
+        Self { db, comms, options }
+    }
+
+    /// Tops up the queue every [`AutoDjOptions::interval`], forever.
+    pub async fn run(&self) {
+        info!("Auto-DJ started");
+        let mut interval = tokio::time::interval(self.options.interval);
+        loop {
+            interval.tick().await;
+
+            match self.top_up().await {
+                Ok(0) => {}
+                Ok(picked) => debug!("Auto-DJ queued {} songs", picked),
+                Err(e) => error!("Auto-DJ failed to top up the queue: {}", e),
+            }
+        }
+    }
+
+    /// The songs waiting in the auto-DJ queue, next one first. Files that aren't indexed are
+    /// left out.
+    async fn queued(&self) -> Result<Vec<DbSong>> {
+        let mut queued = vec![];
+        for request_id in self.comms.queue(RequestQueue::AutoDj).await? {
+            let Some(info) = self.comms.request_status(request_id).await? else {
+                continue;
+            };
+            if let Some(song) = match info.filename() {
+                Some(filename) => DbSong::fetch(self.db, filename).await?,
+                None => None,
+            } {
+                queued.push(song);
+            }
+        }
+
+        Ok(queued)
+    }
+
+    /// Fetches the songs that may be picked, dropping the album, artist and song separation, in
+    /// that order, if a small library runs out of songs.
+    async fn candidates(&self, now: NaiveDateTime) -> Result<Vec<(DbSong, SongRating)>> {
+        let options = &self.options;
+        let song_since = now - options.song_separation;
+        let artist_since = now - options.artist_separation;
+        let album_since = now - options.album_separation;
+        let rules = [
+            (Some(song_since), Some(artist_since), Some(album_since)),
+            (Some(song_since), Some(artist_since), None),
+            (Some(song_since), None, None),
+            (None, None, None),
+        ];
+
+        for (song_since, artist_since, album_since) in rules {
+            let candidates = DbSong::fetch_autodj_candidates(
+                self.db,
+                song_since,
+                artist_since,
+                album_since,
+                options.max_duration,
+            )
+            .await?;
+            if !candidates.is_empty() {
+                return Ok(candidates);
+            }
+        }
+
+        Ok(vec![])
+    }
+
+    /// Queues songs until there are [`AutoDjOptions::lookahead`] seconds of music waiting and
+    /// returns how many were queued.
+    pub async fn top_up(&self) -> Result<usize> {
+        let mut queued = self.queued().await?;
+        let mut queued_duration = queued.iter().map(|song| song.duration).sum::<f64>();
+        if !queued.is_empty() && queued_duration >= self.options.lookahead {
+            return Ok(0);
+        }
+
+        let candidates = self.candidates(chrono::Utc::now().naive_utc()).await?;
+        let mut picked = 0;
+        while queued.is_empty() || queued_duration < self.options.lookahead {
+            let song = {
+                let mut rng = rand::thread_rng();
+                pick(&candidates, &queued, self.options.rating_weight, &mut rng)
+            };
+            let Some(song) = song else {
+                break;
+            };
+
+            self.comms
+                .push(RequestQueue::AutoDj, &song.file_path)
+                .await?;
+            debug!("Auto-DJ picked {}", song);
+            queued_duration += song.duration;
+            queued.push(song.clone());
+            picked += 1;
+        }
+
+        Ok(picked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn song(hash: &str, artist: &str, album: &str) -> DbSong {
+        DbSong {
+            artist: artist.to_string(),
+            album: album.to_string(),
+            ..DbSong::fixture(hash, 200.0)
+        }
+    }
+
+    fn unrated(songs: &[DbSong]) -> Vec<(DbSong, SongRating)> {
+        songs
+            .iter()
+            .map(|song| (song.clone(), SongRating::default()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pick_avoids_queued() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let candidates = unrated(&[
+            song("a", "Toby Fox", "Vol. 1"),
+            song("b", "Toby Fox", "Vol. 2"),
+            song("c", "Malcolm Brown", "Vol. 2"),
+            song("d", "Malcolm Brown", "Vol. 3"),
+        ]);
+        let queued = [
+            song("a", "Toby Fox", "Vol. 1"),
+            song("c", "Someone", "Vol. 2"),
+        ];
+
+        for _ in 0..20 {
+            let picked = pick(&candidates, &queued, 0.0, &mut rng).unwrap();
+            assert_eq!(picked.file_hash, "d");
+        }
+    }
+
+    #[test]
+    fn test_pick_falls_back_to_same_artist() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let candidates = unrated(&[
+            song("a", "Toby Fox", "Vol. 1"),
+            song("b", "Toby Fox", "Vol. 2"),
+        ]);
+        let queued = [song("a", "Toby Fox", "Vol. 1")];
+
+        let picked = pick(&candidates, &queued, 0.0, &mut rng).unwrap();
+        assert_eq!(picked.file_hash, "b");
+
+        let queued = [
+            song("a", "Toby Fox", "Vol. 1"),
+            song("b", "Toby Fox", "Vol. 2"),
+        ];
+        assert!(pick(&candidates, &queued, 0.0, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_pick_weights_ratings() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let candidates = [
+            (
+                song("liked", "Toby Fox", "Vol. 1"),
+                SongRating {
+                    likes: 10,
+                    dislikes: 0,
+                },
+            ),
+            (
+                song("disliked", "Malcolm Brown", "Vol. 2"),
+                SongRating {
+                    likes: 0,
+                    dislikes: 10,
+                },
+            ),
+        ];
+
+        let liked = (0..1000)
+            .filter(|_| pick(&candidates, &[], 1.0, &mut rng).unwrap().file_hash == "liked")
+            .count();
+        // 11 to 0.05
+        assert!(liked > 950, "picked the liked song {} times", liked);
+
+        let liked = (0..1000)
+            .filter(|_| pick(&candidates, &[], 0.0, &mut rng).unwrap().file_hash == "liked")
+            .count();
+        assert!(
+            (400..600).contains(&liked),
+            "picked the liked song {} times",
+            liked
+        );
+    }
+
+    #[test]
+    fn test_weight() {
+        let rating = SongRating {
+            likes: 3,
+            dislikes: 1,
+        };
+        assert_eq!(weight(&rating, 0.0), 1.0);
+        assert_eq!(weight(&rating, 0.5), 2.0);
+        assert_eq!(weight(&rating, -1.0), MIN_WEIGHT);
+    }
+}
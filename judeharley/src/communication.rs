@@ -1,17 +1,29 @@
-use std::path::PathBuf;
+use std::{future::Future, path::PathBuf, time::Duration};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tracing::{debug, warn};
 
 use crate::JudeHarleyError;
 
-#[derive(Deserialize, Debug)]
+mod client;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod protocol;
+
+pub use client::{ClientOptions, ClientStats, LiquidsoapClient};
+pub use protocol::{RequestInfo, RequestQueue, RequestStatus, SkipTarget, TrackMetadata};
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct QueueItem {
     pub album: Option<String>,
     pub artist: String,
     pub title: String,
     pub filename: String,
     pub queue: String,
+    /// Liquidsoap's ID of the request, missing from older scripts
+    #[serde(default)]
+    pub request_id: Option<u32>,
 }
 
 #[async_trait::async_trait]
@@ -20,155 +32,414 @@ pub trait LiquidsoapCommunication {
     async fn send(&mut self, command: &str) -> Result<(), Self::Error>;
     async fn send_wait(&mut self, command: &str) -> Result<String, Self::Error>;
     async fn song_requests(&mut self) -> Result<Vec<QueueItem>, Self::Error>;
+    async fn reconnect(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Controls how long and how often we try to reach Liquidsoap before giving up.
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub connect_timeout: Duration,
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(5),
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+async fn connect_with_backoff<T, F, Fut>(
+    target: String,
+    options: &ConnectOptions,
+    connect: F,
+) -> Result<T, JudeHarleyError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, std::io::Error>>,
+{
+    let mut backoff = options.initial_backoff;
+    let mut attempt = 1;
+
+    loop {
+        let error = match tokio::time::timeout(options.connect_timeout, connect()).await {
+            Ok(Ok(stream)) => {
+                debug!("Connected to liquidsoap at {}", target);
+                return Ok(stream);
+            }
+            Ok(Err(e)) => e,
+            Err(_) => std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("timed out after {:?}", options.connect_timeout),
+            ),
+        };
+
+        if attempt >= options.max_attempts {
+            return Err(JudeHarleyError::LiquidsoapConnect {
+                target,
+                attempts: attempt,
+                source: error,
+            });
+        }
 
-    async fn request_song(&mut self, song: &str) -> Result<String, Self::Error> {
-        self.send_wait(&format!("srq.push {}", song)).await
+        warn!(
+            "Failed to connect to liquidsoap at {} (attempt {}/{}): {}",
+            target, attempt, options.max_attempts, error
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(options.max_backoff);
+        attempt += 1;
     }
-    async fn priority_request(&mut self, song: &str) -> Result<String, Self::Error> {
-        self.send_wait(&format!("prioq.push {}", song)).await
+}
+
+/// Whether a write failed because the connection is gone. Only writes are retried: once a
+/// command is written, Liquidsoap may have run it, so a failed read must not send it again.
+fn is_disconnect(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::ConnectionReset
+    )
+}
+
+async fn read_until_end<S>(stream: &mut S) -> Result<String, std::io::Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = Vec::new();
+    let mut read_buffer = [0; 4096];
+
+    loop {
+        let bytes_read = stream.read(&mut read_buffer).await?;
+        if bytes_read == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "liquidsoap closed the connection",
+            ));
+        }
+        debug!("Read {} bytes from liquidsoap", bytes_read);
+        buf.extend_from_slice(&read_buffer[..bytes_read]);
+
+        if let Some(end_idx) = buf.windows(3).position(|window| window == b"END") {
+            return Ok(String::from_utf8_lossy(&buf[..end_idx]).to_string());
+        }
     }
 }
 
+async fn write_line<S>(stream: &mut S, data: &str) -> Result<(), std::io::Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    debug!("Writing to liquidsoap: {}", data);
+    stream.write_all(format!("{}\n", data).as_bytes()).await?;
+    stream.flush().await
+}
+
+/// Talks to Liquidsoap through its local command socket (`settings.server.socket`).
 pub struct ByersUnixStream {
+    path: PathBuf,
+    options: ConnectOptions,
     stream: tokio::net::UnixStream,
 }
 
 impl ByersUnixStream {
-    pub async fn new() -> Result<Self, std::io::Error> {
-        // wait until /usr/src/app/ls/lumiradio.sock exists
-        let stream = loop {
-            if PathBuf::from("/usr/src/app/ls/lumiradio.sock").exists() {
-                let stream_result = tokio::net::UnixStream::connect(PathBuf::from(
-                    "/usr/src/app/ls/lumiradio.sock",
-                ))
-                .await;
-                if let Ok(stream) = stream_result {
-                    break stream;
-                }
-            }
-            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-        };
+    pub async fn new(
+        path: impl Into<PathBuf>,
+        options: ConnectOptions,
+    ) -> Result<Self, JudeHarleyError> {
+        let path = path.into();
+        let stream = Self::connect(&path, &options).await?;
 
-        Ok(Self { stream })
+        Ok(Self {
+            path,
+            options,
+            stream,
+        })
     }
 
-    pub async fn reconnect(&mut self) -> Result<(), std::io::Error> {
-        self.stream = Self::new().await?.stream;
-        Ok(())
+    async fn connect(
+        path: &PathBuf,
+        options: &ConnectOptions,
+    ) -> Result<tokio::net::UnixStream, JudeHarleyError> {
+        connect_with_backoff(path.display().to_string(), options, || {
+            tokio::net::UnixStream::connect(path)
+        })
+        .await
     }
 
     pub async fn read_until_end(&mut self) -> Result<String, std::io::Error> {
-        let mut buf = Vec::new();
-        let mut read_buffer = [0; 4096];
-
-        loop {
-            self.stream.readable().await?;
-            let bytes_read = match self.stream.try_read(&mut read_buffer) {
-                Ok(n) => {
-                    debug!("Read {} bytes from liquidsoap", n);
-                    n
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    debug!("Would block, reading from liquidsoap");
-                    continue;
-                }
-                Err(e) => {
-                    return Err(e);
-                }
-            };
-            buf.extend_from_slice(&read_buffer[..bytes_read]);
-
-            if let Some(end_idx) = buf.windows(3).position(|window| window == b"END") {
-                return Ok(String::from_utf8_lossy(&buf[..end_idx]).to_string());
-            }
+        read_until_end(&mut self.stream).await
+    }
 
-            // clear the read buffer in case we didn't read the whole thing
-            read_buffer = [0; 4096];
-        }
+    pub async fn write_line(&mut self, data: &str) -> Result<(), std::io::Error> {
+        write_line(&mut self.stream, data).await
+    }
+
+    pub async fn write_str_and_wait_for_response(
+        &mut self,
+        data: &str,
+    ) -> Result<String, std::io::Error> {
+        self.write_line(data).await?;
+        self.read_until_end().await
+    }
+}
+
+#[async_trait::async_trait]
+impl LiquidsoapCommunication for ByersUnixStream {
+    type Error = JudeHarleyError;
+
+    async fn send_wait(&mut self, command: &str) -> Result<String, Self::Error> {
+        self.send(command).await?;
+        self.read_until_end().await.map_err(Into::into)
     }
 
-    pub async fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
-        loop {
-            self.stream.writable().await?;
-            match self.stream.try_write(data) {
-                Ok(n) => {
-                    debug!("Wrote {} bytes to liquidsoap", n);
-                    break;
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    debug!("Would block, writing to liquidsoap");
-                }
-                Err(e) => {
-                    return Err(e);
-                }
+    async fn send(&mut self, command: &str) -> Result<(), Self::Error> {
+        match self.write_line(command).await {
+            Err(e) if is_disconnect(&e) => {
+                warn!("Socket broken, reconnecting");
+                self.reconnect().await?;
+                self.write_line(command).await.map_err(Into::into)
             }
+            result => result.map_err(Into::into),
         }
+    }
 
+    async fn song_requests(&mut self) -> Result<Vec<QueueItem>, Self::Error> {
+        let result = self.send_wait("song_request_queue").await?;
+        serde_json::from_str(&result).map_err(Into::into)
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.stream = Self::connect(&self.path, &self.options).await?;
         Ok(())
     }
+}
 
-    pub async fn write_str(&mut self, data: &str) -> Result<(), std::io::Error> {
-        debug!("Writing to liquidsoap: {}", data);
-        self.write(data.as_bytes()).await
+/// Talks to Liquidsoap through its telnet server (`settings.server.telnet`), which allows
+/// Byers to run on a different host.
+pub struct ByersTcpStream {
+    host: String,
+    port: u16,
+    options: ConnectOptions,
+    stream: tokio::net::TcpStream,
+}
+
+impl ByersTcpStream {
+    pub async fn new(
+        host: impl Into<String>,
+        port: u16,
+        options: ConnectOptions,
+    ) -> Result<Self, JudeHarleyError> {
+        let host = host.into();
+        let stream = Self::connect(&host, port, &options).await?;
+
+        Ok(Self {
+            host,
+            port,
+            options,
+            stream,
+        })
     }
 
-    pub async fn write_line(&mut self, data: &str) -> Result<(), std::io::Error> {
-        let data_with_newline = format!("{}\n", data);
-        self.write_str(&data_with_newline).await?;
+    async fn connect(
+        host: &str,
+        port: u16,
+        options: &ConnectOptions,
+    ) -> Result<tokio::net::TcpStream, JudeHarleyError> {
+        connect_with_backoff(format!("{}:{}", host, port), options, || {
+            tokio::net::TcpStream::connect((host, port))
+        })
+        .await
+    }
 
-        Ok(())
+    pub async fn read_until_end(&mut self) -> Result<String, std::io::Error> {
+        read_until_end(&mut self.stream).await
+    }
+
+    pub async fn write_line(&mut self, data: &str) -> Result<(), std::io::Error> {
+        write_line(&mut self.stream, data).await
     }
 
     pub async fn write_str_and_wait_for_response(
         &mut self,
         data: &str,
     ) -> Result<String, std::io::Error> {
-        let data_with_newline = format!("{}\n", data);
-        self.write_str(&data_with_newline).await?;
-
-        let result = self.read_until_end().await?;
-        Ok(result)
+        self.write_line(data).await?;
+        self.read_until_end().await
     }
 }
 
 #[async_trait::async_trait]
-impl LiquidsoapCommunication for ByersUnixStream {
+impl LiquidsoapCommunication for ByersTcpStream {
     type Error = JudeHarleyError;
 
     async fn send_wait(&mut self, command: &str) -> Result<String, Self::Error> {
-        let result = self.write_str_and_wait_for_response(command).await;
+        self.send(command).await?;
+        self.read_until_end().await.map_err(Into::into)
+    }
 
-        if let Err(e) = result {
-            if e.kind() == std::io::ErrorKind::BrokenPipe {
-                warn!("Socket broken, reconnecting");
+    async fn send(&mut self, command: &str) -> Result<(), Self::Error> {
+        match self.write_line(command).await {
+            Err(e) if is_disconnect(&e) => {
+                warn!("Telnet connection broken, reconnecting");
                 self.reconnect().await?;
-                return self.send_wait(command).await;
-            } else {
-                return Err(e.into());
+                self.write_line(command).await.map_err(Into::into)
             }
+            result => result.map_err(Into::into),
         }
+    }
+
+    async fn song_requests(&mut self) -> Result<Vec<QueueItem>, Self::Error> {
+        let result = self.send_wait("song_request_queue").await?;
+        serde_json::from_str(&result).map_err(Into::into)
+    }
 
-        result.map_err(Into::into)
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.stream = Self::connect(&self.host, self.port, &self.options).await?;
+        Ok(())
     }
+}
 
-    async fn send(&mut self, command: &str) -> Result<(), Self::Error> {
-        let result = self.write_line(command).await;
+/// A Liquidsoap connection whose transport is picked at runtime from the configuration.
+pub enum LiquidsoapConnection {
+    Unix(ByersUnixStream),
+    Telnet(ByersTcpStream),
+}
 
-        if let Err(e) = result {
-            if e.kind() == std::io::ErrorKind::BrokenPipe {
-                warn!("Socket broken, reconnecting");
-                self.reconnect().await?;
-                return self.send(command).await;
-            } else {
-                return Err(e.into());
-            }
+#[async_trait::async_trait]
+impl LiquidsoapCommunication for LiquidsoapConnection {
+    type Error = JudeHarleyError;
+
+    async fn send(&mut self, command: &str) -> Result<(), Self::Error> {
+        match self {
+            Self::Unix(stream) => stream.send(command).await,
+            Self::Telnet(stream) => stream.send(command).await,
         }
+    }
 
-        result.map_err(Into::into)
+    async fn send_wait(&mut self, command: &str) -> Result<String, Self::Error> {
+        match self {
+            Self::Unix(stream) => stream.send_wait(command).await,
+            Self::Telnet(stream) => stream.send_wait(command).await,
+        }
     }
 
     async fn song_requests(&mut self) -> Result<Vec<QueueItem>, Self::Error> {
-        let result = self.send_wait("song_request_queue").await?;
-        serde_json::from_str(&result).map_err(Into::into)
+        match self {
+            Self::Unix(stream) => stream.song_requests().await,
+            Self::Telnet(stream) => stream.song_requests().await,
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        match self {
+            Self::Unix(stream) => stream.reconnect().await,
+            Self::Telnet(stream) => stream.reconnect().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mock::MockLiquidsoap, *};
+
+    #[tokio::test]
+    async fn test_song_requests() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        server
+            .add_song("/music/a.mp3", "Homestuck", "Showtime", Some("Volume 1"))
+            .await;
+        let mut comms = server.connect().await.unwrap();
+
+        let id = comms.send_wait("srq.push /music/a.mp3").await.unwrap();
+        assert_eq!(id.trim(), "0");
+        comms.send_wait("prioq.push /music/b.mp3").await.unwrap();
+
+        let queue = comms.song_requests().await.unwrap();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue[0].queue, "prioq");
+        assert_eq!(queue[0].title, "b");
+        assert_eq!(queue[1].queue, "srq");
+        assert_eq!(queue[1].title, "Showtime");
+        assert_eq!(queue[1].album.as_deref(), Some("Volume 1"));
+        assert_eq!(server.srq().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_admin_commands() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let mut comms = server.connect().await.unwrap();
+
+        let response = comms.send_wait("var.set volume = 0.5").await.unwrap();
+        assert_eq!(response.trim(), "Variable volume set (was 1.).");
+        assert_eq!(
+            comms.send_wait("var.get volume").await.unwrap().trim(),
+            "0.5"
+        );
+        assert_eq!(server.var("volume").await.as_deref(), Some("0.5"));
+
+        comms.send_wait("music.reload").await.unwrap();
+        assert_eq!(server.reloads().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let mut comms = server.connect().await.unwrap();
+
+        // the server hangs up on `quit`, so the next command has to reconnect first
+        assert_eq!(comms.send_wait("quit").await.unwrap().trim(), "Bye!");
+        comms.send_wait("srq.push /music/a.mp3").await.unwrap();
+        assert_eq!(server.srq().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_failures_are_not_retried() {
+        let path =
+            std::env::temp_dir().join(format!("lumiradio-hangup-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        // reads one command per connection and hangs up without answering it
+        let server = tokio::spawn(async move {
+            let mut commands = vec![];
+            while let Ok(Ok((mut stream, _))) =
+                tokio::time::timeout(Duration::from_millis(200), listener.accept()).await
+            {
+                let mut buf = [0; 64];
+                let read = stream.read(&mut buf).await.unwrap();
+                commands.push(String::from_utf8_lossy(&buf[..read]).to_string());
+            }
+            commands
+        });
+
+        let mut comms = ByersUnixStream::new(&path, ConnectOptions::default())
+            .await
+            .unwrap();
+        let result = comms.send_wait("srq.push /music/a.mp3").await;
+
+        assert!(matches!(result, Err(JudeHarleyError::Io(_))));
+        assert_eq!(server.await.unwrap(), vec!["srq.push /music/a.mp3\n"]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_connect_gives_up() {
+        let options = ConnectOptions {
+            connect_timeout: Duration::from_millis(100),
+            max_attempts: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        };
+        let result = ByersUnixStream::new("/nonexistent/lumiradio.sock", options).await;
+
+        assert!(matches!(
+            result,
+            Err(JudeHarleyError::LiquidsoapConnect { attempts: 2, .. })
+        ));
     }
 }
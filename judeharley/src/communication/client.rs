@@ -0,0 +1,535 @@
+use std::{
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{debug, error, warn};
+
+use super::{
+    protocol, LiquidsoapCommunication, QueueItem, RequestInfo, RequestQueue, SkipTarget,
+    TrackMetadata,
+};
+use crate::{
+    metrics::{LIQUIDSOAP_COMMAND_SECONDS, LIQUIDSOAP_RECONNECTS},
+    prelude::*,
+};
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// How long a command may take, including the time it spends waiting in the queue.
+    pub command_timeout: Duration,
+    /// How many commands may wait in the queue before callers have to wait to enqueue.
+    pub queue_size: usize,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            command_timeout: Duration::from_secs(10),
+            queue_size: 64,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClientMetrics {
+    queue_depth: AtomicUsize,
+    commands: AtomicU64,
+    failures: AtomicU64,
+    timeouts: AtomicU64,
+    reconnects: AtomicU64,
+    last_latency_us: AtomicU64,
+    total_latency_us: AtomicU64,
+}
+
+/// A snapshot of the client's metrics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientStats {
+    /// Commands waiting to be sent to Liquidsoap.
+    pub queue_depth: usize,
+    /// Commands that got a response, successful or not.
+    pub commands: u64,
+    pub failures: u64,
+    pub timeouts: u64,
+    pub reconnects: u64,
+    pub last_latency: Duration,
+    pub average_latency: Duration,
+}
+
+enum Request {
+    Send(String),
+    SendWait(String),
+    Reconnect,
+}
+
+impl Request {
+    fn describe(&self) -> &str {
+        match self {
+            Request::Send(command) | Request::SendWait(command) => command,
+            Request::Reconnect => "reconnect",
+        }
+    }
+}
+
+/// The command without its arguments, so song paths don't end up as metric labels
+fn metric_label(command: &str) -> &str {
+    command.split_whitespace().next().unwrap_or_default()
+}
+
+struct Job {
+    request: Request,
+    deadline: Instant,
+    reply: oneshot::Sender<Result<String>>,
+}
+
+/// A cloneable handle to a task that owns the Liquidsoap connection.
+///
+/// Commands are queued and sent one at a time, so callers never have to hold a lock on the
+/// connection. Every command has a deadline; if Liquidsoap doesn't answer in time, the
+/// connection is re-established so a late response can't end up answering the next command.
+/// Commands that come in while reconnecting fail right away with
+/// [`JudeHarleyError::LiquidsoapReconnecting`] instead of waiting for it.
+pub struct LiquidsoapClient<C> {
+    sender: mpsc::Sender<Job>,
+    metrics: Arc<ClientMetrics>,
+    options: ClientOptions,
+    _connection: PhantomData<fn() -> C>,
+}
+
+impl<C> Clone for LiquidsoapClient<C> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            metrics: self.metrics.clone(),
+            options: self.options.clone(),
+            _connection: PhantomData,
+        }
+    }
+}
+
+impl<C> LiquidsoapClient<C>
+where
+    C: LiquidsoapCommunication<Error = JudeHarleyError> + Send + 'static,
+{
+    /// Spawns the task driving `connection`. It stops once every handle has been dropped.
+    pub fn spawn(connection: C, options: ClientOptions) -> Self {
+        let (sender, receiver) = mpsc::channel(options.queue_size.max(1));
+        let metrics = Arc::new(ClientMetrics::default());
+
+        tokio::spawn(run(connection, receiver, metrics.clone()));
+
+        Self {
+            sender,
+            metrics,
+            options,
+            _connection: PhantomData,
+        }
+    }
+}
+
+impl<C> LiquidsoapClient<C> {
+    async fn execute(&self, request: Request, timeout: Duration) -> Result<String> {
+        let command = request.describe().to_string();
+        let (reply, response) = oneshot::channel();
+        let job = Job {
+            request,
+            deadline: Instant::now() + timeout,
+            reply,
+        };
+
+        self.metrics.queue_depth.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send(job).await.is_err() {
+            self.metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+            return Err(JudeHarleyError::LiquidsoapClosed);
+        }
+
+        match tokio::time::timeout(timeout, response).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(JudeHarleyError::LiquidsoapClosed),
+            Err(_) => Err(JudeHarleyError::LiquidsoapTimeout(command)),
+        }
+    }
+
+    /// Sends a command and discards Liquidsoap's response.
+    pub async fn send(&self, command: &str) -> Result<()> {
+        self.execute(
+            Request::Send(command.to_string()),
+            self.options.command_timeout,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn send_wait(&self, command: &str) -> Result<String> {
+        self.send_wait_with_timeout(command, self.options.command_timeout)
+            .await
+    }
+
+    pub async fn send_wait_with_timeout(&self, command: &str, timeout: Duration) -> Result<String> {
+        self.execute(Request::SendWait(command.to_string()), timeout)
+            .await
+    }
+
+    pub async fn song_requests(&self) -> Result<Vec<QueueItem>> {
+        let result = self.send_wait("song_request_queue").await?;
+        serde_json::from_str(&result).map_err(Into::into)
+    }
+
+    /// Queues `song` in `queue` and returns the request ID.
+    pub async fn push(&self, queue: RequestQueue, song: &str) -> Result<u32> {
+        let command = format!("{}.push {}", queue.id(), song);
+        let response = self.send_wait(&command).await?;
+        protocol::parse_request_id(&command, &response)
+    }
+
+    /// Queues `song` in the song request queue and returns the request ID.
+    pub async fn request_song(&self, song: &str) -> Result<u32> {
+        self.push(RequestQueue::SongRequests, song).await
+    }
+
+    pub async fn priority_request(&self, song: &str) -> Result<u32> {
+        self.push(RequestQueue::PriorityRequests, song).await
+    }
+
+    /// The volume multiplier, where `1.0` is unchanged.
+    pub async fn volume(&self) -> Result<f32> {
+        let command = "var.get volume";
+        let response = self.send_wait(command).await?;
+        protocol::parse_volume(command, &response)
+    }
+
+    /// Sets the volume multiplier and returns the previous one.
+    pub async fn set_volume(&self, volume: f32) -> Result<f32> {
+        let command = protocol::set_volume_command(volume);
+        let response = self.send_wait(&command).await?;
+        protocol::parse_set_volume(&command, &response)
+    }
+
+    pub async fn skip(&self, target: SkipTarget) -> Result<()> {
+        let command = target.command();
+        let response = self.send_wait(&command).await?;
+        protocol::parse_ok(&command, &response)
+    }
+
+    /// Stops the Icecast output; the stream stays silent until [`Self::resume`] is called.
+    pub async fn pause(&self) -> Result<()> {
+        let command = "lumiradio.stop";
+        let response = self.send_wait(command).await?;
+        protocol::parse_ok(command, &response)
+    }
+
+    pub async fn resume(&self) -> Result<()> {
+        let command = "lumiradio.start";
+        let response = self.send_wait(command).await?;
+        protocol::parse_ok(command, &response)
+    }
+
+    pub async fn is_playing(&self) -> Result<bool> {
+        let command = "lumiradio.status";
+        let response = self.send_wait(command).await?;
+        protocol::parse_output_status(command, &response)
+    }
+
+    /// The IDs of the requests waiting in `queue`, next one first.
+    pub async fn queue(&self, queue: RequestQueue) -> Result<Vec<u32>> {
+        let command = format!("{}.queue", queue.id());
+        let response = self.send_wait(&command).await?;
+        protocol::parse_queue(&command, &response)
+    }
+
+    pub async fn remove_request(&self, queue: RequestQueue, id: u32) -> Result<()> {
+        let command = format!("{}.remove {}", queue.id(), id);
+        let response = self.send_wait(&command).await?;
+        protocol::parse_ok(&command, &response)
+    }
+
+    pub async fn current_track(&self) -> Result<Option<TrackMetadata>> {
+        let command = "current_track";
+        let response = self.send_wait(command).await?;
+        protocol::parse_current_track(command, &response)
+    }
+
+    /// Returns `None` if Liquidsoap has already forgotten the request.
+    pub async fn request_status(&self, id: u32) -> Result<Option<RequestInfo>> {
+        let command = format!("request.metadata {}", id);
+        let response = self.send_wait(&command).await?;
+        protocol::parse_request_info(id, &command, &response)
+    }
+
+    pub async fn reconnect(&self) -> Result<()> {
+        self.execute(Request::Reconnect, self.options.command_timeout)
+            .await
+            .map(|_| ())
+    }
+
+    pub fn stats(&self) -> ClientStats {
+        let commands = self.metrics.commands.load(Ordering::Relaxed);
+        let total_latency_us = self.metrics.total_latency_us.load(Ordering::Relaxed);
+
+        ClientStats {
+            queue_depth: self.metrics.queue_depth.load(Ordering::Relaxed),
+            commands,
+            failures: self.metrics.failures.load(Ordering::Relaxed),
+            timeouts: self.metrics.timeouts.load(Ordering::Relaxed),
+            reconnects: self.metrics.reconnects.load(Ordering::Relaxed),
+            last_latency: Duration::from_micros(
+                self.metrics.last_latency_us.load(Ordering::Relaxed),
+            ),
+            average_latency: Duration::from_micros(
+                total_latency_us.checked_div(commands).unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+/// Reconnects in a task of its own, so queued commands don't have to wait for it. `reply` is
+/// answered once it's done.
+fn spawn_reconnect<C>(
+    mut connection: C,
+    metrics: &ClientMetrics,
+    reply: Option<oneshot::Sender<Result<String>>>,
+) -> JoinHandle<C>
+where
+    C: LiquidsoapCommunication<Error = JudeHarleyError> + Send + 'static,
+{
+    metrics.reconnects.fetch_add(1, Ordering::Relaxed);
+    LIQUIDSOAP_RECONNECTS.inc();
+
+    tokio::spawn(async move {
+        let result = connection.reconnect().await;
+        match &result {
+            Ok(()) => debug!("Reconnected to liquidsoap"),
+            Err(e) => warn!("Failed to reconnect to liquidsoap: {}", e),
+        }
+        if let Some(reply) = reply {
+            let _ = reply.send(result.map(|_| String::new()));
+        }
+
+        connection
+    })
+}
+
+async fn run<C>(connection: C, mut receiver: mpsc::Receiver<Job>, metrics: Arc<ClientMetrics>)
+where
+    C: LiquidsoapCommunication<Error = JudeHarleyError> + Send + 'static,
+{
+    // `None` while a reconnect has the connection
+    let mut connection = Some(connection);
+    let mut reconnecting: Option<JoinHandle<C>> = None;
+
+    loop {
+        let job = tokio::select! {
+            job = receiver.recv() => match job {
+                Some(job) => job,
+                None => break,
+            },
+            reconnected = async { reconnecting.as_mut().unwrap().await }, if reconnecting.is_some() => {
+                reconnecting = None;
+                match reconnected {
+                    Ok(reconnected) => connection = Some(reconnected),
+                    Err(e) => {
+                        error!("Reconnecting to liquidsoap panicked: {}", e);
+                        break;
+                    }
+                }
+                continue;
+            }
+        };
+        metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+
+        // the caller already gave up on this one
+        if job.reply.is_closed() || Instant::now() >= job.deadline {
+            metrics.timeouts.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+
+        let Some(conn) = connection.as_mut() else {
+            let command = job.request.describe().to_string();
+            let _ = job
+                .reply
+                .send(Err(JudeHarleyError::LiquidsoapReconnecting(command)));
+            continue;
+        };
+        let command = match job.request {
+            Request::Reconnect => {
+                let conn = connection.take().unwrap();
+                reconnecting = Some(spawn_reconnect(conn, &metrics, Some(job.reply)));
+                continue;
+            }
+            // the response still has to be read, otherwise it would answer the next command
+            Request::Send(command) | Request::SendWait(command) => command,
+        };
+
+        let started = Instant::now();
+        let result = tokio::time::timeout_at(job.deadline, conn.send_wait(&command)).await;
+        let result = match result {
+            Ok(result) => {
+                let latency = started.elapsed().as_micros() as u64;
+                metrics.commands.fetch_add(1, Ordering::Relaxed);
+                metrics.last_latency_us.store(latency, Ordering::Relaxed);
+                metrics
+                    .total_latency_us
+                    .fetch_add(latency, Ordering::Relaxed);
+                LIQUIDSOAP_COMMAND_SECONDS
+                    .with_label_values(&[metric_label(&command)])
+                    .observe(started.elapsed().as_secs_f64());
+                if result.is_err() {
+                    metrics.failures.fetch_add(1, Ordering::Relaxed);
+                }
+                debug!("Liquidsoap answered `{}` in {}us", command, latency);
+
+                result
+            }
+            Err(_) => {
+                warn!("Liquidsoap command `{}` timed out, reconnecting", command);
+                metrics.timeouts.fetch_add(1, Ordering::Relaxed);
+                let conn = connection.take().unwrap();
+                reconnecting = Some(spawn_reconnect(conn, &metrics, None));
+
+                Err(JudeHarleyError::LiquidsoapTimeout(command))
+            }
+        };
+
+        let _ = job.reply.send(result);
+    }
+
+    debug!("All Liquidsoap client handles dropped, stopping");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::{mock::MockLiquidsoap, ByersUnixStream, RequestStatus};
+
+    #[tokio::test]
+    async fn test_concurrent_requests() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = LiquidsoapClient::spawn(server.connect().await.unwrap(), Default::default());
+
+        let requests = (0..10).map(|i| {
+            let client = client.clone();
+            async move { client.request_song(&format!("/music/{}.mp3", i)).await }
+        });
+        let results = futures::future::join_all(requests).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(server.srq().await.len(), 10);
+        assert_eq!(client.song_requests().await.unwrap().len(), 10);
+
+        let stats = client.stats();
+        assert_eq!(stats.commands, 11);
+        assert_eq!(stats.queue_depth, 0);
+        assert_eq!(stats.failures, 0);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_reconnects() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = LiquidsoapClient::spawn(server.connect().await.unwrap(), Default::default());
+
+        server.set_delay(Duration::from_millis(200)).await;
+        let result = client
+            .send_wait_with_timeout("var.get volume", Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(JudeHarleyError::LiquidsoapTimeout(_))));
+
+        // the late answer to the first command must not be mistaken for this one
+        server.set_delay(Duration::ZERO).await;
+        let id = request_after_reconnect(&client).await;
+        assert_eq!(id, 0);
+
+        let stats = client.stats();
+        assert_eq!(stats.timeouts, 1);
+        assert_eq!(stats.reconnects, 1);
+    }
+
+    /// Requests a song once the client is done reconnecting.
+    async fn request_after_reconnect(client: &LiquidsoapClient<ByersUnixStream>) -> u32 {
+        for _ in 0..50 {
+            match client.request_song("/music/a.mp3").await {
+                Err(JudeHarleyError::LiquidsoapReconnecting(_)) => {
+                    tokio::time::sleep(Duration::from_millis(50)).await
+                }
+                result => return result.unwrap(),
+            }
+        }
+
+        panic!("the client never reconnected");
+    }
+
+    #[tokio::test]
+    async fn test_commands_fail_while_reconnecting() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = LiquidsoapClient::spawn(server.connect().await.unwrap(), Default::default());
+
+        // without the socket, the reconnect keeps backing off
+        let moved = server.socket_path().with_extension("moved");
+        std::fs::rename(server.socket_path(), &moved).unwrap();
+        server.set_delay(Duration::from_millis(200)).await;
+        let result = client
+            .send_wait_with_timeout("var.get volume", Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(JudeHarleyError::LiquidsoapTimeout(_))));
+
+        let started = Instant::now();
+        let result = client.request_song("/music/a.mp3").await;
+        assert!(matches!(
+            result,
+            Err(JudeHarleyError::LiquidsoapReconnecting(_))
+        ));
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        std::fs::rename(&moved, server.socket_path()).unwrap();
+        server.set_delay(Duration::ZERO).await;
+        request_after_reconnect(&client).await;
+        assert_eq!(server.srq().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_typed_commands() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let comms = LiquidsoapClient::spawn(server.connect().await.unwrap(), Default::default());
+
+        assert_eq!(comms.set_volume(0.5).await.unwrap(), 1.0);
+        assert_eq!(comms.volume().await.unwrap(), 0.5);
+
+        comms.pause().await.unwrap();
+        assert!(server.stopped().await);
+        assert!(!comms.is_playing().await.unwrap());
+        comms.resume().await.unwrap();
+        assert!(comms.is_playing().await.unwrap());
+
+        let first = comms.request_song("/music/a.mp3").await.unwrap();
+        let second = comms.request_song("/music/b.mp3").await.unwrap();
+        let queue = comms.queue(RequestQueue::SongRequests).await.unwrap();
+        assert_eq!(queue, vec![first, second]);
+
+        let info = comms.request_status(second).await.unwrap().unwrap();
+        assert_eq!(info.status, RequestStatus::Ready);
+        assert_eq!(info.filename(), Some("/music/b.mp3"));
+
+        comms
+            .remove_request(RequestQueue::SongRequests, second)
+            .await
+            .unwrap();
+        assert!(comms.request_status(second).await.unwrap().is_none());
+        assert!(comms
+            .remove_request(RequestQueue::SongRequests, second)
+            .await
+            .is_err());
+
+        assert!(comms.current_track().await.unwrap().is_none());
+        comms.skip(SkipTarget::Current).await.unwrap();
+        let current = comms.current_track().await.unwrap().unwrap();
+        assert_eq!(current.filename.as_deref(), Some("/music/a.mp3"));
+    }
+}
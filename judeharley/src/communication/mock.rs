@@ -0,0 +1,369 @@
+//! A fake Liquidsoap command server for tests.
+//!
+//! It listens on a Unix socket and answers commands with the same `END`-terminated protocol
+//! as Liquidsoap's `settings.server.socket`, keeping the queues and interactive variables in
+//! memory so tests can assert on them afterwards.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::Mutex,
+    task::{JoinHandle, JoinSet},
+};
+use tracing::debug;
+
+use super::{ByersUnixStream, ConnectOptions, QueueItem};
+use crate::prelude::*;
+
+static SOCKET_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRequest {
+    pub id: u32,
+    pub filename: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct MockMetadata {
+    artist: String,
+    title: String,
+    album: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    next_request_id: u32,
+    prioq: VecDeque<MockRequest>,
+    srq: VecDeque<MockRequest>,
+    autodj: VecDeque<MockRequest>,
+    metadata: HashMap<String, MockMetadata>,
+    vars: HashMap<String, String>,
+    reloads: usize,
+    commands: Vec<String>,
+    delay: Duration,
+    stopped: bool,
+    current: Option<QueueItem>,
+}
+
+impl MockState {
+    fn queue_mut(&mut self, queue: &str) -> Option<&mut VecDeque<MockRequest>> {
+        match queue {
+            "srq" => Some(&mut self.srq),
+            "prioq" => Some(&mut self.prioq),
+            "autodj" => Some(&mut self.autodj),
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, queue: &str, filename: &str) -> String {
+        let request = MockRequest {
+            id: self.next_request_id,
+            filename: filename.to_string(),
+        };
+        self.next_request_id += 1;
+
+        let id = request.id;
+        if let Some(queue) = self.queue_mut(queue) {
+            queue.push_back(request);
+        }
+
+        id.to_string()
+    }
+
+    fn handle_queue(&mut self, queue: &str, action: &str, args: &str) -> Option<String> {
+        let response = match action {
+            "push" => self.push(queue, args),
+            "queue" => self
+                .queue_mut(queue)?
+                .iter()
+                .map(|r| r.id.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            "skip" => {
+                self.queue_mut(queue)?.pop_front();
+                "Done".to_string()
+            }
+            "remove" => {
+                let id = args.parse::<u32>().ok();
+                let requests = self.queue_mut(queue)?;
+                let before = requests.len();
+                requests.retain(|r| Some(r.id) != id);
+                if requests.len() < before {
+                    "OK".to_string()
+                } else {
+                    "ERROR: no such request".to_string()
+                }
+            }
+            _ => return None,
+        };
+
+        Some(response)
+    }
+
+    fn request_metadata(&self, args: &str) -> String {
+        let id = args.parse::<u32>().ok();
+        let Some(request) = self
+            .prioq
+            .iter()
+            .chain(self.srq.iter())
+            .chain(self.autodj.iter())
+            .find(|r| Some(r.id) == id)
+        else {
+            return "No such request.".to_string();
+        };
+
+        format!(
+            "rid=\"{}\"\nstatus=\"ready\"\nfilename=\"{}\"",
+            request.id, request.filename
+        )
+    }
+
+    /// Starts playing the next request, like Liquidsoap would at the end of a track.
+    fn skip_current(&mut self) {
+        self.current = if let Some(request) = self.prioq.pop_front() {
+            Some(self.queue_item(&request, "prioq"))
+        } else if let Some(request) = self.srq.pop_front() {
+            Some(self.queue_item(&request, "srq"))
+        } else {
+            self.autodj
+                .pop_front()
+                .map(|request| self.queue_item(&request, "autodj"))
+        };
+    }
+
+    fn queue_item(&self, request: &MockRequest, queue: &str) -> QueueItem {
+        let metadata = self
+            .metadata
+            .get(&request.filename)
+            .cloned()
+            .unwrap_or_else(|| MockMetadata {
+                artist: String::new(),
+                title: Path::new(&request.filename)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                album: None,
+            });
+
+        QueueItem {
+            album: metadata.album,
+            artist: metadata.artist,
+            title: metadata.title,
+            filename: request.filename.clone(),
+            queue: queue.to_string(),
+            request_id: Some(request.id),
+        }
+    }
+
+    fn song_request_queue(&self) -> String {
+        let items = self
+            .prioq
+            .iter()
+            .map(|r| self.queue_item(r, "prioq"))
+            .chain(self.srq.iter().map(|r| self.queue_item(r, "srq")))
+            .collect::<Vec<_>>();
+
+        serde_json::to_string(&items).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    fn handle(&mut self, line: &str) -> String {
+        self.commands.push(line.to_string());
+
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        if let Some((queue @ ("srq" | "prioq" | "autodj"), action)) = command.split_once('.') {
+            if let Some(response) = self.handle_queue(queue, action, args) {
+                return response;
+            }
+        }
+
+        match command {
+            "song_request_queue" => self.song_request_queue(),
+            "current_track" => match &self.current {
+                Some(track) => serde_json::to_string(track).unwrap_or_default(),
+                None => "[]".to_string(),
+            },
+            "request.metadata" => self.request_metadata(args),
+            "lumiradio.skip" => {
+                self.skip_current();
+                "Done".to_string()
+            }
+            "lumiradio.start" => {
+                self.stopped = false;
+                "OK".to_string()
+            }
+            "lumiradio.stop" => {
+                self.stopped = true;
+                "OK".to_string()
+            }
+            "lumiradio.status" => if self.stopped { "off" } else { "on" }.to_string(),
+            "music.reload" => {
+                self.reloads += 1;
+                "OK".to_string()
+            }
+            "var.get" => match self.vars.get(args) {
+                Some(value) => value.clone(),
+                None => "Variable not found.".to_string(),
+            },
+            "var.set" => {
+                let Some((name, value)) = args.split_once('=') else {
+                    return "Usage: var.set <variable> = <value>".to_string();
+                };
+                let (name, value) = (name.trim(), value.trim());
+                match self.vars.get_mut(name) {
+                    Some(old) => {
+                        let was = std::mem::replace(old, value.to_string());
+                        format!("Variable {} set (was {}).", name, was)
+                    }
+                    None => "Variable not found.".to_string(),
+                }
+            }
+            _ => "ERROR: unknown command, type \"help\" to get a list of commands.".to_string(),
+        }
+    }
+}
+
+/// An in-process stand-in for Liquidsoap, listening on a temporary Unix socket.
+///
+/// The socket is removed and all connections are closed when the mock is dropped.
+pub struct MockLiquidsoap {
+    path: PathBuf,
+    state: Arc<Mutex<MockState>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockLiquidsoap {
+    /// Starts the server with the same interactive variables as our `script.liq`.
+    pub async fn start() -> Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "lumiradio-mock-{}-{}.sock",
+            std::process::id(),
+            SOCKET_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        let state = Arc::new(Mutex::new(MockState::default()));
+        state
+            .lock()
+            .await
+            .vars
+            .insert("volume".to_string(), "1.".to_string());
+
+        let accept_state = state.clone();
+        let handle = tokio::spawn(async move {
+            // dropping the set when the accept loop is aborted closes all connections
+            let mut connections = JoinSet::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.spawn(serve(stream, accept_state.clone()));
+            }
+        });
+
+        Ok(Self {
+            path,
+            state,
+            handle,
+        })
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens a client connection to this server.
+    pub async fn connect(&self) -> Result<ByersUnixStream> {
+        ByersUnixStream::new(&self.path, ConnectOptions::default()).await
+    }
+
+    /// Registers tags for a file, which are then reported by `song_request_queue`.
+    pub async fn add_song(&self, filename: &str, artist: &str, title: &str, album: Option<&str>) {
+        self.state.lock().await.metadata.insert(
+            filename.to_string(),
+            MockMetadata {
+                artist: artist.to_string(),
+                title: title.to_string(),
+                album: album.map(ToString::to_string),
+            },
+        );
+    }
+
+    pub async fn srq(&self) -> Vec<MockRequest> {
+        self.state.lock().await.srq.iter().cloned().collect()
+    }
+
+    pub async fn prioq(&self) -> Vec<MockRequest> {
+        self.state.lock().await.prioq.iter().cloned().collect()
+    }
+
+    pub async fn autodj(&self) -> Vec<MockRequest> {
+        self.state.lock().await.autodj.iter().cloned().collect()
+    }
+
+    pub async fn var(&self, name: &str) -> Option<String> {
+        self.state.lock().await.vars.get(name).cloned()
+    }
+
+    pub async fn reloads(&self) -> usize {
+        self.state.lock().await.reloads
+    }
+
+    /// Whether the Icecast output has been stopped with `lumiradio.stop`.
+    pub async fn stopped(&self) -> bool {
+        self.state.lock().await.stopped
+    }
+
+    /// Makes the server wait before answering each command, to simulate a busy Liquidsoap.
+    pub async fn set_delay(&self, delay: Duration) {
+        self.state.lock().await.delay = delay;
+    }
+
+    /// Every command received so far, in order.
+    pub async fn commands(&self) -> Vec<String> {
+        self.state.lock().await.commands.clone()
+    }
+}
+
+impl Drop for MockLiquidsoap {
+    fn drop(&mut self) {
+        self.handle.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn serve(stream: UnixStream, state: Arc<Mutex<MockState>>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        debug!("Mock liquidsoap received: {}", line);
+
+        if line == "quit" || line == "exit" {
+            let _ = writer.write_all(b"Bye!\nEND\n").await;
+            return;
+        }
+
+        let (response, delay) = {
+            let mut state = state.lock().await;
+            (state.handle(line), state.delay)
+        };
+        tokio::time::sleep(delay).await;
+        let response = format!("{}\nEND\n", response);
+        if writer.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Commands understood by our Liquidsoap script and parsers for their responses.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+/// The request queues defined in `script.liq`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestQueue {
+    /// `srq`, fed by user song requests
+    SongRequests,
+    /// `prioq`, played before any user song request
+    PriorityRequests,
+    /// `autodj`, fed by the auto-DJ and played when there are no requests
+    AutoDj,
+}
+
+impl RequestQueue {
+    pub fn id(&self) -> &'static str {
+        match self {
+            RequestQueue::SongRequests => "srq",
+            RequestQueue::PriorityRequests => "prioq",
+            RequestQueue::AutoDj => "autodj",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "srq" => Some(RequestQueue::SongRequests),
+            "prioq" => Some(RequestQueue::PriorityRequests),
+            "autodj" => Some(RequestQueue::AutoDj),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipTarget {
+    /// The track that is currently on air
+    Current,
+    /// The next request waiting in a queue
+    Queue(RequestQueue),
+}
+
+impl SkipTarget {
+    pub(crate) fn command(&self) -> String {
+        match self {
+            SkipTarget::Current => "lumiradio.skip".to_string(),
+            SkipTarget::Queue(queue) => format!("{}.skip", queue.id()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestStatus {
+    Idle,
+    Resolving,
+    Ready,
+    Playing,
+    Destroyed,
+    Other(String),
+}
+
+impl From<&str> for RequestStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "idle" => RequestStatus::Idle,
+            "resolving" => RequestStatus::Resolving,
+            "ready" => RequestStatus::Ready,
+            "playing" => RequestStatus::Playing,
+            "destroyed" => RequestStatus::Destroyed,
+            other => RequestStatus::Other(other.to_string()),
+        }
+    }
+}
+
+/// What Liquidsoap knows about a single request, from `request.metadata <rid>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestInfo {
+    pub id: u32,
+    pub status: RequestStatus,
+    pub metadata: HashMap<String, String>,
+}
+
+impl RequestInfo {
+    pub fn filename(&self) -> Option<&str> {
+        self.metadata.get("filename").map(String::as_str)
+    }
+}
+
+/// Metadata of the track on air, as reported by the `current_track` command.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct TrackMetadata {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub filename: Option<String>,
+}
+
+fn protocol_error(command: &str, response: &str) -> JudeHarleyError {
+    JudeHarleyError::LiquidsoapProtocol {
+        command: command.to_string(),
+        response: response.trim().to_string(),
+    }
+}
+
+/// Fails if Liquidsoap reported an error or doesn't know the command.
+pub(crate) fn parse_ok(command: &str, response: &str) -> Result<()> {
+    let trimmed = response.trim();
+    if trimmed.starts_with("ERROR") || trimmed.starts_with("Unknown command") {
+        return Err(protocol_error(command, response));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn set_volume_command(volume: f32) -> String {
+    format!("var.set volume = {}", volume)
+}
+
+pub(crate) fn parse_volume(command: &str, response: &str) -> Result<f32> {
+    response
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|volume| volume.is_finite() && *volume >= 0.0)
+        .ok_or_else(|| protocol_error(command, response))
+}
+
+/// Parses `Variable volume set (was 1.).` into the previous volume.
+pub(crate) fn parse_set_volume(command: &str, response: &str) -> Result<f32> {
+    let previous = response
+        .trim()
+        .strip_prefix("Variable volume set (was ")
+        .and_then(|rest| rest.strip_suffix(")."))
+        .ok_or_else(|| protocol_error(command, response))?;
+
+    parse_volume(command, previous)
+}
+
+/// Parses the output of `lumiradio.status`.
+pub(crate) fn parse_output_status(command: &str, response: &str) -> Result<bool> {
+    match response.trim() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(protocol_error(command, response)),
+    }
+}
+
+/// Parses a request ID, as returned by `<queue>.push`.
+pub(crate) fn parse_request_id(command: &str, response: &str) -> Result<u32> {
+    response
+        .trim()
+        .parse()
+        .map_err(|_| protocol_error(command, response))
+}
+
+/// Parses the space separated request IDs returned by `<queue>.queue`.
+pub(crate) fn parse_queue(command: &str, response: &str) -> Result<Vec<u32>> {
+    response
+        .split_whitespace()
+        .map(|rid| rid.parse().map_err(|_| protocol_error(command, response)))
+        .collect()
+}
+
+/// Parses the `key="value"` lines returned by `request.metadata <rid>`.
+///
+/// Returns `None` if Liquidsoap doesn't know the request (anymore).
+pub(crate) fn parse_request_info(
+    id: u32,
+    command: &str,
+    response: &str,
+) -> Result<Option<RequestInfo>> {
+    if response.trim() == "No such request." {
+        return Ok(None);
+    }
+
+    let mut metadata = HashMap::new();
+    for line in response.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| protocol_error(command, response))?;
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .ok_or_else(|| protocol_error(command, response))?;
+        metadata.insert(key.to_string(), value.replace("\\\"", "\""));
+    }
+
+    let status = metadata
+        .get("status")
+        .map(|s| RequestStatus::from(s.as_str()))
+        .ok_or_else(|| protocol_error(command, response))?;
+
+    Ok(Some(RequestInfo {
+        id,
+        status,
+        metadata,
+    }))
+}
+
+/// Parses the JSON returned by `current_track`, which is an empty list before the first track.
+pub(crate) fn parse_current_track(command: &str, response: &str) -> Result<Option<TrackMetadata>> {
+    let value: serde_json::Value =
+        serde_json::from_str(response.trim()).map_err(|_| protocol_error(command, response))?;
+
+    match value {
+        serde_json::Value::Array(values) if values.is_empty() => Ok(None),
+        serde_json::Value::Object(_) => serde_json::from_value(value)
+            .map(Some)
+            .map_err(|_| protocol_error(command, response)),
+        _ => Err(protocol_error(command, response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_volume() {
+        assert_eq!(parse_volume("var.get volume", "1.\n").unwrap(), 1.0);
+        assert_eq!(parse_volume("var.get volume", "0.25").unwrap(), 0.25);
+        assert!(matches!(
+            parse_volume("var.get volume", "Variable not found."),
+            Err(JudeHarleyError::LiquidsoapProtocol { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_set_volume() {
+        let command = set_volume_command(0.5);
+        assert_eq!(command, "var.set volume = 0.5");
+        assert_eq!(
+            parse_set_volume(&command, "Variable volume set (was 1.).\n").unwrap(),
+            1.0
+        );
+        assert!(parse_set_volume(&command, "Variable not found.").is_err());
+    }
+
+    #[test]
+    fn test_parse_queue() {
+        assert_eq!(
+            parse_queue("srq.queue", "3 4 12\n").unwrap(),
+            vec![3, 4, 12]
+        );
+        assert!(parse_queue("srq.queue", "\n").unwrap().is_empty());
+        assert!(parse_queue("srq.queue", "ERROR: unknown command").is_err());
+    }
+
+    #[test]
+    fn test_parse_request_info() {
+        let response = "status=\"ready\"\nfilename=\"/music/a \\\"b\\\".mp3\"\nrid=\"3\"\n";
+        let info = parse_request_info(3, "request.metadata 3", response)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.status, RequestStatus::Ready);
+        assert_eq!(info.filename(), Some("/music/a \"b\".mp3"));
+
+        assert!(
+            parse_request_info(4, "request.metadata 4", "No such request.")
+                .unwrap()
+                .is_none()
+        );
+        assert!(parse_request_info(5, "request.metadata 5", "garbage").is_err());
+    }
+
+    #[test]
+    fn test_parse_current_track() {
+        assert!(parse_current_track("current_track", "[]")
+            .unwrap()
+            .is_none());
+
+        let track = parse_current_track(
+            "current_track",
+            r#"{"artist": "Toby Fox", "title": "Showtime", "filename": "/music/a.mp3"}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(track.title.as_deref(), Some("Showtime"));
+        assert_eq!(track.album, None);
+    }
+}
@@ -3,7 +3,7 @@ use std::fmt::Display;
 use chrono::NaiveDateTime;
 use fred::{pool::RedisPool, prelude::KeysInterface};
 
-use crate::prelude::*;
+use crate::{metrics::COOLDOWN_HITS, prelude::*};
 
 #[derive(Debug, Clone, Copy)]
 pub struct UserCooldownKey<'a> {
@@ -52,15 +52,26 @@ pub trait CooldownKey
 where
     Self: Display,
 {
+    /// The name of the cooldown, without the user it applies to
+    fn name(&self) -> &str;
 }
 
-impl<'a> CooldownKey for UserCooldownKey<'a> {}
-impl<'a> CooldownKey for GlobalCooldownKey<'a> {}
+impl<'a> CooldownKey for UserCooldownKey<'a> {
+    fn name(&self) -> &str {
+        self.key
+    }
+}
+impl<'a> CooldownKey for GlobalCooldownKey<'a> {
+    fn name(&self) -> &str {
+        self.key
+    }
+}
 
 pub async fn is_on_cooldown<C>(pool: &RedisPool, key: C) -> Result<Option<NaiveDateTime>>
 where
     C: CooldownKey + Display,
 {
+    let name = key.name().to_string();
     let key = key.to_string();
     let value: Option<String> = pool.get(&key).await?;
 
@@ -76,6 +87,7 @@ where
         return Ok(None);
     }
 
+    COOLDOWN_HITS.with_label_values(&[&name]).inc();
     Ok(Some(over))
 }
 
@@ -98,3 +110,13 @@ where
 
     Ok(())
 }
+
+/// Lifts a cooldown before it runs out, e.g. when the action it guards was undone.
+pub async fn reset_cooldown<C>(pool: &RedisPool, key: C) -> Result<()>
+where
+    C: CooldownKey + Display,
+{
+    pool.del::<(), _>(key.to_string()).await?;
+
+    Ok(())
+}
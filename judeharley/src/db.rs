@@ -1,10 +1,10 @@
-use std::{fmt::Display, path::Path};
+use std::{collections::HashSet, fmt::Display, path::Path};
 
 use chrono::NaiveDateTime;
 use num_traits::cast::ToPrimitive;
 use sqlx::{types::BigDecimal, PgPool};
 
-use crate::{discord::DiscordConnection, JudeHarleyError};
+use crate::{communication::RequestQueue, discord::DiscordConnection, JudeHarleyError};
 
 // generate a macro that accepts an sqlx PgPool and a block of code and runs it and at the end, runs self.update(db)
 #[macro_export]
@@ -15,6 +15,7 @@ macro_rules! update {
     }};
 }
 
+#[derive(Debug, Clone)]
 pub struct DbSong {
     pub title: String,
     pub artist: String,
@@ -25,6 +26,31 @@ pub struct DbSong {
     pub bitrate: i32,
 }
 
+#[cfg(test)]
+impl DbSong {
+    /// A song by Toby Fox, titled and hashed `hash`, for tests.
+    pub(crate) fn fixture(hash: &str, duration: f64) -> Self {
+        Self {
+            title: hash.to_string(),
+            artist: "Toby Fox".to_string(),
+            album: "Homestuck Vol. 5".to_string(),
+            file_path: format!("/music/{}.mp3", hash),
+            duration,
+            file_hash: hash.to_string(),
+            bitrate: 320,
+        }
+    }
+}
+
+/// What the index knows about a song's file, to tell whether it changed since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbSongFile {
+    pub file_path: String,
+    /// In bytes, unknown for songs indexed before we kept track
+    pub file_size: Option<i64>,
+    pub file_modified_at: Option<NaiveDateTime>,
+}
+
 impl Display for DbSong {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&format!("{} - {}", self.artist, self.title))
@@ -32,13 +58,23 @@ impl Display for DbSong {
 }
 
 impl DbSong {
-    pub async fn upsert(&self, db: &PgPool) -> Result<(), JudeHarleyError> {
-        sqlx::query!(
+    /// Inserts the song or updates it in place if its audio is indexed already, moving it to its
+    /// new path and remembering the file's size and modification time. Returns whether the song
+    /// is new.
+    pub async fn upsert(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        file: &DbSongFile,
+    ) -> Result<bool, JudeHarleyError> {
+        let inserted = sqlx::query!(
             r#"
-            INSERT INTO songs (file_path, file_hash, title, artist, album, duration, bitrate)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-            ON CONFLICT (file_path)
-            DO UPDATE SET file_hash = $2, title = $3, artist = $4, album = $5, duration = $6, bitrate = $7
+            INSERT INTO songs (file_path, file_hash, title, artist, album, duration, bitrate,
+                file_size, file_modified_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (file_hash)
+            DO UPDATE SET file_path = $1, title = $3, artist = $4, album = $5, duration = $6,
+                bitrate = $7, file_size = $8, file_modified_at = $9
+            RETURNING (xmax = 0) AS "inserted!"
             "#,
             self.file_path,
             self.file_hash,
@@ -46,102 +82,297 @@ impl DbSong {
             self.artist,
             self.album,
             self.duration,
-            self.bitrate
+            self.bitrate,
+            file.file_size,
+            file.file_modified_at
         )
-        .execute(db)
-        .await?;
+        .fetch_one(&mut **transaction)
+        .await?
+        .inserted;
 
-        Ok(())
+        Ok(inserted)
     }
 
-    pub async fn insert(&self, db: &PgPool) -> Result<(), JudeHarleyError> {
-        if let Some(_) = Self::fetch(db, &self.file_path).await? {
-            return Ok(());
-        }
+    /// Replaces the song's tags.
+    pub async fn set_tags(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        tags: &[(String, String)],
+    ) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM song_tags
+            WHERE song_id = $1
+            "#,
+            self.file_hash
+        )
+        .execute(&mut **transaction)
+        .await?;
 
+        let (keys, values): (Vec<_>, Vec<_>) = tags.iter().cloned().unzip();
         sqlx::query!(
             r#"
-            INSERT INTO songs (file_path, file_hash, title, artist, album, duration, bitrate)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            INSERT INTO song_tags (song_id, tag, value)
+            SELECT $1, tag, value
+            FROM unnest($2::text[], $3::text[]) AS tags(tag, value)
             "#,
-            self.file_path,
             self.file_hash,
-            self.title,
-            self.artist,
-            self.album,
-            self.duration,
-            self.bitrate
+            &keys,
+            &values
         )
-        .execute(db)
+        .execute(&mut **transaction)
         .await?;
 
         Ok(())
     }
 
-    pub async fn add_tags(
-        &self,
-        db: &PgPool,
-        tags: &[(String, String)],
-    ) -> Result<(), JudeHarleyError> {
+    /// The hash, size and modification time of every indexed file.
+    pub async fn fetch_files(db: &PgPool) -> Result<Vec<(String, DbSongFile)>, JudeHarleyError> {
+        let files = sqlx::query!(
+            r#"
+            SELECT file_hash, file_path, file_size, file_modified_at
+            FROM songs
+            "#
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| {
+            (
+                row.file_hash,
+                DbSongFile {
+                    file_path: row.file_path,
+                    file_size: row.file_size,
+                    file_modified_at: row.file_modified_at,
+                },
+            )
+        })
+        .collect();
+
+        Ok(files)
+    }
+
+    /// Removes the songs with `file_hashes` and their tags, returning how many there were.
+    pub async fn delete_hashes(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        file_hashes: &[String],
+    ) -> Result<u64, JudeHarleyError> {
         sqlx::query!(
             r#"
             DELETE FROM song_tags
-            WHERE song_id = $1
+            WHERE song_id = ANY($1)
             "#,
-            self.file_hash
+            file_hashes
         )
-        .execute(db)
+        .execute(&mut **transaction)
         .await?;
 
-        for (key, value) in tags {
-            sqlx::query!(
-                r#"
-                INSERT INTO song_tags (song_id, tag, value)
-                VALUES ($1, $2, $3)
-                "#,
-                self.file_hash,
-                key,
-                value
-            )
-            .execute(db)
-            .await?;
-        }
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM songs
+            WHERE file_hash = ANY($1)
+            "#,
+            file_hashes
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Moves the songs with `file_hashes` out of the way, so other songs can take their paths
+    /// before [`DbSong::upsert`] gives them their new ones.
+    pub async fn release_paths(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        file_hashes: &[String],
+    ) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            UPDATE songs
+            SET file_path = 'moving:' || file_hash
+            WHERE file_hash = ANY($1)
+            "#,
+            file_hashes
+        )
+        .execute(&mut **transaction)
+        .await?;
 
         Ok(())
     }
 
-    pub async fn delete(&self, db: &PgPool) -> Result<(), JudeHarleyError> {
+    /// Hashes of the songs that are still identified by a hash of their path, from before songs
+    /// were identified by their audio.
+    pub async fn fetch_path_hashed(db: &PgPool) -> Result<HashSet<String>, JudeHarleyError> {
+        let hashes = sqlx::query!(
+            r#"
+            SELECT file_hash
+            FROM songs
+            WHERE path_hashed
+            "#
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| row.file_hash)
+        .collect();
+
+        Ok(hashes)
+    }
+
+    /// Gives songs new hashes, as `(old_hash, new_hash)` pairs, keeping their tags, plays,
+    /// requests and playlist entries.
+    pub async fn rekey(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        hashes: &[(String, String)],
+    ) -> Result<(), JudeHarleyError> {
+        let (old_hashes, new_hashes): (Vec<_>, Vec<_>) = hashes.iter().cloned().unzip();
         sqlx::query!(
             r#"
-            DELETE FROM song_tags
-            WHERE song_id = $1
+            UPDATE songs
+            SET file_hash = rekeyed.new_hash, path_hashed = false
+            FROM unnest($1::text[], $2::text[]) AS rekeyed(old_hash, new_hash)
+            WHERE songs.file_hash = rekeyed.old_hash
             "#,
-            self.file_hash
+            &old_hashes,
+            &new_hashes
         )
-        .execute(db)
+        .execute(&mut **transaction)
         .await?;
 
         sqlx::query!(
             r#"
-            DELETE FROM songs
-            WHERE file_hash = $1
+            UPDATE song_tags
+            SET song_id = rekeyed.new_hash
+            FROM unnest($1::text[], $2::text[]) AS rekeyed(old_hash, new_hash)
+            WHERE song_tags.song_id = rekeyed.old_hash
             "#,
-            self.file_hash
+            &old_hashes,
+            &new_hashes
         )
-        .execute(db)
+        .execute(&mut **transaction)
+        .await?;
+
+        Self::move_history(transaction, hashes).await
+    }
+
+    /// Moves the plays, requests and playlist entries of songs to other songs, as
+    /// `(from_hash, to_hash)` pairs.
+    pub async fn move_history(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        hashes: &[(String, String)],
+    ) -> Result<(), JudeHarleyError> {
+        let (from_hashes, to_hashes): (Vec<_>, Vec<_>) = hashes.iter().cloned().unzip();
+        sqlx::query!(
+            r#"
+            UPDATE played_songs
+            SET song_id = moved.to_hash
+            FROM unnest($1::text[], $2::text[]) AS moved(from_hash, to_hash)
+            WHERE played_songs.song_id = moved.from_hash
+            "#,
+            &from_hashes,
+            &to_hashes
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE song_requests
+            SET song_id = moved.to_hash
+            FROM unnest($1::text[], $2::text[]) AS moved(from_hash, to_hash)
+            WHERE song_requests.song_id = moved.from_hash
+            "#,
+            &from_hashes,
+            &to_hashes
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE playlist_songs
+            SET song_id = moved.to_hash
+            FROM unnest($1::text[], $2::text[]) AS moved(from_hash, to_hash)
+            WHERE playlist_songs.song_id = moved.from_hash
+            "#,
+            &from_hashes,
+            &to_hashes
+        )
+        .execute(&mut **transaction)
         .await?;
 
         Ok(())
     }
 
-    pub async fn delete_by_path(db: &PgPool, file_path: &Path) -> Result<(), JudeHarleyError> {
-        let file_path = file_path.display().to_string();
+    /// Moves the song at `from` to `to`, replacing the song that was indexed at `to`. Returns
+    /// whether there was a song at `from`.
+    pub async fn move_file(db: &PgPool, from: &Path, to: &Path) -> Result<bool, JudeHarleyError> {
+        let from = from.display().to_string();
+        let to = to.display().to_string();
+
+        let mut transaction = db.begin().await?;
+        let hashes = sqlx::query!(
+            r#"
+            SELECT file_path, file_hash
+            FROM songs
+            WHERE file_path = $1 OR file_path = $2
+            "#,
+            from,
+            to
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        if !hashes.iter().any(|row| row.file_path == from) {
+            return Ok(false);
+        }
+        if let Some(replaced) = hashes.into_iter().find(|row| row.file_path == to) {
+            Self::delete_hashes(&mut transaction, &[replaced.file_hash]).await?;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE songs
+            SET file_path = $2
+            WHERE file_path = $1
+            "#,
+            from,
+            to
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Moves the songs inside the directory `from` to `to`, returning how many there were.
+    pub async fn move_directory(
+        db: &PgPool,
+        from: &Path,
+        to: &Path,
+    ) -> Result<u64, JudeHarleyError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE songs
+            SET file_path = $2 || substr(file_path, length($1) + 1)
+            WHERE starts_with(file_path, $1)
+            "#,
+            format!("{}/", from.display()),
+            format!("{}/", to.display())
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete(&self, db: &PgPool) -> Result<(), JudeHarleyError> {
         sqlx::query!(
             r#"
             DELETE FROM song_tags
             WHERE song_id = $1
             "#,
-            file_path
+            self.file_hash
         )
         .execute(db)
         .await?;
@@ -149,9 +380,9 @@ impl DbSong {
         sqlx::query!(
             r#"
             DELETE FROM songs
-            WHERE file_path = $1
+            WHERE file_hash = $1
             "#,
-            file_path
+            self.file_hash
         )
         .execute(db)
         .await?;
@@ -159,11 +390,14 @@ impl DbSong {
         Ok(())
     }
 
-    pub async fn prune(db: &PgPool) -> Result<(), JudeHarleyError> {
+    pub async fn delete_by_path(db: &PgPool, file_path: &Path) -> Result<(), JudeHarleyError> {
+        let file_path = file_path.display().to_string();
         sqlx::query!(
             r#"
             DELETE FROM song_tags
+            WHERE song_id = $1
             "#,
+            file_path
         )
         .execute(db)
         .await?;
@@ -171,7 +405,9 @@ impl DbSong {
         sqlx::query!(
             r#"
             DELETE FROM songs
+            WHERE file_path = $1
             "#,
+            file_path
         )
         .execute(db)
         .await?;
@@ -219,6 +455,21 @@ impl DbSong {
         .map_err(Into::into)
     }
 
+    /// When the song that is currently playing started.
+    pub async fn last_played_at(db: &PgPool) -> Result<Option<NaiveDateTime>, JudeHarleyError> {
+        let last_played = sqlx::query!(
+            r#"
+            SELECT played_at FROM played_songs
+            ORDER BY played_at DESC
+            LIMIT 1
+            "#,
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(last_played.map(|p| p.played_at))
+    }
+
     pub async fn played(&self, db: &PgPool) -> Result<i64, JudeHarleyError> {
         let played = sqlx::query!(
             r#"
@@ -234,6 +485,156 @@ impl DbSong {
         Ok(played)
     }
 
+    /// Likes and dislikes of all plays of this song.
+    pub async fn rating(&self, db: &PgPool) -> Result<SongRating, JudeHarleyError> {
+        sqlx::query_as!(
+            SongRating,
+            r#"
+            SELECT COUNT(*) FILTER (WHERE song_votes.liked) AS "likes!",
+                COUNT(*) FILTER (WHERE NOT song_votes.liked) AS "dislikes!"
+            FROM song_votes
+            JOIN played_songs ON played_songs.id = song_votes.played_song_id
+            WHERE played_songs.song_id = $1
+            "#,
+            self.file_hash
+        )
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Songs the auto-DJ may pick along with their ratings: songs that weren't played since
+    /// `song_since`, by an artist that wasn't played since `artist_since`, from an album that
+    /// wasn't played since `album_since` and no longer than `max_duration` seconds. `None`
+    /// leaves out that rule.
+    pub async fn fetch_autodj_candidates(
+        db: &PgPool,
+        song_since: Option<NaiveDateTime>,
+        artist_since: Option<NaiveDateTime>,
+        album_since: Option<NaiveDateTime>,
+        max_duration: Option<f64>,
+    ) -> Result<Vec<(Self, SongRating)>, JudeHarleyError> {
+        let rows = sqlx::query!(
+            r#"
+            WITH recent AS (
+                SELECT songs.file_hash, songs.artist, songs.album, played_songs.played_at
+                FROM played_songs
+                JOIN songs ON songs.file_hash = played_songs.song_id
+                WHERE played_songs.played_at > LEAST($1::timestamp, $2::timestamp, $3::timestamp)
+            ),
+            ratings AS (
+                SELECT played_songs.song_id,
+                    COUNT(*) FILTER (WHERE song_votes.liked) AS likes,
+                    COUNT(*) FILTER (WHERE NOT song_votes.liked) AS dislikes
+                FROM song_votes
+                JOIN played_songs ON played_songs.id = song_votes.played_song_id
+                GROUP BY played_songs.song_id
+            )
+            SELECT songs.title, songs.artist, songs.album, songs.file_path, songs.duration,
+                songs.file_hash, songs.bitrate,
+                COALESCE(ratings.likes, 0) AS "likes!", COALESCE(ratings.dislikes, 0) AS "dislikes!"
+            FROM songs
+            LEFT JOIN ratings ON ratings.song_id = songs.file_hash
+            WHERE ($4::float8 IS NULL OR songs.duration <= $4)
+                AND NOT EXISTS (
+                    SELECT 1 FROM recent
+                    WHERE (recent.file_hash = songs.file_hash AND recent.played_at > $1)
+                        OR (recent.artist = songs.artist AND recent.played_at > $2)
+                        OR (recent.album = songs.album AND recent.played_at > $3)
+                )
+            "#,
+            song_since,
+            artist_since,
+            album_since,
+            max_duration
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    DbSong {
+                        title: row.title,
+                        artist: row.artist,
+                        album: row.album,
+                        file_path: row.file_path,
+                        duration: row.duration,
+                        file_hash: row.file_hash,
+                        bitrate: row.bitrate,
+                    },
+                    SongRating {
+                        likes: row.likes,
+                        dislikes: row.dislikes,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// Fetches the songs on an album, ordered by their disc and track number tags.
+    pub async fn fetch_album_in_track_order(
+        db: &PgPool,
+        album: &str,
+    ) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbSong,
+            r#"
+            SELECT songs.title, songs.artist, songs.album, songs.file_path, songs.duration,
+                songs.file_hash, songs.bitrate
+            FROM songs
+            LEFT JOIN LATERAL (
+                SELECT substring(value FROM '^\s*(\d+)')::int AS number
+                FROM song_tags
+                WHERE song_tags.song_id = songs.file_hash AND lower(tag) IN ('disc', 'discnumber')
+                LIMIT 1
+            ) AS disc ON TRUE
+            LEFT JOIN LATERAL (
+                SELECT substring(value FROM '^\s*(\d+)')::int AS number
+                FROM song_tags
+                WHERE song_tags.song_id = songs.file_hash AND lower(tag) IN ('track', 'tracknumber')
+                LIMIT 1
+            ) AS track ON TRUE
+            WHERE lower(songs.album) = lower($1)
+            ORDER BY disc.number NULLS FIRST, track.number NULLS LAST, songs.file_path
+            "#,
+            album
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Fetches the songs with a tag, e.g. `genre` = `Ambient`, in random order. Both are
+    /// compared case-insensitively.
+    pub async fn fetch_by_tag(
+        db: &PgPool,
+        tag: &str,
+        value: &str,
+    ) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbSong,
+            r#"
+            SELECT songs.title, songs.artist, songs.album, songs.file_path, songs.duration,
+                songs.file_hash, songs.bitrate
+            FROM songs
+            WHERE EXISTS (
+                SELECT 1 FROM song_tags
+                WHERE song_tags.song_id = songs.file_hash
+                    AND lower(song_tags.tag) = lower($1)
+                    AND lower(song_tags.value) = lower($2)
+            )
+            ORDER BY random()
+            "#,
+            tag,
+            value
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
     pub async fn requested(&self, db: &PgPool) -> Result<i64, JudeHarleyError> {
         let requested = sqlx::query!(
             r#"
@@ -301,6 +702,42 @@ impl DbSong {
         .map_err(Into::into)
     }
 
+    pub async fn fetch_by_hashes(
+        db: &PgPool,
+        file_hashes: &[String],
+    ) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbSong,
+            r#"
+            SELECT title, artist, album, file_path, duration, file_hash, bitrate
+            FROM songs
+            WHERE file_hash = ANY($1)
+            "#,
+            file_hashes
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn fetch_by_paths(
+        db: &PgPool,
+        file_paths: &[String],
+    ) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbSong,
+            r#"
+            SELECT title, artist, album, file_path, duration, file_hash, bitrate
+            FROM songs
+            WHERE file_path = ANY($1)
+            "#,
+            file_paths
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
     pub async fn fetch_by_directory(
         db: &PgPool,
         directory: &Path,
@@ -319,144 +756,1050 @@ impl DbSong {
             "#,
             format!("{}%", directory)
         )
-        .fetch_all(db)
-        .await
-        .map_err(Into::into)
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn fetch_all_paths(db: &PgPool) -> Result<Vec<String>, JudeHarleyError> {
+        let paths = sqlx::query!(
+            r#"
+            SELECT file_path FROM songs
+            "#,
+        )
+        .fetch_all(db)
+        .await?;
+
+        let paths = paths.into_iter().map(|p| p.file_path).collect::<Vec<_>>();
+
+        Ok(paths)
+    }
+
+    pub async fn fetch_all(db: &PgPool) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbSong,
+            r#"
+            SELECT title, artist, album, file_path, duration, file_hash, bitrate
+            FROM songs
+            "#,
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn search(db: &sqlx::PgPool, query: &str) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbSong,
+            r#"
+            WITH search AS (
+                SELECT to_tsquery(string_agg(lexeme || ':*', ' & ' ORDER BY positions)) AS query
+                FROM unnest(to_tsvector($1))
+            )
+            SELECT title, artist, album, file_path, duration, file_hash, bitrate
+            FROM songs, search
+            WHERE tsvector @@ query
+            "#,
+            query
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// A page of songs matching a full text search and/or an exact album and artist, best
+    /// matches first. Also returns the total number of matches, which is 0 past the last page.
+    pub async fn search_paginated(
+        db: &PgPool,
+        query: Option<&str>,
+        album: Option<&str>,
+        artist: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Self>, i64), JudeHarleyError> {
+        let rows = sqlx::query!(
+            r#"
+            WITH search AS (
+                SELECT to_tsquery(string_agg(lexeme || ':*', ' & ' ORDER BY positions)) AS query
+                FROM unnest(to_tsvector($1))
+            )
+            SELECT title, artist, album, file_path, duration, file_hash, bitrate,
+                COUNT(*) OVER () AS "total!"
+            FROM songs, search
+            WHERE ($1::TEXT IS NULL OR tsvector @@ query)
+                AND ($2::TEXT IS NULL OR LOWER(album) = LOWER($2))
+                AND ($3::TEXT IS NULL OR LOWER(artist) = LOWER($3))
+            ORDER BY ts_rank(tsvector, query) DESC NULLS LAST, artist, album, title
+            LIMIT $4 OFFSET $5
+            "#,
+            query,
+            album,
+            artist,
+            limit,
+            offset
+        )
+        .fetch_all(db)
+        .await?;
+
+        let total = rows.first().map(|r| r.total).unwrap_or_default();
+        let songs = rows
+            .into_iter()
+            .map(|r| DbSong {
+                title: r.title,
+                artist: r.artist,
+                album: r.album,
+                file_path: r.file_path,
+                duration: r.duration,
+                file_hash: r.file_hash,
+                bitrate: r.bitrate,
+            })
+            .collect();
+
+        Ok((songs, total))
+    }
+
+    pub async fn last_requested(
+        &self,
+        db: &sqlx::PgPool,
+    ) -> Result<NaiveDateTime, JudeHarleyError> {
+        let last_played = sqlx::query!(
+            r#"
+            SELECT created_at
+            FROM song_requests
+            WHERE song_id = $1 AND status <> 'dropped'
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+            self.file_hash
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to query database: {}", e);
+            e
+        })?;
+
+        let last_played = if let Some(last_played) = last_played {
+            last_played.created_at
+        } else {
+            chrono::NaiveDateTime::from_timestamp_opt(0, 0).unwrap()
+        };
+
+        Ok(last_played)
+    }
+
+    pub async fn is_on_cooldown(
+        &self,
+        db: &PgPool,
+        policy: &DbRequestPolicy,
+    ) -> Result<bool, JudeHarleyError> {
+        Ok(self.cooldown_until(db, policy).await?.is_some())
+    }
+
+    /// When this song can be requested again, if it can't be right now.
+    pub async fn cooldown_until(
+        &self,
+        db: &PgPool,
+        policy: &DbRequestPolicy,
+    ) -> Result<Option<NaiveDateTime>, JudeHarleyError> {
+        let last_played = self.last_requested(db).await?;
+        let over = last_played + policy.song_cooldown(self.duration);
+
+        if over > chrono::Utc::now().naive_utc() {
+            return Ok(Some(over));
+        }
+
+        Ok(None)
+    }
+
+    /// Records a pending request for this song, `request_id` being the ID Liquidsoap gave it
+    /// when it was pushed to `queue`. `boonbucks_paid` is refunded if the request is cancelled,
+    /// and the user cooldown it was made through, if any, is put back.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn request(
+        &self,
+        db: &sqlx::PgPool,
+        author_id: u64,
+        guild_id: Option<i64>,
+        queue: RequestQueue,
+        request_id: u32,
+        boonbucks_paid: i32,
+        bypassed_cooldown_until: Option<NaiveDateTime>,
+    ) -> Result<(), JudeHarleyError> {
+        let mut transaction = db.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO users (id) VALUES ($1)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+            author_id as i64
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO song_requests (song_id, user_id, queue, request_id, boonbucks_paid, bypassed_cooldown_until, guild_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            self.file_hash,
+            author_id as i64,
+            queue.id(),
+            request_id as i32,
+            boonbucks_paid,
+            bypassed_cooldown_until,
+            guild_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to insert song request: {}", e);
+            e
+        })?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn tags(&self, db: &PgPool) -> Result<Vec<(String, String)>, JudeHarleyError> {
+        let tags = sqlx::query!(
+            r#"
+            SELECT tag, value FROM song_tags
+            WHERE song_id = $1
+            "#,
+            self.file_hash
+        )
+        .fetch_all(db)
+        .await?;
+
+        let tags = tags
+            .into_iter()
+            .map(|t| (t.tag, t.value))
+            .collect::<Vec<_>>();
+
+        Ok(tags)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DbPlayedSong {
+    pub id: i32,
+    pub song_id: String,
+    pub played_at: NaiveDateTime,
+    /// `srq`, `prioq`, `autodj` or `playlist`, unknown for songs played before we kept track
+    pub source: Option<String>,
+    pub request_id: Option<i32>,
+}
+
+impl DbPlayedSong {
+    pub async fn insert(
+        db: &PgPool,
+        song_id: &str,
+        source: &str,
+        request_id: Option<i32>,
+    ) -> Result<Self, JudeHarleyError> {
+        sqlx::query_as!(
+            DbPlayedSong,
+            r#"
+            INSERT INTO played_songs (song_id, source, request_id)
+            VALUES ($1, $2, $3)
+            RETURNING id, song_id, played_at, source, request_id
+            "#,
+            song_id,
+            source,
+            request_id
+        )
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn fetch(db: &PgPool, id: i32) -> Result<Option<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbPlayedSong,
+            r#"
+            SELECT id, song_id, played_at, source, request_id
+            FROM played_songs
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Fetches up to `limit` plays before `before`, newest first.
+    pub async fn fetch_history(
+        db: &PgPool,
+        limit: i64,
+        before: Option<NaiveDateTime>,
+    ) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbPlayedSong,
+            r#"
+            SELECT id, song_id, played_at, source, request_id
+            FROM played_songs
+            WHERE $2::timestamp IS NULL OR played_at < $2
+            ORDER BY played_at DESC
+            LIMIT $1
+            "#,
+            limit,
+            before
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+/// Likes and dislikes listeners gave a song.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SongRating {
+    pub likes: i64,
+    pub dislikes: i64,
+}
+
+impl SongRating {
+    /// Likes minus dislikes
+    pub fn score(&self) -> i64 {
+        self.likes - self.dislikes
+    }
+}
+
+/// A listener's like or dislike of a single play of a song.
+#[derive(Debug, Clone)]
+pub struct DbSongVote {
+    pub played_song_id: i32,
+    pub user_id: i64,
+    pub liked: bool,
+    pub created_at: NaiveDateTime,
+}
+
+impl DbSongVote {
+    /// Records the user's vote for the play, replacing the vote they gave it before.
+    pub async fn upsert(
+        db: &PgPool,
+        played_song_id: i32,
+        user_id: i64,
+        liked: bool,
+    ) -> Result<Self, JudeHarleyError> {
+        DbUser::fetch_or_insert(db, user_id).await?;
+
+        sqlx::query_as!(
+            DbSongVote,
+            r#"
+            INSERT INTO song_votes (played_song_id, user_id, liked)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (played_song_id, user_id)
+            DO UPDATE SET liked = $3, created_at = NOW()
+            RETURNING played_song_id, user_id, liked, created_at
+            "#,
+            played_song_id,
+            user_id,
+            liked
+        )
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+/// A file Liquidsoap played that isn't indexed, kept around until Frohike indexes it.
+#[derive(Debug, Clone)]
+pub struct DbUnknownTrack {
+    pub file_path: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub first_played_at: NaiveDateTime,
+    pub last_played_at: NaiveDateTime,
+    pub play_count: i32,
+}
+
+impl DbUnknownTrack {
+    /// Records a play of an unindexed file.
+    pub async fn record(
+        db: &PgPool,
+        file_path: &str,
+        title: &str,
+        artist: &str,
+        album: &str,
+    ) -> Result<Self, JudeHarleyError> {
+        sqlx::query_as!(
+            DbUnknownTrack,
+            r#"
+            INSERT INTO unknown_tracks (file_path, title, artist, album)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (file_path)
+            DO UPDATE SET title = $2, artist = $3, album = $4, last_played_at = NOW(),
+                play_count = unknown_tracks.play_count + 1
+            RETURNING *
+            "#,
+            file_path,
+            title,
+            artist,
+            album
+        )
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Forgets about files once they have been indexed.
+    pub async fn resolve(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        file_paths: &[String],
+    ) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM unknown_tracks
+            WHERE file_path = ANY($1)
+            "#,
+            file_paths
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum ScheduleSlotKind {
+    /// Plays `album` in track order
+    Album,
+    /// Plays songs tagged `tag` = `tag_value` in random order
+    Tag,
+    /// Plays the playlist `playlist_id` in order
+    Playlist,
+}
+
+/// A programming block, queued to `prioq` when it starts.
+#[derive(Debug, Clone)]
+pub struct DbScheduleSlot {
+    pub id: i32,
+    pub name: String,
+    pub kind: ScheduleSlotKind,
+    pub album: Option<String>,
+    pub tag: Option<String>,
+    pub tag_value: Option<String>,
+    pub playlist_id: Option<i32>,
+    /// Looked up from `playlist_id`, ignored when inserting
+    pub playlist_name: Option<String>,
+    /// In UTC, the first occurrence if the slot repeats
+    pub starts_at: NaiveDateTime,
+    /// How many seconds of music to queue
+    pub length: i32,
+    pub repeats_weekly: bool,
+    /// Start of the last occurrence that was queued
+    pub last_played_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl DbScheduleSlot {
+    pub async fn insert(&self, db: &PgPool) -> Result<Self, JudeHarleyError> {
+        sqlx::query_as!(
+            DbScheduleSlot,
+            r#"
+            INSERT INTO schedule (name, kind, album, tag, tag_value, playlist_id, starts_at, length,
+                repeats_weekly)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, name, kind AS "kind: ScheduleSlotKind", album, tag, tag_value, playlist_id,
+                (SELECT name FROM playlists WHERE playlists.id = schedule.playlist_id) AS playlist_name,
+                starts_at, length, repeats_weekly, last_played_at, created_at
+            "#,
+            self.name,
+            self.kind as ScheduleSlotKind,
+            self.album,
+            self.tag,
+            self.tag_value,
+            self.playlist_id,
+            self.starts_at,
+            self.length,
+            self.repeats_weekly
+        )
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn fetch_all(db: &PgPool) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbScheduleSlot,
+            r#"
+            SELECT id, name, kind AS "kind: ScheduleSlotKind", album, tag, tag_value, playlist_id,
+                (SELECT name FROM playlists WHERE playlists.id = schedule.playlist_id) AS playlist_name,
+                starts_at, length, repeats_weekly, last_played_at, created_at
+            FROM schedule
+            ORDER BY starts_at
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Deletes the slot, returning it if it existed.
+    pub async fn delete(db: &PgPool, id: i32) -> Result<Option<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbScheduleSlot,
+            r#"
+            DELETE FROM schedule
+            WHERE id = $1
+            RETURNING id, name, kind AS "kind: ScheduleSlotKind", album, tag, tag_value, playlist_id,
+                (SELECT name FROM playlists WHERE playlists.id = schedule.playlist_id) AS playlist_name,
+                starts_at, length, repeats_weekly, last_played_at, created_at
+            "#,
+            id
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Remembers that the occurrence starting at `occurrence` has been queued.
+    pub async fn mark_played(
+        &self,
+        db: &PgPool,
+        occurrence: NaiveDateTime,
+    ) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            UPDATE schedule
+            SET last_played_at = $2
+            WHERE id = $1
+            "#,
+            self.id,
+            occurrence
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The songs the slot plays, in order. Tag slots are shuffled every time.
+    pub async fn songs(&self, db: &PgPool) -> Result<Vec<DbSong>, JudeHarleyError> {
+        match self.kind {
+            ScheduleSlotKind::Album => match &self.album {
+                Some(album) => DbSong::fetch_album_in_track_order(db, album).await,
+                None => Ok(vec![]),
+            },
+            ScheduleSlotKind::Tag => match (&self.tag, &self.tag_value) {
+                (Some(tag), Some(value)) => DbSong::fetch_by_tag(db, tag, value).await,
+                _ => Ok(vec![]),
+            },
+            ScheduleSlotKind::Playlist => match self.playlist_id {
+                Some(id) => DbPlaylist::songs(db, id).await,
+                None => Ok(vec![]),
+            },
+        }
+    }
+
+    /// What the slot plays, e.g. `the album Homestuck Vol. 5` or `songs with genre = Ambient`
+    pub fn describe(&self) -> String {
+        match self.kind {
+            ScheduleSlotKind::Album => {
+                format!("the album {}", self.album.as_deref().unwrap_or_default())
+            }
+            ScheduleSlotKind::Tag => format!(
+                "songs with {} = {}",
+                self.tag.as_deref().unwrap_or_default(),
+                self.tag_value.as_deref().unwrap_or_default()
+            ),
+            ScheduleSlotKind::Playlist => format!(
+                "the playlist {}",
+                self.playlist_name.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// A curated, ordered list of songs.
+#[derive(Debug, Clone)]
+pub struct DbPlaylist {
+    pub id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// A song in a playlist. Positions start at 1.
+#[derive(Debug, Clone)]
+pub struct DbPlaylistSong {
+    pub position: i32,
+    pub song: DbSong,
+}
+
+impl DbPlaylist {
+    pub async fn create(
+        db: &PgPool,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<Self, JudeHarleyError> {
+        sqlx::query_as!(
+            DbPlaylist,
+            r#"
+            INSERT INTO playlists (name, description)
+            VALUES ($1, $2)
+            RETURNING id, name, description, created_at, updated_at
+            "#,
+            name,
+            description
+        )
+        .fetch_one(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn fetch(db: &PgPool, id: i32) -> Result<Option<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbPlaylist,
+            r#"
+            SELECT id, name, description, created_at, updated_at
+            FROM playlists
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn fetch_by_name(db: &PgPool, name: &str) -> Result<Option<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbPlaylist,
+            r#"
+            SELECT id, name, description, created_at, updated_at
+            FROM playlists
+            WHERE lower(name) = lower($1)
+            "#,
+            name
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Fetches the playlist `name`, creating it if there is none.
+    pub async fn fetch_or_create(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        name: &str,
+    ) -> Result<Self, JudeHarleyError> {
+        let playlist = sqlx::query_as!(
+            DbPlaylist,
+            r#"
+            SELECT id, name, description, created_at, updated_at
+            FROM playlists
+            WHERE lower(name) = lower($1)
+            "#,
+            name
+        )
+        .fetch_optional(&mut **transaction)
+        .await?;
+        if let Some(playlist) = playlist {
+            return Ok(playlist);
+        }
+
+        sqlx::query_as!(
+            DbPlaylist,
+            r#"
+            INSERT INTO playlists (name, description)
+            VALUES ($1, $2)
+            RETURNING id, name, description, created_at, updated_at
+            "#,
+            name,
+            None::<String>
+        )
+        .fetch_one(&mut **transaction)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn fetch_all(db: &PgPool) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbPlaylist,
+            r#"
+            SELECT id, name, description, created_at, updated_at
+            FROM playlists
+            ORDER BY name
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Saves the name and description.
+    pub async fn update(&mut self, db: &PgPool) -> Result<(), JudeHarleyError> {
+        self.updated_at = sqlx::query!(
+            r#"
+            UPDATE playlists
+            SET name = $2, description = $3, updated_at = NOW()
+            WHERE id = $1
+            RETURNING updated_at
+            "#,
+            self.id,
+            self.name,
+            self.description
+        )
+        .fetch_one(db)
+        .await?
+        .updated_at;
+
+        Ok(())
+    }
+
+    /// Deletes the playlist and its songs, returning whether it existed. Schedule slots
+    /// playing it are deleted too.
+    pub async fn delete(db: &PgPool, id: i32) -> Result<bool, JudeHarleyError> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM playlists
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The playlist's songs with their positions, in order. Songs that aren't indexed anymore
+    /// are left out.
+    pub async fn entries(db: &PgPool, id: i32) -> Result<Vec<DbPlaylistSong>, JudeHarleyError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT playlist_songs.position, songs.title, songs.artist, songs.album,
+                songs.file_path, songs.duration, songs.file_hash, songs.bitrate
+            FROM playlist_songs
+            JOIN songs ON songs.file_hash = playlist_songs.song_id
+            WHERE playlist_songs.playlist_id = $1
+            ORDER BY playlist_songs.position
+            "#,
+            id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| DbPlaylistSong {
+                position: row.position,
+                song: DbSong {
+                    title: row.title,
+                    artist: row.artist,
+                    album: row.album,
+                    file_path: row.file_path,
+                    duration: row.duration,
+                    file_hash: row.file_hash,
+                    bitrate: row.bitrate,
+                },
+            })
+            .collect())
+    }
+
+    /// The playlist's songs, in order.
+    pub async fn songs(db: &PgPool, id: i32) -> Result<Vec<DbSong>, JudeHarleyError> {
+        Ok(Self::entries(db, id)
+            .await?
+            .into_iter()
+            .map(|entry| entry.song)
+            .collect())
+    }
+
+    /// Appends a song and returns its position.
+    pub async fn add_song(&self, db: &PgPool, song_id: &str) -> Result<i32, JudeHarleyError> {
+        let mut transaction = db.begin().await?;
+        let position = sqlx::query!(
+            r#"
+            INSERT INTO playlist_songs (playlist_id, position, song_id)
+            SELECT $1, COALESCE(MAX(position), 0) + 1, $2
+            FROM playlist_songs
+            WHERE playlist_id = $1
+            RETURNING position
+            "#,
+            self.id,
+            song_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?
+        .position;
+        Self::touch(&mut transaction, self.id).await?;
+        transaction.commit().await?;
+
+        Ok(position)
+    }
+
+    /// Removes the song at `position`, moving the songs after it up. Returns whether there was a
+    /// song at that position.
+    pub async fn remove_song(&self, db: &PgPool, position: i32) -> Result<bool, JudeHarleyError> {
+        let mut transaction = db.begin().await?;
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM playlist_songs
+            WHERE playlist_id = $1 AND position = $2
+            "#,
+            self.id,
+            position
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE playlist_songs
+            SET position = position - 1
+            WHERE playlist_id = $1 AND position > $2
+            "#,
+            self.id,
+            position
+        )
+        .execute(&mut *transaction)
+        .await?;
+        Self::touch(&mut transaction, self.id).await?;
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Replaces all songs of the playlist with `song_ids`, in that order.
+    pub async fn replace_songs(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        song_ids: &[String],
+    ) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM playlist_songs
+            WHERE playlist_id = $1
+            "#,
+            self.id
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO playlist_songs (playlist_id, position, song_id)
+            SELECT $1, songs.position::int, songs.song_id
+            FROM unnest($2::varchar[]) WITH ORDINALITY AS songs(song_id, position)
+            "#,
+            self.id,
+            song_ids
+        )
+        .execute(&mut **transaction)
+        .await?;
+        Self::touch(transaction, self.id).await?;
+
+        Ok(())
+    }
+
+    async fn touch(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        id: i32,
+    ) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            UPDATE playlists
+            SET updated_at = NOW()
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        Ok(())
     }
+}
 
-    pub async fn fetch_all_paths(db: &PgPool) -> Result<Vec<String>, JudeHarleyError> {
-        let paths = sqlx::query!(
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum SongRequestStatus {
+    /// Still waiting in Liquidsoap's queue
+    Pending,
+    /// Made it to air
+    Fulfilled,
+    /// Cancelled, skipped or lost when Liquidsoap restarted
+    Dropped,
+}
+
+#[derive(Debug, Clone)]
+pub struct DbSongRequest {
+    pub id: i32,
+    pub song_id: String,
+    pub user_id: i64,
+    pub created_at: NaiveDateTime,
+    pub request_id: Option<i32>,
+    pub queue: Option<String>,
+    pub status: SongRequestStatus,
+    pub played_song_id: Option<i32>,
+    /// Boondollars the user paid for the request
+    pub boonbucks_paid: i32,
+    /// When the user cooldown the request skipped would have run out
+    pub bypassed_cooldown_until: Option<NaiveDateTime>,
+    /// Discord guild the request was made in, `None` for requests made through the web API
+    pub guild_id: Option<i64>,
+}
+
+/// How long requests count as pending. Liquidsoap starts counting request IDs from zero when
+/// it restarts, so older requests can't be told apart from newer ones with the same ID.
+const PENDING_REQUEST_HOURS: i64 = 24;
+
+/// When the oldest request that can still be pending was made.
+fn pending_since() -> NaiveDateTime {
+    chrono::Utc::now().naive_utc() - chrono::Duration::hours(PENDING_REQUEST_HOURS)
+}
+
+impl DbSongRequest {
+    /// Counts the user's requests that are still waiting in a queue.
+    pub async fn count_pending(db: &PgPool, user_id: i64) -> Result<i64, JudeHarleyError> {
+        let count = sqlx::query!(
             r#"
-            SELECT file_path FROM songs
+            SELECT COUNT(*) AS "count!" FROM song_requests
+            WHERE user_id = $1 AND status = 'pending' AND created_at > $2
             "#,
+            user_id,
+            pending_since()
         )
-        .fetch_all(db)
+        .fetch_one(db)
         .await?;
 
-        let paths = paths.into_iter().map(|p| p.file_path).collect::<Vec<_>>();
-
-        Ok(paths)
+        Ok(count.count)
     }
 
-    pub async fn fetch_all(db: &PgPool) -> Result<Vec<Self>, JudeHarleyError> {
+    /// Fetches the pending requests with the given Liquidsoap request IDs, newest first.
+    ///
+    /// Requests older than [`PENDING_REQUEST_HOURS`] aren't considered.
+    pub async fn fetch_by_request_ids(
+        db: &PgPool,
+        request_ids: &[i32],
+    ) -> Result<Vec<Self>, JudeHarleyError> {
         sqlx::query_as!(
-            DbSong,
+            DbSongRequest,
             r#"
-            SELECT title, artist, album, file_path, duration, file_hash, bitrate
-            FROM songs
+            SELECT id, song_id, user_id, created_at, request_id, queue, status AS "status: SongRequestStatus", played_song_id,
+                boonbucks_paid, bypassed_cooldown_until, guild_id
+            FROM song_requests
+            WHERE request_id = ANY($1) AND status = 'pending' AND created_at > $2
+            ORDER BY created_at DESC
             "#,
+            request_ids,
+            pending_since()
         )
         .fetch_all(db)
         .await
         .map_err(Into::into)
     }
 
-    pub async fn search(db: &sqlx::PgPool, query: &str) -> Result<Vec<Self>, JudeHarleyError> {
+    /// Marks the pending request that Liquidsoap knew as `request_id` in `queue` as played.
+    ///
+    /// Returns `None` if the track wasn't requested through us.
+    pub async fn fulfil(
+        db: &PgPool,
+        queue: &str,
+        request_id: i32,
+        played_song: &DbPlayedSong,
+    ) -> Result<Option<Self>, JudeHarleyError> {
         sqlx::query_as!(
-            DbSong,
+            DbSongRequest,
             r#"
-            WITH search AS (
-                SELECT to_tsquery(string_agg(lexeme || ':*', ' & ' ORDER BY positions)) AS query
-                FROM unnest(to_tsvector($1))
+            UPDATE song_requests
+            SET status = 'fulfilled', played_song_id = $4
+            WHERE id = (
+                SELECT id FROM song_requests
+                WHERE status = 'pending' AND queue = $1 AND request_id = $2 AND song_id = $3
+                    AND created_at > $5
+                ORDER BY created_at DESC
+                LIMIT 1
             )
-            SELECT title, artist, album, file_path, duration, file_hash, bitrate
-            FROM songs, search
-            WHERE tsvector @@ query
+            RETURNING id, song_id, user_id, created_at, request_id, queue, status AS "status: SongRequestStatus", played_song_id,
+                boonbucks_paid, bypassed_cooldown_until, guild_id
             "#,
-            query
+            queue,
+            request_id,
+            played_song.song_id,
+            played_song.id,
+            pending_since()
         )
-        .fetch_all(db)
+        .fetch_optional(db)
         .await
         .map_err(Into::into)
     }
 
-    pub async fn last_requested(
-        &self,
-        db: &sqlx::PgPool,
-    ) -> Result<NaiveDateTime, JudeHarleyError> {
-        let last_played = sqlx::query!(
+    /// Marks requests as dropped that can't be played anymore: pending requests in `queue` that
+    /// were made before `before`, since queues are played in order, and anything too old to be
+    /// pending.
+    pub async fn drop_stale(
+        db: &PgPool,
+        queue: &str,
+        before: NaiveDateTime,
+    ) -> Result<u64, JudeHarleyError> {
+        let result = sqlx::query!(
             r#"
-            SELECT created_at
-            FROM song_requests
-            WHERE song_id = $1
-            ORDER BY created_at DESC
-            LIMIT 1
+            UPDATE song_requests
+            SET status = 'dropped'
+            WHERE status = 'pending'
+                AND ((queue = $1 AND created_at < $2) OR created_at < $3)
             "#,
-            self.file_hash
+            queue,
+            before,
+            pending_since()
         )
-        .fetch_optional(db)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to query database: {}", e);
-            e
-        })?;
-
-        let last_played = if let Some(last_played) = last_played {
-            last_played.created_at
-        } else {
-            chrono::NaiveDateTime::from_timestamp_opt(0, 0).unwrap()
-        };
-
-        Ok(last_played)
-    }
-
-    pub async fn is_on_cooldown(&self, db: &PgPool) -> Result<bool, JudeHarleyError> {
-        let last_played = self.last_requested(db).await?;
-        let cooldown_time = if self.duration < 300.0 {
-            chrono::Duration::seconds(1800)
-        } else if self.duration < 600.0 {
-            chrono::Duration::seconds(3600)
-        } else {
-            chrono::Duration::seconds(5413)
-        };
-
-        let over = last_played + cooldown_time;
-
-        if over > chrono::Utc::now().naive_utc() {
-            return Ok(true);
-        }
+        .execute(db)
+        .await?;
 
-        Ok(false)
+        Ok(result.rows_affected())
     }
 
-    pub async fn request(&self, db: &sqlx::PgPool, author_id: u64) -> Result<(), JudeHarleyError> {
-        DbUser::fetch_or_insert(db, author_id as i64).await?;
+    /// Marks the request as dropped, which also lifts the song's request cooldown, and refunds
+    /// what the user paid for it.
+    ///
+    /// Returns `false` if the request wasn't pending anymore.
+    pub async fn cancel(&self, db: &PgPool) -> Result<bool, JudeHarleyError> {
+        let mut transaction = db.begin().await?;
 
-        sqlx::query!(
+        let dropped = sqlx::query!(
             r#"
-            INSERT INTO song_requests (song_id, user_id)
-            VALUES ($1, $2)
+            UPDATE song_requests
+            SET status = 'dropped'
+            WHERE id = $1 AND status = 'pending'
+            RETURNING boonbucks_paid
             "#,
-            self.file_hash,
-            author_id as i64
+            self.id
         )
-        .execute(db)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to insert song request: {}", e);
-            e
-        })?;
-
-        Ok(())
-    }
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(dropped) = dropped else {
+            return Ok(false);
+        };
 
-    pub async fn tags(&self, db: &PgPool) -> Result<Vec<(String, String)>, JudeHarleyError> {
-        let tags = sqlx::query!(
+        sqlx::query!(
             r#"
-            SELECT tag, value FROM song_tags
-            WHERE song_id = $1
+            UPDATE users
+            SET boonbucks = boonbucks + $2
+            WHERE id = $1
             "#,
-            self.file_hash
+            self.user_id,
+            dropped.boonbucks_paid
         )
-        .fetch_all(db)
+        .execute(&mut *transaction)
         .await?;
 
-        let tags = tags
-            .into_iter()
-            .map(|t| (t.tag, t.value))
-            .collect::<Vec<_>>();
+        transaction.commit().await?;
 
-        Ok(tags)
+        Ok(true)
     }
 }
 
@@ -599,6 +1942,51 @@ impl DbUser {
         Ok(())
     }
 
+    /// Takes `amount` Boondollars from the user in a single statement, so two purchases at
+    /// once can't take them below zero. Returns the new balance, or `None` if the user can't
+    /// afford it.
+    pub async fn spend_boonbucks(
+        db: &PgPool,
+        id: i64,
+        amount: i32,
+    ) -> Result<Option<i32>, JudeHarleyError> {
+        let balance = sqlx::query!(
+            r#"
+            UPDATE users
+            SET boonbucks = boonbucks - $2
+            WHERE id = $1 AND boonbucks >= $2
+            RETURNING boonbucks
+            "#,
+            id,
+            amount
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(balance.map(|b| b.boonbucks))
+    }
+
+    /// Gives back Boondollars taken by [`DbUser::spend_boonbucks`].
+    pub async fn refund_boonbucks(
+        db: &PgPool,
+        id: i64,
+        amount: i32,
+    ) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET boonbucks = boonbucks + $2
+            WHERE id = $1
+            "#,
+            id,
+            amount
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn fetch_position_in_hours(&self, db: &sqlx::PgPool) -> Result<i64, JudeHarleyError> {
         let position = sqlx::query!(
             r#"
@@ -857,6 +2245,65 @@ impl DbSlcbUser {
         .await
         .map_err(Into::into)
     }
+
+    pub async fn fetch_all(db: &PgPool) -> Result<Vec<Self>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbSlcbUser,
+            r#"
+            SELECT * FROM slcb_currency
+            ORDER BY id ASC
+            "#,
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn insert(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        username: &str,
+        points: i32,
+        hours: i32,
+        user_id: Option<&str>,
+    ) -> Result<Self, JudeHarleyError> {
+        sqlx::query_as!(
+            DbSlcbUser,
+            r#"
+            INSERT INTO slcb_currency (username, points, hours, user_id)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, username, points, hours, user_id
+            "#,
+            username,
+            points,
+            hours,
+            user_id
+        )
+        .fetch_one(&mut **transaction)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            UPDATE slcb_currency
+            SET username = $2, points = $3, hours = $4, user_id = $5
+            WHERE id = $1
+            "#,
+            self.id,
+            self.username,
+            self.points,
+            self.hours,
+            self.user_id
+        )
+        .execute(&mut **transaction)
+        .await?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -871,7 +2318,7 @@ impl DbServerConfig {
         let config = sqlx::query_as!(
             DbServerConfig,
             r#"
-            SELECT * FROM server_config
+            SELECT id, slot_jackpot, dice_roll FROM server_config
             WHERE id = $1
             "#,
             id
@@ -918,6 +2365,126 @@ impl DbServerConfig {
     }
 }
 
+/// A guild's rules for song requests, stored with its server config. All times are in seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbRequestPolicy {
+    pub requests_open: bool,
+    pub user_cooldown: i32,
+    /// Cooldown of songs shorter than 5 minutes
+    pub short_song_cooldown: i32,
+    /// Cooldown of songs shorter than 10 minutes
+    pub medium_song_cooldown: i32,
+    pub long_song_cooldown: i32,
+    /// How many requests a user may have waiting in the queue
+    pub max_pending: Option<i32>,
+    /// How long the song request queue may get
+    pub max_queue_length: Option<i32>,
+    /// Boondollars a song request costs
+    pub price: i32,
+    /// Boondollars it costs to request a song while on cooldown, not possible if `None`
+    pub cooldown_bypass_price: Option<i32>,
+    /// Boondollars it costs to request a song into the priority queue, not possible if `None`
+    pub premium_price: Option<i32>,
+}
+
+impl Default for DbRequestPolicy {
+    fn default() -> Self {
+        Self {
+            requests_open: true,
+            user_cooldown: 5400,
+            short_song_cooldown: 1800,
+            medium_song_cooldown: 3600,
+            long_song_cooldown: 5413,
+            max_pending: None,
+            max_queue_length: None,
+            price: 0,
+            cooldown_bypass_price: None,
+            premium_price: None,
+        }
+    }
+}
+
+impl DbRequestPolicy {
+    /// Fetches the guild's policy, or the default one if it never changed it.
+    pub async fn fetch(db: &PgPool, server_id: i64) -> Result<Self, JudeHarleyError> {
+        let policy = sqlx::query_as!(
+            DbRequestPolicy,
+            r#"
+            SELECT requests_open,
+                request_user_cooldown AS user_cooldown,
+                request_short_song_cooldown AS short_song_cooldown,
+                request_medium_song_cooldown AS medium_song_cooldown,
+                request_long_song_cooldown AS long_song_cooldown,
+                request_max_pending AS max_pending,
+                request_max_queue_length AS max_queue_length,
+                request_price AS price,
+                request_cooldown_bypass_price AS cooldown_bypass_price,
+                request_premium_price AS premium_price
+            FROM server_config
+            WHERE id = $1
+            "#,
+            server_id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(policy.unwrap_or_default())
+    }
+
+    /// Fetches the policy of the guild, if there is one.
+    pub async fn fetch_for(db: &PgPool, server_id: Option<i64>) -> Result<Self, JudeHarleyError> {
+        match server_id {
+            Some(server_id) => Self::fetch(db, server_id).await,
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub async fn update(&self, db: &PgPool, server_id: i64) -> Result<(), JudeHarleyError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO server_config (id, requests_open, request_user_cooldown, request_short_song_cooldown,
+                request_medium_song_cooldown, request_long_song_cooldown, request_max_pending, request_max_queue_length,
+                request_price, request_cooldown_bypass_price, request_premium_price)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (id)
+            DO UPDATE SET requests_open = $2, request_user_cooldown = $3, request_short_song_cooldown = $4,
+                request_medium_song_cooldown = $5, request_long_song_cooldown = $6, request_max_pending = $7,
+                request_max_queue_length = $8, request_price = $9, request_cooldown_bypass_price = $10,
+                request_premium_price = $11
+            "#,
+            server_id,
+            self.requests_open,
+            self.user_cooldown,
+            self.short_song_cooldown,
+            self.medium_song_cooldown,
+            self.long_song_cooldown,
+            self.max_pending,
+            self.max_queue_length,
+            self.price,
+            self.cooldown_bypass_price,
+            self.premium_price
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// How long a song can't be requested again after it was requested. Longer songs wait
+    /// longer, so they don't take over the stream.
+    pub fn song_cooldown(&self, duration: f64) -> chrono::Duration {
+        let seconds = if duration < 300.0 {
+            self.short_song_cooldown
+        } else if duration < 600.0 {
+            self.medium_song_cooldown
+        } else {
+            self.long_song_cooldown
+        };
+
+        chrono::Duration::seconds(seconds as i64)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DbServerRoleConfig {
     pub id: i32,
@@ -1074,6 +2641,10 @@ pub struct DbServerChannelConfig {
     pub allow_watch_time_accumulation: bool,
     pub allow_point_accumulation: bool,
     pub hydration_reminder: bool,
+    /// Announce song requests here when they start playing
+    pub now_playing_announcements: bool,
+    /// Mention the requester in the announcement
+    pub ping_requester: bool,
 }
 
 impl DbServerChannelConfig {
@@ -1112,7 +2683,7 @@ impl DbServerChannelConfig {
             VALUES ($1, $2)
             ON CONFLICT (id)
             DO NOTHING
-            RETURNING id, server_id, allow_watch_time_accumulation, allow_point_accumulation, hydration_reminder
+            RETURNING id, server_id, allow_watch_time_accumulation, allow_point_accumulation, hydration_reminder, now_playing_announcements, ping_requester
             "#,
             channel_id,
             server_id
@@ -1127,13 +2698,16 @@ impl DbServerChannelConfig {
         sqlx::query!(
             r#"
             UPDATE server_channel_config
-            SET allow_watch_time_accumulation = $2, allow_point_accumulation = $3, hydration_reminder = $4
+            SET allow_watch_time_accumulation = $2, allow_point_accumulation = $3, hydration_reminder = $4,
+                now_playing_announcements = $5, ping_requester = $6
             WHERE id = $1
             "#,
             self.id,
             self.allow_watch_time_accumulation,
             self.allow_point_accumulation,
-            self.hydration_reminder
+            self.hydration_reminder,
+            self.now_playing_announcements,
+            self.ping_requester
         )
         .execute(db)
         .await?;
@@ -1155,6 +2729,63 @@ impl DbServerChannelConfig {
         .await
         .map_err(Into::into)
     }
+
+    pub async fn fetch_announcement_channels(
+        db: &PgPool,
+    ) -> Result<Vec<DbServerChannelConfig>, JudeHarleyError> {
+        sqlx::query_as!(
+            DbServerChannelConfig,
+            r#"
+            SELECT * FROM server_channel_config
+            WHERE now_playing_announcements = true
+            "#,
+        )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Makes `channel_id` the only announcement channel of the server, or turns announcements
+    /// off for the server if it's `None`.
+    pub async fn set_announcement_channel(
+        db: &PgPool,
+        server_id: i64,
+        channel_id: Option<i64>,
+        ping_requester: bool,
+    ) -> Result<(), JudeHarleyError> {
+        let mut transaction = db.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE server_channel_config
+            SET now_playing_announcements = false, ping_requester = false
+            WHERE server_id = $1
+            "#,
+            server_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        if let Some(channel_id) = channel_id {
+            sqlx::query!(
+                r#"
+                INSERT INTO server_channel_config (id, server_id, now_playing_announcements, ping_requester)
+                VALUES ($1, $2, true, $3)
+                ON CONFLICT (id)
+                DO UPDATE SET now_playing_announcements = true, ping_requester = $3
+                "#,
+                channel_id,
+                server_id,
+                ping_requester
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,208 @@
+//! Events published on Redis by Langley and picked up by Byers and anybody else interested.
+//!
+//! Every event is sent as a JSON object with a `version` field next to the event's own fields.
+//! Adding fields doesn't need a new version, removing or changing them does.
+
+use chrono::{DateTime, TimeZone, Utc};
+use fred::{pool::RedisPool, prelude::PubsubInterface};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    communication::RequestQueue,
+    db::{DbPlayedSong, DbSong, DbSongRequest},
+    prelude::*,
+};
+
+/// Version of the event schema published by this build.
+///
+/// 2: `song_id` and `duration` of [`TrackStarted`] are `null` for tracks that aren't indexed
+pub const SCHEMA_VERSION: u32 = 2;
+
+pub trait Event: Serialize + DeserializeOwned {
+    /// Redis channel the event is published on
+    const CHANNEL: &'static str;
+}
+
+#[derive(Serialize)]
+struct VersionedRef<'a, E> {
+    version: u32,
+    #[serde(flatten)]
+    event: &'a E,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+/// Serializes the event with the current schema version.
+pub fn to_json<E: Event>(event: &E) -> Result<String> {
+    serde_json::to_string(&VersionedRef {
+        version: SCHEMA_VERSION,
+        event,
+    })
+    .map_err(Into::into)
+}
+
+/// Parses an event, failing if it was published with a schema version we don't know.
+pub fn from_json<E: Event>(payload: &str) -> Result<E> {
+    let Version { version } = serde_json::from_str(payload)?;
+    if version != SCHEMA_VERSION {
+        return Err(JudeHarleyError::UnsupportedEventVersion(version));
+    }
+
+    serde_json::from_str(payload).map_err(Into::into)
+}
+
+pub async fn publish<E: Event>(redis_pool: &RedisPool, event: &E) -> Result<()> {
+    redis_pool
+        .publish::<i32, _, _>(E::CHANNEL, to_json(event)?)
+        .await?;
+
+    Ok(())
+}
+
+/// Published whenever a new track starts playing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackStarted {
+    /// Hash of the song, as in `songs.file_hash`, `None` if the file isn't indexed
+    pub song_id: Option<String>,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    /// Duration in seconds, `None` if the file isn't indexed
+    pub duration: Option<f64>,
+    pub started_at: DateTime<Utc>,
+    /// `srq`, `prioq`, `autodj` or `playlist`
+    pub source: String,
+    /// ID of the play in `played_songs`, `None` if the file isn't indexed
+    pub played_song_id: Option<i32>,
+}
+
+impl Event for TrackStarted {
+    const CHANNEL: &'static str = "byers:status";
+}
+
+impl TrackStarted {
+    pub fn new(song: &DbSong, played_song: &DbPlayedSong) -> Self {
+        Self {
+            song_id: Some(song.file_hash.clone()),
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            duration: Some(song.duration),
+            started_at: Utc.from_utc_datetime(&played_song.played_at),
+            source: played_song
+                .source
+                .clone()
+                .unwrap_or_else(|| "playlist".to_string()),
+            played_song_id: Some(played_song.id),
+        }
+    }
+
+    /// For tracks we only know the metadata Liquidsoap sent us of.
+    pub fn unindexed(title: &str, artist: &str, album: &str, source: &str) -> Self {
+        Self {
+            song_id: None,
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: album.to_string(),
+            duration: None,
+            started_at: Utc::now(),
+            source: source.to_string(),
+            played_song_id: None,
+        }
+    }
+}
+
+/// Published when a song that somebody requested starts playing, right after its
+/// [`TrackStarted`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SongRequestStarted {
+    /// Hash of the song, as in `songs.file_hash`
+    pub song_id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    /// `srq` or `prioq`
+    pub queue: String,
+    /// Discord ID of the user who requested the song
+    pub requested_by: i64,
+    /// ID of the play in `played_songs`
+    pub played_song_id: Option<i32>,
+    /// Discord guild the song was requested in, `None` if it was requested through the web
+    pub guild_id: Option<i64>,
+}
+
+impl Event for SongRequestStarted {
+    const CHANNEL: &'static str = "byers:song_request_started";
+}
+
+impl SongRequestStarted {
+    pub fn new(song: &DbSong, queue: RequestQueue, request: &DbSongRequest) -> Self {
+        Self {
+            song_id: song.file_hash.clone(),
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            queue: queue.id().to_string(),
+            requested_by: request.user_id,
+            played_song_id: request.played_song_id,
+            guild_id: request.guild_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> TrackStarted {
+        TrackStarted {
+            song_id: Some("abc".to_string()),
+            title: "Showtime".to_string(),
+            artist: "Toby Fox".to_string(),
+            album: "Homestuck Vol. 1".to_string(),
+            duration: Some(92.5),
+            started_at: Utc.with_ymd_and_hms(2023, 11, 22, 18, 0, 0).unwrap(),
+            source: "playlist".to_string(),
+            played_song_id: Some(1),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let payload = to_json(&track()).unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(value["version"], SCHEMA_VERSION);
+        assert_eq!(value["song_id"], "abc");
+        assert_eq!(from_json::<TrackStarted>(&payload).unwrap(), track());
+    }
+
+    #[test]
+    fn test_rejects_unknown_versions() {
+        let payload = to_json(&track()).unwrap().replace(
+            &format!(r#""version":{}"#, SCHEMA_VERSION),
+            r#""version":1"#,
+        );
+
+        assert!(matches!(
+            from_json::<TrackStarted>(&payload),
+            Err(JudeHarleyError::UnsupportedEventVersion(1))
+        ));
+        assert!(from_json::<TrackStarted>("Album - Artist - Title").is_err());
+    }
+
+    #[test]
+    fn test_ignores_unknown_fields() {
+        let mut value = serde_json::to_value(track()).unwrap();
+        value["version"] = SCHEMA_VERSION.into();
+        value["bpm"] = 120.into();
+
+        assert_eq!(
+            from_json::<TrackStarted>(&value.to_string()).unwrap(),
+            track()
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! The `/healthz` and `/readyz` endpoints of our web servers, and where they listen.
+
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{extract::State, http::StatusCode, Json};
+use fred::{pool::RedisPool, prelude::ClientLike};
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// How long a single dependency may take to answer before it counts as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Readiness {
+    pub postgres: bool,
+    pub redis: bool,
+}
+
+impl Readiness {
+    pub async fn check(db: &PgPool, redis_pool: &RedisPool) -> Self {
+        let postgres = tokio::time::timeout(CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(db));
+        let redis = tokio::time::timeout(CHECK_TIMEOUT, redis_pool.next().ping::<()>());
+        let (postgres, redis) = tokio::join!(postgres, redis);
+
+        Self {
+            postgres: matches!(postgres, Ok(Ok(_))),
+            redis: matches!(redis, Ok(Ok(_))),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.postgres && self.redis
+    }
+}
+
+/// Where our web servers listen unless `BIND_ADDRESS` says otherwise
+pub fn default_bind_address() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8000))
+}
+
+/// `GET /healthz`, the process is up
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// `GET /readyz`, Postgres and Redis are reachable
+pub async fn readyz(
+    State(db): State<PgPool>,
+    State(redis): State<RedisPool>,
+) -> (StatusCode, Json<Readiness>) {
+    let readiness = Readiness::check(&db, &redis).await;
+    let status = if readiness.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
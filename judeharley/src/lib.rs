@@ -8,11 +8,17 @@ use sqlx::postgres::PgPoolOptions;
 pub use crate::prelude::*;
 pub use sqlx::{types::BigDecimal, PgPool};
 
+pub mod autodj;
 pub mod communication;
 pub mod cooldowns;
 pub mod db;
 pub mod discord;
+pub mod events;
+pub mod health;
+pub mod metrics;
 pub mod prelude;
+pub mod requests;
+pub mod schedule;
 
 pub mod maintenance;
 
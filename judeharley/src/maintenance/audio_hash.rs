@@ -0,0 +1,344 @@
+//! Hashes of the audio in a file, leaving out its tags, so songs keep their identity when they
+//! are moved, renamed or retagged.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::prelude::*;
+
+/// The SHA-256 of the audio in the file at `path`, as hex. Only the headers are read to find
+/// the audio, which is then streamed into the hasher.
+pub fn hash_audio(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let ranges = audio_ranges(&extension, &mut file)?;
+    Ok(hash_ranges(&mut file, &ranges)?)
+}
+
+fn hash_ranges<R: Read + Seek>(reader: &mut R, ranges: &[Range<u64>]) -> io::Result<String> {
+    let mut hasher: Sha256 = Digest::new();
+    let mut buffer = vec![0; 64 * 1024];
+    for range in ranges {
+        reader.seek(SeekFrom::Start(range.start))?;
+        let mut left = range.end - range.start;
+        while left > 0 {
+            let size = left.min(buffer.len() as u64) as usize;
+            let chunk = &mut buffer[..size];
+            reader.read_exact(chunk)?;
+            hasher.update(&*chunk);
+            left -= chunk.len() as u64;
+        }
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The parts of the file that hold audio. Files we can't make sense of are hashed whole, minus
+/// any ID3 and APE tags.
+fn audio_ranges<R: Read + Seek>(extension: &str, reader: &mut R) -> io::Result<Vec<Range<u64>>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let range = strip_tags(reader, 0..len)?;
+    let parts = match extension {
+        "flac" => flac_frames(reader, range.clone())?.map(|frames| vec![frames]),
+        "wav" => wav_chunks(reader, range.clone())?,
+        "ogg" => ogg_audio_pages(reader, range.clone())?,
+        _ => None,
+    };
+
+    Ok(parts.unwrap_or_else(|| vec![range]))
+}
+
+/// Reads `buf.len()` bytes at `offset`, which the caller made sure are in the file.
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(buf)
+}
+
+fn u32_le(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64
+}
+
+/// Strips ID3v2 tags from the start and ID3v1 and APEv2 tags from the end.
+fn strip_tags<R: Read + Seek>(reader: &mut R, range: Range<u64>) -> io::Result<Range<u64>> {
+    let Range { mut start, mut end } = range;
+    let mut header = [0; 10];
+    while end - start >= 10 {
+        read_at(reader, start, &mut header)?;
+        if !header.starts_with(b"ID3") {
+            break;
+        }
+
+        // the size is syncsafe, only the lower 7 bits of each byte count
+        let size = header[6..10]
+            .iter()
+            .fold(0, |size, byte| (size << 7) | (*byte as u64 & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        let next = start + 10 + size + footer;
+        if next > end {
+            break;
+        }
+        start = next;
+    }
+
+    let mut footer = [0; 32];
+    loop {
+        let len = end - start;
+        if len >= 128 {
+            read_at(reader, end - 128, &mut footer[..3])?;
+            if footer.starts_with(b"TAG") {
+                end -= 128;
+                continue;
+            }
+        }
+        if len >= 32 {
+            read_at(reader, end - 32, &mut footer)?;
+            if footer.starts_with(b"APETAGEX") {
+                // the size includes the footer but not the header
+                let has_header = u32_le(&footer[20..24]) & 0x8000_0000 != 0;
+                let size = u32_le(&footer[12..16]) + if has_header { 32 } else { 0 };
+                if size > len {
+                    break;
+                }
+                end -= size;
+                continue;
+            }
+        }
+
+        break;
+    }
+
+    Ok(start..end)
+}
+
+/// Skips the metadata blocks, which hold the Vorbis comments and pictures.
+fn flac_frames<R: Read + Seek>(
+    reader: &mut R,
+    range: Range<u64>,
+) -> io::Result<Option<Range<u64>>> {
+    let mut marker = [0; 4];
+    if range.end - range.start < 4 {
+        return Ok(None);
+    }
+    read_at(reader, range.start, &mut marker)?;
+    if &marker != b"fLaC" {
+        return Ok(None);
+    }
+
+    let mut pos = range.start + 4;
+    let mut header = [0; 4];
+    loop {
+        if range.end - pos < 4 {
+            return Ok(None);
+        }
+        read_at(reader, pos, &mut header)?;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        pos += 4 + length;
+        if pos > range.end {
+            return Ok(None);
+        }
+
+        if header[0] & 0x80 != 0 {
+            return Ok(Some(pos..range.end));
+        }
+    }
+}
+
+/// The format and sample data, leaving out `LIST`, `id3 ` and any other chunks.
+fn wav_chunks<R: Read + Seek>(
+    reader: &mut R,
+    range: Range<u64>,
+) -> io::Result<Option<Vec<Range<u64>>>> {
+    let mut header = [0; 12];
+    if range.end - range.start < 12 {
+        return Ok(None);
+    }
+    read_at(reader, range.start, &mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+
+    let mut parts = vec![];
+    let mut pos = range.start + 12;
+    let mut chunk = [0; 8];
+    while range.end - pos >= 8 {
+        read_at(reader, pos, &mut chunk)?;
+        let (id, size) = (&chunk[..4], u32_le(&chunk[4..8]));
+        let data = pos + 8..pos + 8 + size;
+        if data.end > range.end {
+            break;
+        }
+        if id == b"fmt " || id == b"data" {
+            parts.push(data);
+        }
+        // chunks are padded to an even size
+        pos = (pos + 8 + size + size % 2).min(range.end);
+    }
+
+    Ok((!parts.is_empty()).then_some(parts))
+}
+
+/// The contents of the pages after the headers. Page headers are left out too, their sequence
+/// numbers change when the comment header grows or shrinks.
+fn ogg_audio_pages<R: Read + Seek>(
+    reader: &mut R,
+    range: Range<u64>,
+) -> io::Result<Option<Vec<Range<u64>>>> {
+    let mut parts = vec![];
+    let mut pos = range.start;
+    let mut header = [0; 27];
+    let mut segment_sizes = [0; 255];
+    while range.end - pos >= 4 {
+        read_at(reader, pos, &mut header[..4])?;
+        if !header.starts_with(b"OggS") {
+            break;
+        }
+        if range.end - pos < 27 {
+            return Ok(None);
+        }
+        read_at(reader, pos, &mut header)?;
+
+        let granule_position = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        let segments = header[26] as usize;
+        let body_start = pos + 27 + segments as u64;
+        if body_start > range.end {
+            return Ok(None);
+        }
+        read_at(reader, pos + 27, &mut segment_sizes[..segments])?;
+        let body_size = segment_sizes[..segments]
+            .iter()
+            .map(|size| *size as u64)
+            .sum::<u64>();
+        let body = body_start..body_start + body_size;
+        if body.end > range.end {
+            return Ok(None);
+        }
+
+        // header pages have no position, or none at all if a packet continues on the next page
+        if !parts.is_empty() || (granule_position != 0 && granule_position != u64::MAX) {
+            parts.push(body.clone());
+        }
+        pos = body.end;
+    }
+
+    Ok((!parts.is_empty()).then_some(parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn hash(extension: &str, bytes: &[u8]) -> String {
+        let mut reader = Cursor::new(bytes);
+        let ranges = audio_ranges(extension, &mut reader).unwrap();
+        hash_ranges(&mut reader, &ranges).unwrap()
+    }
+
+    /// The parts of `bytes` in `ranges`.
+    fn parts<'a>(bytes: &'a [u8], ranges: &[Range<u64>]) -> Vec<&'a [u8]> {
+        ranges
+            .iter()
+            .map(|range| &bytes[range.start as usize..range.end as usize])
+            .collect()
+    }
+
+    #[test]
+    fn test_mp3_tags_are_ignored() {
+        let audio = b"\xff\xfb\x90\x64audio frames".to_vec();
+
+        let mut tagged = b"ID3\x04\x00\x00\x00\x00\x00\x05TIT2x".to_vec();
+        tagged.extend_from_slice(&audio);
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.resize(128, 0);
+        tagged.extend_from_slice(&id3v1);
+
+        let stripped = strip_tags(&mut Cursor::new(&tagged), 0..tagged.len() as u64).unwrap();
+        assert_eq!(parts(&tagged, &[stripped]), vec![&audio[..]]);
+        assert_eq!(hash("mp3", &tagged), hash("mp3", &audio));
+        assert_ne!(hash("mp3", &audio), hash("mp3", b"other audio frames"));
+    }
+
+    #[test]
+    fn test_hash_audio_streams_file() {
+        // longer than the buffer the audio is hashed with
+        let audio = (0..200_000).map(|i| i as u8).collect::<Vec<_>>();
+        let mut tagged = b"ID3\x04\x00\x00\x00\x00\x00\x05TIT2x".to_vec();
+        tagged.extend_from_slice(&audio);
+
+        let path = std::env::temp_dir().join(format!("audio_hash_{}.mp3", std::process::id()));
+        std::fs::write(&path, &tagged).unwrap();
+        let hashed = hash_audio(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(hashed.unwrap(), format!("{:x}", Sha256::digest(&audio)));
+    }
+
+    #[test]
+    fn test_flac_frames() {
+        let mut flac = b"fLaC".to_vec();
+        flac.extend_from_slice(b"\x00\x00\x00\x02si");
+        flac.extend_from_slice(b"\x84\x00\x00\x03abc");
+        flac.extend_from_slice(b"frames");
+
+        let frames = flac_frames(&mut Cursor::new(&flac), 0..flac.len() as u64).unwrap();
+        assert_eq!(parts(&flac, &[frames.unwrap()]), vec![&b"frames"[..]]);
+
+        let truncated = b"fLaC\x80\x00\x00\x09abc";
+        assert_eq!(
+            flac_frames(&mut Cursor::new(truncated), 0..truncated.len() as u64).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_wav_chunks() {
+        let mut wav = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        wav.extend_from_slice(b"fmt \x02\x00\x00\x00fm");
+        wav.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00");
+        wav.extend_from_slice(b"data\x04\x00\x00\x00data");
+
+        let chunks = wav_chunks(&mut Cursor::new(&wav), 0..wav.len() as u64).unwrap();
+        assert_eq!(
+            parts(&wav, &chunks.unwrap()),
+            vec![&b"fm"[..], &b"data"[..]]
+        );
+    }
+
+    #[test]
+    fn test_ogg_audio_pages() {
+        fn page(granule_position: u64, sequence: u32, body: &[u8]) -> Vec<u8> {
+            let mut page = b"OggS\x00\x00".to_vec();
+            page.extend_from_slice(&granule_position.to_le_bytes());
+            page.extend_from_slice(&[0; 4]);
+            page.extend_from_slice(&sequence.to_le_bytes());
+            page.extend_from_slice(&[0; 4]);
+            page.push(1);
+            page.push(body.len() as u8);
+            page.extend_from_slice(body);
+            page
+        }
+
+        let mut ogg = page(0, 0, b"ident");
+        ogg.extend(page(u64::MAX, 1, b"comments"));
+        ogg.extend(page(0, 2, b"setup"));
+        ogg.extend(page(1024, 3, b"audio"));
+        ogg.extend(page(2048, 4, b"more audio"));
+
+        let pages = ogg_audio_pages(&mut Cursor::new(&ogg), 0..ogg.len() as u64).unwrap();
+        assert_eq!(
+            parts(&ogg, &pages.unwrap()),
+            vec![&b"audio"[..], &b"more audio"[..]]
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use std::{collections::HashMap, path::Path};
+
+use calamine::Reader;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{db::DbSlcbUser, prelude::*};
+
+/// A single user as exported from Streamlabs Chatbot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamlabsRow {
+    pub username: String,
+    pub points: i32,
+    pub hours: i32,
+    pub user_id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub new: Vec<StreamlabsRow>,
+    pub changed: Vec<(DbSlcbUser, StreamlabsRow)>,
+    pub conflicts: Vec<(StreamlabsRow, String)>,
+    pub unchanged: usize,
+}
+
+struct Columns {
+    username: usize,
+    points: usize,
+    hours: usize,
+    user_id: Option<usize>,
+}
+
+impl Columns {
+    fn from_header(header: &[String]) -> Result<Self> {
+        let find = |names: &[&str]| {
+            header
+                .iter()
+                .position(|h| names.contains(&h.trim().to_lowercase().as_str()))
+        };
+        let missing = |name: &str| Error::InvalidImport(format!("missing column `{}`", name));
+
+        Ok(Self {
+            username: find(&["username", "name", "user"]).ok_or_else(|| missing("username"))?,
+            points: find(&["points"]).ok_or_else(|| missing("points"))?,
+            hours: find(&["hours"]).ok_or_else(|| missing("hours"))?,
+            user_id: find(&["user id", "userid", "user_id", "channel id"]),
+        })
+    }
+
+    fn parse_row(&self, line: usize, row: &[String]) -> Result<Option<StreamlabsRow>> {
+        let cell = |idx: usize| row.get(idx).map(|c| c.trim()).unwrap_or("");
+        let number = |idx: usize, name: &str, round: fn(f64) -> f64| {
+            let value = cell(idx);
+            value
+                .parse::<i32>()
+                .or_else(|_| value.parse::<f64>().map(|v| round(v) as i32))
+                .map_err(|_| {
+                    Error::InvalidImport(format!("line {}: invalid {} `{}`", line, name, value))
+                })
+        };
+
+        let username = cell(self.username);
+        if username.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(StreamlabsRow {
+            username: username.to_string(),
+            // points are rounded down so nobody gains Boondollars, hours like `4.75` to the
+            // nearest hour
+            points: number(self.points, "points", f64::floor)?,
+            hours: number(self.hours, "hours", f64::round)?,
+            user_id: self
+                .user_id
+                .map(cell)
+                .filter(|id| !id.is_empty())
+                .map(ToString::to_string),
+        }))
+    }
+}
+
+fn parse_rows(mut rows: impl Iterator<Item = Vec<String>>) -> Result<Vec<StreamlabsRow>> {
+    let Some(header) = rows.next() else {
+        return Err(Error::InvalidImport("file is empty".to_string()));
+    };
+    let columns = Columns::from_header(&header)?;
+
+    let mut result = vec![];
+    // line 1 is the header
+    for (line, row) in rows.enumerate() {
+        if let Some(row) = columns.parse_row(line + 2, &row)? {
+            result.push(row);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Reads a Streamlabs Chatbot currency export, either as CSV or as XLSX.
+pub fn read_streamlabs_export(path: &Path) -> Result<Vec<StreamlabsRow>> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "csv" => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_path(path)?;
+            let rows = reader
+                .records()
+                .map(|r| r.map(|r| r.iter().map(ToString::to_string).collect::<Vec<_>>()))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+
+            parse_rows(rows.into_iter())
+        }
+        "xlsx" | "xls" | "ods" => {
+            let mut workbook = calamine::open_workbook_auto(path)?;
+            let Some(range) = workbook.worksheet_range_at(0) else {
+                return Err(Error::InvalidImport("workbook has no sheets".to_string()));
+            };
+            let range = range?;
+
+            parse_rows(
+                range
+                    .rows()
+                    .map(|r| r.iter().map(ToString::to_string).collect::<Vec<_>>()),
+            )
+        }
+        _ => Err(Error::InvalidImport(format!(
+            "unsupported file type `{}`",
+            extension
+        ))),
+    }
+}
+
+/// Compares the exported rows with what is already stored in `slcb_currency`.
+///
+/// Rows are matched by their YouTube channel ID if the export has one, otherwise by username.
+pub fn plan_import(existing: &[DbSlcbUser], rows: Vec<StreamlabsRow>) -> ImportReport {
+    let mut by_user_id: HashMap<&str, Vec<&DbSlcbUser>> = HashMap::new();
+    let mut by_username: HashMap<&str, Vec<&DbSlcbUser>> = HashMap::new();
+    for user in existing {
+        if let Some(user_id) = user.user_id.as_deref() {
+            by_user_id.entry(user_id).or_default().push(user);
+        }
+        by_username.entry(&user.username).or_default().push(user);
+    }
+
+    let row_key = |row: &StreamlabsRow| row.user_id.clone().unwrap_or(row.username.clone());
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    for row in &rows {
+        *occurrences.entry(row_key(row)).or_default() += 1;
+    }
+
+    let mut report = ImportReport::default();
+    for row in rows {
+        if occurrences[&row_key(&row)] > 1 {
+            report
+                .conflicts
+                .push((row, "appears more than once in the export".to_string()));
+            continue;
+        }
+
+        let candidates = row
+            .user_id
+            .as_deref()
+            .and_then(|id| by_user_id.get(id))
+            .or_else(|| by_username.get(row.username.as_str()))
+            .cloned()
+            .unwrap_or_default();
+
+        let matched = match candidates.as_slice() {
+            [] => {
+                report.new.push(row);
+                continue;
+            }
+            [matched] => *matched,
+            _ => {
+                let reason = format!("matches {} existing rows", candidates.len());
+                report.conflicts.push((row, reason));
+                continue;
+            }
+        };
+
+        if let (Some(existing_id), Some(row_id)) = (&matched.user_id, &row.user_id) {
+            if existing_id != row_id {
+                let reason = format!("username already belongs to channel {}", existing_id);
+                report.conflicts.push((row, reason));
+                continue;
+            }
+        }
+
+        let unchanged = matched.username == row.username
+            && matched.points == row.points
+            && matched.hours == row.hours
+            && (row.user_id.is_none() || matched.user_id == row.user_id);
+        if unchanged {
+            report.unchanged += 1;
+        } else {
+            report.changed.push((matched.clone(), row));
+        }
+    }
+
+    report
+}
+
+#[tracing::instrument(skip(db))]
+pub async fn import_streamlabs(db: &PgPool, path: &Path, dry_run: bool) -> Result<ImportReport> {
+    let rows = read_streamlabs_export(path)?;
+    info!("Read {} rows from {}", rows.len(), path.display());
+
+    let existing = DbSlcbUser::fetch_all(db).await?;
+    let report = plan_import(&existing, rows);
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    let mut transaction = db.begin().await?;
+    for row in &report.new {
+        DbSlcbUser::insert(
+            &mut transaction,
+            &row.username,
+            row.points,
+            row.hours,
+            row.user_id.as_deref(),
+        )
+        .await?;
+    }
+    for (existing, row) in &report.changed {
+        let mut user = existing.clone();
+        user.username = row.username.clone();
+        user.points = row.points;
+        user.hours = row.hours;
+        if row.user_id.is_some() {
+            user.user_id = row.user_id.clone();
+        }
+        user.update(&mut transaction).await?;
+    }
+    transaction.commit().await?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(username: &str, points: i32, hours: i32, user_id: Option<&str>) -> StreamlabsRow {
+        StreamlabsRow {
+            username: username.to_string(),
+            points,
+            hours,
+            user_id: user_id.map(ToString::to_string),
+        }
+    }
+
+    fn db_user(id: i32, username: &str, points: i32, hours: i32) -> DbSlcbUser {
+        DbSlcbUser {
+            id,
+            username: username.to_string(),
+            points,
+            hours,
+            user_id: None,
+        }
+    }
+
+    fn strings(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_rows() {
+        let rows = vec![
+            strings(&["Name", "Rank", "Points", "Hours"]),
+            strings(&["Jade", "Rung 1", "120.5", "4.75"]),
+            strings(&["Dave", "Rung 1", "30", "2.25"]),
+            strings(&["", "", "", ""]),
+        ];
+
+        let parsed = parse_rows(rows.into_iter()).unwrap();
+        assert_eq!(
+            parsed,
+            vec![row("Jade", 120, 5, None), row("Dave", 30, 2, None)]
+        );
+    }
+
+    #[test]
+    fn test_parse_rows_missing_column() {
+        let rows = vec![strings(&["Name", "Points"])];
+
+        assert!(parse_rows(rows.into_iter()).is_err());
+    }
+
+    #[test]
+    fn test_plan_import() {
+        let existing = vec![
+            db_user(1, "John", 10, 1),
+            db_user(2, "Rose", 20, 2),
+            db_user(3, "Dave", 30, 3),
+            db_user(4, "Dave", 40, 4),
+        ];
+        let rows = vec![
+            row("John", 10, 1, None),
+            row("Rose", 25, 2, None),
+            row("Dave", 50, 5, None),
+            row("Jade", 60, 6, None),
+            row("Karkat", 1, 1, None),
+            row("Karkat", 2, 2, None),
+        ];
+
+        let report = plan_import(&existing, rows);
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(report.new, vec![row("Jade", 60, 6, None)]);
+        assert_eq!(report.changed.len(), 1);
+        assert_eq!(report.changed[0].0.id, 2);
+        assert_eq!(report.conflicts.len(), 3);
+    }
+
+    #[test]
+    fn test_plan_import_matches_user_id_first() {
+        let mut renamed = db_user(1, "Old Name", 10, 1);
+        renamed.user_id = Some("UC123".to_string());
+
+        let report = plan_import(&[renamed], vec![row("New Name", 10, 1, Some("UC123"))]);
+        assert!(report.new.is_empty());
+        assert_eq!(report.changed.len(), 1);
+        assert_eq!(report.changed[0].1.username, "New Name");
+    }
+}
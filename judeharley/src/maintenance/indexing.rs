@@ -1,11 +1,20 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use audiotags::{AudioTagEdit, Id3v2Tag};
-use sha2::{Digest, Sha256};
+use chrono::{DateTime, Timelike, Utc};
 use sqlx::PgPool;
 use tracing::{error, info, warn};
 
-use crate::{db::DbSong, maintenance::rewrite_music_path, prelude::*};
+use crate::{
+    db::{DbSong, DbSongFile, DbUnknownTrack},
+    maintenance::{audio_hash::hash_audio, rewrite_music_path},
+    metrics::{FROHIKE_INDEXING_FAILURES, FROHIKE_INDEXING_SECONDS},
+    prelude::*,
+};
 
 pub trait WavTag {
     fn read_from_wav_path(path: impl AsRef<Path>) -> Result<Self>
@@ -24,49 +33,477 @@ impl WavTag for Id3v2Tag {
     }
 }
 
+/// What a reindex changed.
+#[derive(Debug, Default)]
+pub struct IndexReport {
+    pub added: usize,
+    pub updated: usize,
+    pub moved: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Files whose audio is indexed at another path already, by the path they'd be indexed as
+    pub duplicates: Vec<String>,
+    /// Files that couldn't be read, they keep their old index entry if they had one
+    pub failed: Vec<PathBuf>,
+    /// Directory entries that couldn't be walked. No songs are removed if there are any, their
+    /// files could be among them
+    pub unwalkable: usize,
+}
+
+/// A file's song, tags and size as read from disk.
+struct IndexedFile {
+    song: DbSong,
+    tags: Vec<(String, String)>,
+    file: DbSongFile,
+}
+
+/// A file that is on disk, by the path and hash it is indexed with.
+struct FoundFile {
+    file_path: String,
+    file_hash: String,
+    /// Whether the file was read again, unchanged files keep the hash they had
+    changed: bool,
+}
+
+/// What indexing a changed file does to the song it holds.
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    /// The audio wasn't indexed before
+    Add,
+    /// The song stays at its path
+    Update,
+    /// The song's file was moved here from the given path
+    Move(String),
+    /// The song at the path was still identified by its path, by the given hash, and keeps
+    /// its history
+    Rekey(String),
+    /// The same audio is indexed at the given path
+    Duplicate(String),
+}
+
+/// A song whose audio isn't on disk anymore.
+#[derive(Debug, PartialEq, Eq)]
+struct Removal {
+    file_path: String,
+    file_hash: String,
+    /// The song that takes over the history of a song that was identified by its path, if its
+    /// file is a duplicate of that song now
+    merge_into: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Plan {
+    /// By the path of each changed file
+    changes: HashMap<String, Change>,
+    removals: Vec<Removal>,
+}
+
+impl Plan {
+    fn is_duplicate(&self, file_path: &str) -> bool {
+        matches!(self.changes.get(file_path), Some(Change::Duplicate(_)))
+    }
+
+    /// Keeps the songs whose files weren't found, for when not all files could be looked at.
+    /// Only songs whose path holds other audio now are known to be gone.
+    fn keep_unfound(&mut self, files: &[FoundFile]) {
+        let found = files
+            .iter()
+            .map(|file| file.file_path.as_str())
+            .collect::<HashSet<_>>();
+        self.removals
+            .retain(|removal| found.contains(removal.file_path.as_str()));
+    }
+}
+
+/// Works out what happened to the indexed songs, `known` by path, given the files on disk.
+/// Songs are identified by their audio, so a song whose audio turns up at another path has
+/// been moved. If the same audio is on disk more than once, the path it's indexed at already
+/// keeps it, otherwise the first one found does.
+///
+/// Songs that are still identified by their path, their hashes in `path_hashed`, keep their
+/// history under the hash of the audio at their path. Any other song whose file now holds
+/// different audio is gone, and a new song is indexed there.
+fn plan(
+    known: &HashMap<String, String>,
+    path_hashed: &HashSet<String>,
+    files: &[FoundFile],
+) -> Plan {
+    let known_paths = known
+        .iter()
+        .map(|(path, hash)| (hash.as_str(), path.as_str()))
+        .collect::<HashMap<_, _>>();
+
+    let mut kept_at = HashMap::new();
+    for file in files {
+        let indexed_here = known_paths.get(file.file_hash.as_str()) == Some(&&*file.file_path);
+        kept_at
+            .entry(file.file_hash.as_str())
+            .and_modify(|path| {
+                if indexed_here {
+                    *path = file.file_path.as_str();
+                }
+            })
+            .or_insert(file.file_path.as_str());
+    }
+
+    let mut plan = Plan::default();
+    let mut rekeyed = HashSet::new();
+    for file in files.iter().filter(|file| file.changed) {
+        let path = file.file_path.as_str();
+        let change = match (
+            kept_at[file.file_hash.as_str()],
+            known_paths.get(file.file_hash.as_str()),
+        ) {
+            (kept_at, _) if kept_at != path => Change::Duplicate(kept_at.to_string()),
+            (_, Some(&old_path)) if old_path == path => Change::Update,
+            (_, Some(&old_path)) => Change::Move(old_path.to_string()),
+            (_, None) => match known.get(path) {
+                Some(old_hash)
+                    if path_hashed.contains(old_hash)
+                        && !kept_at.contains_key(old_hash.as_str()) =>
+                {
+                    rekeyed.insert(old_hash.as_str());
+                    Change::Rekey(old_hash.clone())
+                }
+                _ => Change::Add,
+            },
+        };
+        plan.changes.insert(file.file_path.clone(), change);
+    }
+
+    let on_disk = files
+        .iter()
+        .map(|file| (file.file_path.as_str(), file.file_hash.as_str()))
+        .collect::<HashMap<_, _>>();
+    for (path, hash) in known {
+        if kept_at.contains_key(hash.as_str()) || rekeyed.contains(hash.as_str()) {
+            continue;
+        }
+
+        let merge_into = on_disk
+            .get(path.as_str())
+            .filter(|new_hash| path_hashed.contains(hash) && kept_at[*new_hash] != path)
+            .map(|new_hash| new_hash.to_string());
+        plan.removals.push(Removal {
+            file_path: path.clone(),
+            file_hash: hash.clone(),
+            merge_into,
+        });
+    }
+    plan.removals.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+    plan
+}
+
+/// Writes the changed files as planned.
+async fn apply(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    plan: &Plan,
+    changed: &[IndexedFile],
+    report: &mut IndexReport,
+) -> Result<()> {
+    let mut merges = vec![];
+    for removal in &plan.removals {
+        info!("Dropping index for {}", removal.file_path);
+        if let Some(merge_into) = &removal.merge_into {
+            merges.push((removal.file_hash.clone(), merge_into.clone()));
+        }
+    }
+    DbSong::move_history(transaction, &merges).await?;
+    let removed = plan
+        .removals
+        .iter()
+        .map(|removal| removal.file_hash.clone())
+        .collect::<Vec<_>>();
+    report.removed += DbSong::delete_hashes(transaction, &removed).await? as usize;
+
+    let moving = changed
+        .iter()
+        .filter(|indexed| matches!(plan.changes[&indexed.song.file_path], Change::Move(_)))
+        .map(|indexed| indexed.song.file_hash.clone())
+        .collect::<Vec<_>>();
+    DbSong::release_paths(transaction, &moving).await?;
+
+    let rekeyed = changed
+        .iter()
+        .filter_map(|indexed| match &plan.changes[&indexed.song.file_path] {
+            Change::Rekey(old_hash) => Some((old_hash.clone(), indexed.song.file_hash.clone())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    DbSong::rekey(transaction, &rekeyed).await?;
+
+    for indexed in changed {
+        let song = &indexed.song;
+        match &plan.changes[&song.file_path] {
+            Change::Duplicate(kept_at) => {
+                warn!(
+                    "{} is a duplicate of {}, skipping it",
+                    song.file_path, kept_at
+                );
+                report.duplicates.push(song.file_path.clone());
+                continue;
+            }
+            Change::Add => {
+                info!("Indexing {} at path {}", song, song.file_path);
+                report.added += 1;
+            }
+            Change::Update => {
+                info!("Updating {} at path {}", song, song.file_path);
+                report.updated += 1;
+            }
+            Change::Move(from) => {
+                info!("Moving {} from {} to {}", song, from, song.file_path);
+                report.moved += 1;
+            }
+            Change::Rekey(_) => {
+                info!(
+                    "Updating {} at path {}, it's identified by its audio now",
+                    song, song.file_path
+                );
+                report.updated += 1;
+            }
+        }
+
+        song.upsert(transaction, &indexed.file).await?;
+        song.set_tags(transaction, &indexed.tags).await?;
+    }
+
+    let indexed = changed
+        .iter()
+        .map(|indexed| indexed.song.file_path.clone())
+        .filter(|file_path| !plan.is_duplicate(file_path))
+        .collect::<Vec<_>>();
+    DbUnknownTrack::resolve(transaction, &indexed).await?;
+
+    Ok(())
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| ["mp3", "flac", "ogg", "wav"].contains(&extension.as_str()))
+}
+
+/// The size and modification time of the file at `path`, indexed as `file_path`.
+fn stat(path: &Path, file_path: String) -> Result<DbSongFile> {
+    let metadata = std::fs::metadata(path)?;
+    // Postgres only keeps microseconds
+    let file_modified_at = metadata.modified().ok().and_then(|modified| {
+        let modified = DateTime::<Utc>::from(modified).naive_utc();
+        modified.with_nanosecond(modified.nanosecond() / 1_000 * 1_000)
+    });
+
+    Ok(DbSongFile {
+        file_path,
+        file_size: Some(metadata.len() as i64),
+        file_modified_at,
+    })
+}
+
+/// Whether the file is still the way it was when it was indexed. Files without a known size
+/// or modification time always count as changed.
+fn is_unchanged(known: &DbSongFile, current: &DbSongFile) -> bool {
+    current.file_size.is_some() && current.file_modified_at.is_some() && known == current
+}
+
+/// Indexes the files in `directory` that are new or changed since the last run and removes
+/// the songs whose files are gone, all in one transaction. With `dry_run`, the transaction is
+/// rolled back.
 #[tracing::instrument(skip(db))]
-pub async fn index(db: PgPool, directory: PathBuf) -> Result<()> {
-    info!("Pruning indexing database");
-    DbSong::prune(&db).await?;
+pub async fn index(db: PgPool, directory: PathBuf, dry_run: bool) -> Result<IndexReport> {
+    let started = Instant::now();
+    let path_hashed = DbSong::fetch_path_hashed(&db).await?;
+    let known = DbSong::fetch_files(&db)
+        .await?
+        .into_iter()
+        .map(|(file_hash, file)| (file.file_path.clone(), (file_hash, file)))
+        .collect::<HashMap<_, _>>();
 
+    let mut report = IndexReport::default();
     let files = walkdir::WalkDir::new(&directory)
         .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            e.file_type().is_file()
-                && e.path().extension().is_some()
-                && vec!["mp3", "flac", "ogg", "wav"].contains(
-                    &e.path()
-                        .extension()
-                        .unwrap()
-                        .to_string_lossy()
-                        .to_lowercase()
-                        .as_str(),
-                )
+        .filter_map(|e| match e {
+            Ok(e) => Some(e),
+            Err(e) => {
+                error!("failed to walk {}: {}", directory.display(), e);
+                report.unwalkable += 1;
+                None
+            }
         })
+        .filter(|e| e.file_type().is_file() && is_audio_file(e.path()))
         .map(|e| e.path().to_owned())
         .collect::<Vec<_>>();
 
-    let len = files.len();
-    let mut failed_files = vec![];
-    for file in files {
-        let result = index_file(db.clone(), &file, &directory).await;
-        if let Err(e) = result {
-            error!("failed to index file: {}", e);
-            failed_files.push(file);
+    let mut found = vec![];
+    let mut changed = vec![];
+    for path in files {
+        let file_path = rewrite_music_path(&path, &directory)?.display().to_string();
+        let known_file = known.get(&file_path);
+
+        let current = stat(&path, file_path.clone());
+        if let (Some((file_hash, known_file)), Ok(current)) = (known_file, &current) {
+            if is_unchanged(known_file, current) {
+                report.unchanged += 1;
+                found.push(FoundFile {
+                    file_path,
+                    file_hash: file_hash.clone(),
+                    changed: false,
+                });
+                continue;
+            }
+        }
+
+        match current.and_then(|file| read_file(&path, &directory, file)) {
+            Ok(indexed) => {
+                found.push(FoundFile {
+                    file_path,
+                    file_hash: indexed.song.file_hash.clone(),
+                    changed: true,
+                });
+                changed.push(indexed);
+            }
+            Err(e) => {
+                error!("failed to index file {}: {}", path.display(), e);
+                report.failed.push(path);
+                // the file is still there, so its song stays
+                if let Some((file_hash, _)) = known_file {
+                    found.push(FoundFile {
+                        file_path,
+                        file_hash: file_hash.clone(),
+                        changed: false,
+                    });
+                }
+            }
         }
     }
-    info!("Indexed {} files", len);
-    if !failed_files.is_empty() {
-        warn!("Failed to index {} files", failed_files.len());
-        warn!("Failed files: {:#?}", failed_files);
+
+    let known = known
+        .into_iter()
+        .map(|(file_path, (file_hash, _))| (file_path, file_hash))
+        .collect();
+    let mut plan = plan(&known, &path_hashed, &found);
+    if report.unwalkable > 0 {
+        plan.keep_unfound(&found);
+        warn!(
+            "{} entries couldn't be walked, not removing songs whose files weren't found",
+            report.unwalkable
+        );
     }
 
-    Ok(())
+    let mut transaction = db.begin().await?;
+    apply(&mut transaction, &plan, &changed, &mut report).await?;
+    if dry_run {
+        transaction.rollback().await?;
+    } else {
+        transaction.commit().await?;
+    }
+
+    FROHIKE_INDEXING_SECONDS.observe(started.elapsed().as_secs_f64());
+    info!(
+        "Indexed {} files in {:?}: {} added, {} updated, {} moved, {} removed, {} unchanged, {} duplicates, {} failed, {} unwalkable",
+        found.len(),
+        started.elapsed(),
+        report.added,
+        report.updated,
+        report.moved,
+        report.removed,
+        report.unchanged,
+        report.duplicates.len(),
+        report.failed.len(),
+        report.unwalkable
+    );
+    if !report.failed.is_empty() {
+        warn!("Failed files: {:#?}", report.failed);
+    }
+
+    Ok(report)
 }
 
-#[tracing::instrument(skip(db))]
+/// The path of an indexed file on disk.
+fn disk_path(file_path: &str, music_path: &Path) -> Option<PathBuf> {
+    Path::new(file_path)
+        .strip_prefix("/music")
+        .ok()
+        .map(|path| music_path.join(path))
+}
+
+/// Indexes a single file. If its audio is indexed already at a path that is gone, the song is
+/// moved here.
 pub async fn index_file(db: PgPool, path: &Path, music_path: &Path) -> Result<()> {
+    if !is_audio_file(path) {
+        return Ok(());
+    }
+
+    let file_path = rewrite_music_path(path, music_path)?.display().to_string();
+    let indexed = read_file(path, music_path, stat(path, file_path.clone())?)?;
+
+    let mut known = HashMap::new();
+    let mut found = vec![];
+    if let Some(song) = DbSong::fetch(&db, &file_path).await? {
+        known.insert(song.file_path, song.file_hash);
+    }
+    if let Some(song) = DbSong::fetch_from_hash(&db, &indexed.song.file_hash).await? {
+        let still_there = song.file_path != file_path
+            && disk_path(&song.file_path, music_path).is_some_and(|path| path.is_file());
+        if still_there {
+            found.push(FoundFile {
+                file_path: song.file_path.clone(),
+                file_hash: song.file_hash.clone(),
+                changed: false,
+            });
+        }
+        known.insert(song.file_path, song.file_hash);
+    }
+    found.push(FoundFile {
+        file_path,
+        file_hash: indexed.song.file_hash.clone(),
+        changed: true,
+    });
+
+    let path_hashed = DbSong::fetch_path_hashed(&db).await?;
+    let plan = plan(&known, &path_hashed, &found);
+    let mut transaction = db.begin().await?;
+    let changed = std::slice::from_ref(&indexed);
+    apply(
+        &mut transaction,
+        &plan,
+        changed,
+        &mut IndexReport::default(),
+    )
+    .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Moves the index of a renamed file or directory without reading the files again. Files that
+/// weren't indexed at their old path get indexed.
+pub async fn move_index(db: PgPool, from: &Path, to: &Path, music_path: &Path) -> Result<()> {
+    let db_from = rewrite_music_path(from, music_path)?;
+    let db_to = rewrite_music_path(to, music_path)?;
+    info!("Moving index from {} to {}", from.display(), to.display());
+
+    if to.is_dir() {
+        DbSong::move_directory(&db, &db_from, &db_to).await?;
+    } else if !DbSong::move_file(&db, &db_from, &db_to).await? {
+        index_file(db, to, music_path).await?;
+    }
+
+    Ok(())
+}
+
+fn read_file(path: &Path, music_path: &Path, file: DbSongFile) -> Result<IndexedFile> {
+    let result = read_file_inner(path, music_path, file);
+    if result.is_err() {
+        FROHIKE_INDEXING_FAILURES.inc();
+    }
+
+    result
+}
+
+#[tracing::instrument(skip(file))]
+fn read_file_inner(path: &Path, music_path: &Path, file: DbSongFile) -> Result<IndexedFile> {
     let (title, artist, album) = {
         if path.extension().unwrap().to_ascii_lowercase() == "wav" {
             let tag = Id3v2Tag::read_from_wav_path(path)?;
@@ -91,32 +528,25 @@ pub async fn index_file(db: PgPool, path: &Path, music_path: &Path) -> Result<()
         ._bit_rate
         .unwrap_or((meta.file_size * 8) / duration as u64);
 
-    let mut hasher: Sha256 = Digest::new();
-    hasher.update(path.canonicalize()?.to_string_lossy().as_bytes());
-    let hash = hasher.finalize();
-    let hash_str = format!("{:x}", hash);
+    let hash_str = hash_audio(path)?;
 
     let path = rewrite_music_path(path, music_path)?;
 
-    info!(
-        "Indexing {title} by {artist} on {album} at path {}",
-        path.display()
-    );
-
-    let new_song = DbSong {
+    let song = DbSong {
         title: title.replace(char::from(0), ""),
         artist: artist.replace(char::from(0), ""),
         album: album.replace(char::from(0), ""),
         file_path: path.display().to_string(),
         duration,
-        file_hash: hash_str.clone(),
+        file_hash: hash_str,
         bitrate: bitrate as i32,
     };
-    new_song.insert(&db).await?;
-
-    new_song.add_tags(&db, &meta.tags).await?;
 
-    Ok(())
+    Ok(IndexedFile {
+        song,
+        tags: meta.tags,
+        file,
+    })
 }
 
 pub async fn drop_index(db: PgPool, path: &Path, music_path: &Path) -> Result<()> {
@@ -140,6 +570,20 @@ pub async fn drop_index_folder(db: PgPool, folder_path: &Path, music_path: &Path
     Ok(())
 }
 
+/// Drops the index of a file or directory that was moved out of the library. It's gone, so
+/// whether a song is indexed at its path tells which of the two it was.
+pub async fn drop_moved_away(db: PgPool, path: &Path, music_path: &Path) -> Result<()> {
+    let db_path = rewrite_music_path(path, music_path)?;
+    if DbSong::fetch(&db, &db_path.display().to_string())
+        .await?
+        .is_some()
+    {
+        drop_index(db, path, music_path).await
+    } else {
+        drop_index_folder(db, path, music_path).await
+    }
+}
+
 pub async fn create_playlist(db: PgPool, playlist_path: &Path) -> Result<()> {
     let songs = DbSong::fetch_all_paths(&db)
         .await?
@@ -155,3 +599,207 @@ pub async fn create_playlist(db: PgPool, playlist_path: &Path) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(file_size: Option<i64>) -> DbSongFile {
+        DbSongFile {
+            file_path: "/music/Showtime.mp3".to_string(),
+            file_size,
+            file_modified_at: chrono::NaiveDate::from_ymd_opt(2023, 11, 28)
+                .unwrap()
+                .and_hms_micro_opt(20, 15, 33, 123_456),
+        }
+    }
+
+    #[test]
+    fn test_is_unchanged() {
+        assert!(is_unchanged(&file(Some(1024)), &file(Some(1024))));
+        assert!(!is_unchanged(&file(Some(1024)), &file(Some(2048))));
+        // songs indexed before sizes were stored always get read again
+        assert!(!is_unchanged(&file(None), &file(Some(1024))));
+        assert!(!is_unchanged(&file(None), &file(None)));
+    }
+
+    fn known(songs: &[(&str, &str)]) -> HashMap<String, String> {
+        songs
+            .iter()
+            .map(|(path, hash)| (path.to_string(), hash.to_string()))
+            .collect()
+    }
+
+    fn found(file_path: &str, file_hash: &str, changed: bool) -> FoundFile {
+        FoundFile {
+            file_path: file_path.to_string(),
+            file_hash: file_hash.to_string(),
+            changed,
+        }
+    }
+
+    fn path_hashed(hashes: &[&str]) -> HashSet<String> {
+        hashes.iter().map(|hash| hash.to_string()).collect()
+    }
+
+    #[test]
+    fn test_plan_moves_and_removals() {
+        let known = known(&[("/music/a.mp3", "a"), ("/music/b.mp3", "b")]);
+        let plan = plan(
+            &known,
+            &HashSet::new(),
+            &[
+                found("/music/Vol. 5/a.mp3", "a", true),
+                found("/music/c.mp3", "c", true),
+            ],
+        );
+
+        assert_eq!(
+            plan.changes["/music/Vol. 5/a.mp3"],
+            Change::Move("/music/a.mp3".to_string())
+        );
+        assert_eq!(plan.changes["/music/c.mp3"], Change::Add);
+        assert_eq!(
+            plan.removals,
+            vec![Removal {
+                file_path: "/music/b.mp3".to_string(),
+                file_hash: "b".to_string(),
+                merge_into: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_plan_rekeys_path_hashes() {
+        // what every song looks like after switching from path hashes
+        let known = known(&[("/music/a.mp3", "path a"), ("/music/b.mp3", "b")]);
+        let plan = plan(
+            &known,
+            &path_hashed(&["path a"]),
+            &[
+                found("/music/a.mp3", "a", true),
+                found("/music/b.mp3", "b", true),
+            ],
+        );
+
+        assert_eq!(
+            plan.changes["/music/a.mp3"],
+            Change::Rekey("path a".to_string())
+        );
+        assert_eq!(plan.changes["/music/b.mp3"], Change::Update);
+        assert!(plan.removals.is_empty());
+    }
+
+    #[test]
+    fn test_plan_overwritten_file() {
+        let known = known(&[("/music/a.mp3", "a")]);
+        let plan = plan(
+            &known,
+            &HashSet::new(),
+            &[found("/music/a.mp3", "other audio", true)],
+        );
+
+        // a different song now, which doesn't get the history of the old one
+        assert_eq!(plan.changes["/music/a.mp3"], Change::Add);
+        assert_eq!(
+            plan.removals,
+            vec![Removal {
+                file_path: "/music/a.mp3".to_string(),
+                file_hash: "a".to_string(),
+                merge_into: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_plan_duplicates() {
+        let known = known(&[
+            ("/music/b.mp3", "a"),
+            ("/music/c.mp3", "path c"),
+            ("/music/d.mp3", "d"),
+        ]);
+        let plan = plan(
+            &known,
+            &path_hashed(&["path c"]),
+            &[
+                found("/music/a.mp3", "a", true),
+                found("/music/b.mp3", "a", false),
+                found("/music/c.mp3", "a", true),
+                found("/music/d.mp3", "a", true),
+            ],
+        );
+
+        // the path the audio is indexed at keeps it
+        for path in ["/music/a.mp3", "/music/c.mp3", "/music/d.mp3"] {
+            assert_eq!(
+                plan.changes[path],
+                Change::Duplicate("/music/b.mp3".to_string())
+            );
+        }
+        // only the song that was identified by its path was the same song all along
+        assert_eq!(
+            plan.removals,
+            vec![
+                Removal {
+                    file_path: "/music/c.mp3".to_string(),
+                    file_hash: "path c".to_string(),
+                    merge_into: Some("a".to_string()),
+                },
+                Removal {
+                    file_path: "/music/d.mp3".to_string(),
+                    file_hash: "d".to_string(),
+                    merge_into: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_keep_unfound() {
+        let known = known(&[("/music/a.mp3", "a"), ("/music/b.mp3", "b")]);
+        let files = [found("/music/a.mp3", "other audio", true)];
+        let mut plan = plan(&known, &HashSet::new(), &files);
+        assert_eq!(plan.removals.len(), 2);
+
+        plan.keep_unfound(&files);
+        assert_eq!(
+            plan.removals,
+            vec![Removal {
+                file_path: "/music/a.mp3".to_string(),
+                file_hash: "a".to_string(),
+                merge_into: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_plan_swapped_files() {
+        let known = known(&[("/music/a.mp3", "a"), ("/music/b.mp3", "b")]);
+        let plan = plan(
+            &known,
+            &HashSet::new(),
+            &[
+                found("/music/a.mp3", "b", true),
+                found("/music/b.mp3", "a", true),
+            ],
+        );
+
+        assert_eq!(
+            plan.changes["/music/a.mp3"],
+            Change::Move("/music/b.mp3".to_string())
+        );
+        assert_eq!(
+            plan.changes["/music/b.mp3"],
+            Change::Move("/music/a.mp3".to_string())
+        );
+        assert!(plan.removals.is_empty());
+    }
+
+    #[test]
+    fn test_is_audio_file() {
+        assert!(is_audio_file(Path::new("/music/Vol. 5/Cascade.FLAC")));
+        assert!(is_audio_file(Path::new("/music/Showtime.mp3")));
+        assert!(!is_audio_file(Path::new("/music/cover.jpg")));
+        assert!(!is_audio_file(Path::new("/music/README")));
+    }
+}
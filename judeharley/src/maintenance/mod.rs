@@ -1,7 +1,10 @@
 use crate::prelude::*;
 use std::path::{Path, PathBuf};
 
+pub mod audio_hash;
+pub mod import;
 pub mod indexing;
+pub mod playlists;
 
 pub fn rewrite_music_path(path: &Path, music_path: &Path) -> Result<PathBuf> {
     Ok(Path::new("/music").join(path.strip_prefix(music_path)?))
@@ -0,0 +1,309 @@
+//! Importing and exporting [`DbPlaylist`]s as M3U, M3U8 and PLS files.
+
+use std::path::{Component, Path, PathBuf};
+
+use sqlx::PgPool;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{
+    db::{DbPlaylist, DbSong},
+    maintenance::rewrite_music_path,
+    prelude::*,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    M3u8,
+    Pls,
+}
+
+impl PlaylistFormat {
+    /// Picks the format from the file extension.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "m3u" => Ok(Self::M3u),
+            "m3u8" => Ok(Self::M3u8),
+            "pls" => Ok(Self::Pls),
+            _ => Err(Error::UnsupportedPlaylistFormat(extension)),
+        }
+    }
+}
+
+/// Decodes a playlist file. M3U8 and PLS are UTF-8, plain M3U files often are Latin-1.
+fn decode(format: PlaylistFormat, bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(contents) => contents,
+        Err(e) if format == PlaylistFormat::M3u => {
+            e.into_bytes().into_iter().map(char::from).collect()
+        }
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    }
+}
+
+/// Reads the paths or URLs of the entries, in order.
+pub fn parse(format: PlaylistFormat, contents: &str) -> Vec<String> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(ToString::to_string)
+            .collect(),
+        PlaylistFormat::Pls => {
+            let mut files = contents
+                .lines()
+                .filter_map(|line| {
+                    let (key, value) = line.trim().split_once('=')?;
+                    let number = key
+                        .trim()
+                        .to_lowercase()
+                        .strip_prefix("file")?
+                        .parse::<u32>()
+                        .ok()?;
+                    Some((number, value.trim().to_string()))
+                })
+                .collect::<Vec<_>>();
+            files.sort_by_key(|(number, _)| *number);
+            files.into_iter().map(|(_, file)| file).collect()
+        }
+    }
+}
+
+/// Writes `songs` in the given format, with their paths as Liquidsoap sees them.
+pub fn write(format: PlaylistFormat, songs: &[DbSong]) -> String {
+    let mut contents = String::new();
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => {
+            contents.push_str("#EXTM3U\n");
+            for song in songs {
+                contents.push_str(&format!(
+                    "#EXTINF:{},{} - {}\n{}\n",
+                    song.duration.round() as i64,
+                    song.artist,
+                    song.title,
+                    song.file_path
+                ));
+            }
+        }
+        PlaylistFormat::Pls => {
+            contents.push_str("[playlist]\n");
+            for (i, song) in songs.iter().enumerate() {
+                let number = i + 1;
+                contents.push_str(&format!(
+                    "File{number}={}\nTitle{number}={} - {}\nLength{number}={}\n",
+                    song.file_path,
+                    song.artist,
+                    song.title,
+                    song.duration.round() as i64
+                ));
+            }
+            contents.push_str(&format!("NumberOfEntries={}\nVersion=2\n", songs.len()));
+        }
+    }
+
+    contents
+}
+
+/// Turns a playlist entry into the path the song is indexed under. Relative entries are
+/// relative to the playlist, and paths inside `music_path` are rewritten to `/music`.
+/// URLs other than `file://` can't be songs of ours.
+fn resolve_entry(entry: &str, playlist_dir: &Path, music_path: Option<&Path>) -> Option<PathBuf> {
+    let entry = if entry.contains("://") {
+        // percent-decodes the path too, `file:///music/Show%20Time.mp3`
+        let url = Url::parse(entry).ok()?;
+        if url.scheme() != "file" {
+            return None;
+        }
+        url.to_file_path().ok()?
+    } else {
+        PathBuf::from(entry)
+    };
+
+    // resolve `..` without touching the filesystem, the files may only exist on the server
+    let mut path = PathBuf::new();
+    for component in playlist_dir.join(entry).components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir => {}
+            component => path.push(component),
+        }
+    }
+
+    match music_path {
+        Some(music_path) if path.starts_with(music_path) => {
+            rewrite_music_path(&path, music_path).ok()
+        }
+        _ => Some(path),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PlaylistImportReport {
+    pub added: usize,
+    /// Entries that didn't match an indexed song
+    pub missing: Vec<String>,
+}
+
+/// Replaces the songs of the playlist `name`, creating it if needed, with the songs listed in
+/// the file at `path`.
+#[tracing::instrument(skip(db))]
+pub async fn import_playlist(
+    db: &PgPool,
+    name: &str,
+    path: &Path,
+    music_path: Option<&Path>,
+    dry_run: bool,
+) -> Result<PlaylistImportReport> {
+    let format = PlaylistFormat::from_path(path)?;
+    let contents = decode(format, std::fs::read(path)?);
+    let playlist_dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut report = PlaylistImportReport::default();
+    let mut song_ids = vec![];
+    for entry in parse(format, &contents) {
+        let song = match resolve_entry(&entry, playlist_dir, music_path) {
+            Some(song_path) => DbSong::fetch(db, &song_path.display().to_string()).await?,
+            None => None,
+        };
+        match song {
+            Some(song) => song_ids.push(song.file_hash),
+            None => {
+                warn!("{} is not indexed", entry);
+                report.missing.push(entry);
+            }
+        }
+    }
+    report.added = song_ids.len();
+    info!("Read {} songs from {}", song_ids.len(), path.display());
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    let mut transaction = db.begin().await?;
+    let playlist = DbPlaylist::fetch_or_create(&mut transaction, name).await?;
+    playlist.replace_songs(&mut transaction, &song_ids).await?;
+    transaction.commit().await?;
+
+    Ok(report)
+}
+
+/// Writes the songs of `playlist` to `path`, in the format its extension asks for.
+#[tracing::instrument(skip(db))]
+pub async fn export_playlist(db: &PgPool, playlist: &DbPlaylist, path: &Path) -> Result<usize> {
+    let format = PlaylistFormat::from_path(path)?;
+    let songs = DbPlaylist::songs(db, playlist.id).await?;
+
+    std::fs::write(path, write(format, &songs))?;
+
+    Ok(songs.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            PlaylistFormat::from_path(Path::new("a/Rotation.M3U8")).unwrap(),
+            PlaylistFormat::M3u8
+        );
+        assert_eq!(
+            PlaylistFormat::from_path(Path::new("rotation.pls")).unwrap(),
+            PlaylistFormat::Pls
+        );
+        assert!(PlaylistFormat::from_path(Path::new("rotation.xspf")).is_err());
+    }
+
+    #[test]
+    fn test_parse_m3u() {
+        let contents = "\u{feff}#EXTM3U\r\n#EXTINF:200,Toby Fox - Showtime\r\n/music/Showtime.mp3\r\n\r\nVol. 5/Cascade.mp3\r\n";
+
+        assert_eq!(
+            parse(PlaylistFormat::M3u8, contents),
+            vec!["/music/Showtime.mp3", "Vol. 5/Cascade.mp3"]
+        );
+    }
+
+    #[test]
+    fn test_parse_pls() {
+        let contents = "[playlist]\nFile2=/music/Cascade.mp3\nTitle2=Cascade\nfile1 = /music/Showtime.mp3\nNumberOfEntries=2\nVersion=2\n";
+
+        assert_eq!(
+            parse(PlaylistFormat::Pls, contents),
+            vec!["/music/Showtime.mp3", "/music/Cascade.mp3"]
+        );
+    }
+
+    #[test]
+    fn test_write_round_trips() {
+        let songs = [
+            DbSong::fixture("Showtime", 199.6),
+            DbSong::fixture("Cascade", 1003.0),
+        ];
+
+        assert_eq!(
+            write(PlaylistFormat::M3u, &songs),
+            "#EXTM3U\n#EXTINF:200,Toby Fox - Showtime\n/music/Showtime.mp3\n#EXTINF:1003,Toby Fox - Cascade\n/music/Cascade.mp3\n"
+        );
+        for format in [PlaylistFormat::M3u8, PlaylistFormat::Pls] {
+            assert_eq!(
+                parse(format, &write(format, &songs)),
+                vec!["/music/Showtime.mp3", "/music/Cascade.mp3"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_latin1_m3u() {
+        let bytes = b"/music/Caf\xe9.mp3".to_vec();
+
+        assert_eq!(decode(PlaylistFormat::M3u, bytes), "/music/Café.mp3");
+    }
+
+    #[test]
+    fn test_resolve_entry() {
+        let music_path = Path::new("/srv/music");
+        let playlist_dir = Path::new("/srv/music/playlists");
+
+        assert_eq!(
+            resolve_entry(
+                "/srv/music/Vol. 5/Cascade.mp3",
+                playlist_dir,
+                Some(music_path)
+            ),
+            Some(PathBuf::from("/music/Vol. 5/Cascade.mp3"))
+        );
+        assert_eq!(
+            resolve_entry("../Showtime.mp3", playlist_dir, Some(music_path)),
+            Some(PathBuf::from("/music/Showtime.mp3"))
+        );
+        assert_eq!(
+            resolve_entry("file:///music/Showtime.mp3", playlist_dir, None),
+            Some(PathBuf::from("/music/Showtime.mp3"))
+        );
+        assert_eq!(
+            resolve_entry(
+                "file:///music/Vol.%205/Cascade%20(Beta).mp3",
+                playlist_dir,
+                None
+            ),
+            Some(PathBuf::from("/music/Vol. 5/Cascade (Beta).mp3"))
+        );
+        assert_eq!(
+            resolve_entry("https://example.com/stream.mp3", playlist_dir, None),
+            None
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Prometheus metrics for all of our binaries.
+//!
+//! Metrics are plain statics, so anything can record into them without passing a registry
+//! around. Each binary serves the ones it records on its `/metrics` endpoint.
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec,
+    IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+/// Seconds, from 5ms to 10s
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+fn counter(name: &str, help: &str) -> IntCounter {
+    IntCounter::new(name, help).unwrap()
+}
+
+fn counter_vec(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    IntCounterVec::new(Opts::new(name, help), labels).unwrap()
+}
+
+fn registry(metrics: Vec<Box<dyn Collector>>) -> Registry {
+    let registry = Registry::new();
+    for metric in metrics {
+        registry.register(metric).unwrap();
+    }
+
+    registry
+}
+
+/// Renders the metrics in `registry` for a `/metrics` response.
+pub fn render(registry: &Registry) -> String {
+    let mut out = vec![];
+    if let Err(e) = TextEncoder::new().encode(&registry.gather(), &mut out) {
+        tracing::error!("Failed to render metrics: {}", e);
+    }
+
+    String::from_utf8(out).unwrap_or_default()
+}
+
+/// Content type of [`render`]'s output
+pub const CONTENT_TYPE: &str = prometheus::TEXT_FORMAT;
+
+pub static LIQUIDSOAP_COMMAND_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "liquidsoap_command_duration_seconds",
+            "Time Liquidsoap took to answer a command",
+        )
+        .buckets(LATENCY_BUCKETS.to_vec()),
+        &["command"],
+    )
+    .unwrap()
+});
+pub static LIQUIDSOAP_RECONNECTS: Lazy<IntCounter> =
+    Lazy::new(|| counter("liquidsoap_reconnects_total", "Reconnects to Liquidsoap"));
+pub static COOLDOWN_HITS: Lazy<IntCounterVec> = Lazy::new(|| {
+    counter_vec(
+        "cooldown_hits_total",
+        "Actions refused because of a cooldown",
+        &["cooldown"],
+    )
+});
+
+pub static BYERS_COMMANDS: Lazy<IntCounterVec> =
+    Lazy::new(|| counter_vec("byers_commands_total", "Commands invoked", &["command"]));
+pub static BYERS_MINIGAME_PLAYS: Lazy<IntCounterVec> =
+    Lazy::new(|| counter_vec("byers_minigame_plays_total", "Minigames played", &["game"]));
+pub static BYERS_MINIGAME_PAYOUTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    counter_vec(
+        "byers_minigame_payouts_total",
+        "Boondollars paid out by minigames",
+        &["game"],
+    )
+});
+
+pub static LANGLEY_TRACKS_PLAYED: Lazy<IntCounterVec> = Lazy::new(|| {
+    counter_vec(
+        "langley_tracks_played_total",
+        "Tracks reported by Liquidsoap",
+        &["source"],
+    )
+});
+pub static LANGLEY_UNKNOWN_FILES: Lazy<IntCounter> = Lazy::new(|| {
+    counter(
+        "langley_unknown_files_total",
+        "Tracks played that aren't indexed",
+    )
+});
+pub static LANGLEY_REJECTED_REQUESTS: Lazy<IntCounter> = Lazy::new(|| {
+    counter(
+        "langley_rejected_requests_total",
+        "Requests rejected for a missing or wrong secret",
+    )
+});
+
+pub static FROHIKE_INDEXING_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    Histogram::with_opts(
+        HistogramOpts::new(
+            "frohike_indexing_duration_seconds",
+            "Time it took to index the music library",
+        )
+        // 1s to about an hour
+        .buckets(exponential_buckets(1.0, 2.0, 12).unwrap()),
+    )
+    .unwrap()
+});
+pub static FROHIKE_INDEXING_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    counter(
+        "frohike_indexing_failures_total",
+        "Files that could not be indexed",
+    )
+});
+
+pub static BYERS_METRICS: Lazy<Registry> = Lazy::new(|| {
+    registry(vec![
+        Box::new(BYERS_COMMANDS.clone()),
+        Box::new(BYERS_MINIGAME_PLAYS.clone()),
+        Box::new(BYERS_MINIGAME_PAYOUTS.clone()),
+        Box::new(COOLDOWN_HITS.clone()),
+        Box::new(LIQUIDSOAP_COMMAND_SECONDS.clone()),
+        Box::new(LIQUIDSOAP_RECONNECTS.clone()),
+    ])
+});
+pub static LANGLEY_METRICS: Lazy<Registry> = Lazy::new(|| {
+    registry(vec![
+        Box::new(LANGLEY_TRACKS_PLAYED.clone()),
+        Box::new(LANGLEY_UNKNOWN_FILES.clone()),
+        Box::new(LANGLEY_REJECTED_REQUESTS.clone()),
+    ])
+});
+pub static FROHIKE_METRICS: Lazy<Registry> = Lazy::new(|| {
+    registry(vec![
+        Box::new(FROHIKE_INDEXING_SECONDS.clone()),
+        Box::new(FROHIKE_INDEXING_FAILURES.clone()),
+    ])
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        BYERS_COMMANDS.with_label_values(&["song request"]).inc();
+
+        let rendered = render(&BYERS_METRICS);
+        assert!(rendered.contains("byers_commands_total{command=\"song request\"} 1\n"));
+        // unlabelled metrics show up before anything was recorded
+        assert!(rendered.contains("# TYPE liquidsoap_reconnects_total counter\n"));
+        assert!(render(&LANGLEY_METRICS).contains("langley_rejected_requests_total 0\n"));
+        assert!(render(&FROHIKE_METRICS).contains("# TYPE frohike_indexing_duration_seconds"));
+    }
+}
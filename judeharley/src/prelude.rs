@@ -29,6 +29,30 @@ pub enum JudeHarleyError {
     Id3(#[from] id3::Error),
     #[error(transparent)]
     AudioTags(#[from] audiotags::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Spreadsheet(#[from] calamine::Error),
+    #[error("invalid Streamlabs export: {0}")]
+    InvalidImport(String),
+    #[error("unsupported playlist format `{0}`, expected m3u, m3u8 or pls")]
+    UnsupportedPlaylistFormat(String),
+    #[error("could not connect to Liquidsoap at {target} after {attempts} attempts: {source}")]
+    LiquidsoapConnect {
+        target: String,
+        attempts: u32,
+        source: std::io::Error,
+    },
+    #[error("Liquidsoap did not answer `{0}` in time")]
+    LiquidsoapTimeout(String),
+    #[error("the Liquidsoap client has shut down")]
+    LiquidsoapClosed,
+    #[error("reconnecting to Liquidsoap, `{0}` was not sent")]
+    LiquidsoapReconnecting(String),
+    #[error("unexpected response from Liquidsoap to `{command}`: {response}")]
+    LiquidsoapProtocol { command: String, response: String },
+    #[error("unsupported event schema version {0}")]
+    UnsupportedEventVersion(u32),
 }
 
 pub trait DiscordTimestamp {
@@ -0,0 +1,1211 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDateTime};
+use fred::pool::RedisPool;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    communication::{LiquidsoapClient, LiquidsoapCommunication, RequestQueue},
+    cooldowns::{is_on_cooldown, reset_cooldown, set_cooldown, UserCooldownKey},
+    db::{DbRequestPolicy, DbSong, DbSongRequest, DbUser},
+    prelude::*,
+};
+
+/// Name of the cooldown users get after requesting a song
+pub const USER_COOLDOWN_KEY: &str = "song_request";
+
+/// A request that is waiting in one of Liquidsoap's queues.
+#[derive(Debug, Clone)]
+pub struct QueuedRequest {
+    pub request_id: u32,
+    pub queue: RequestQueue,
+    /// 1-based position across all queues, in the order the requests will be played.
+    pub position: usize,
+    /// `None` if the file Liquidsoap reports isn't indexed.
+    pub song: Option<DbSong>,
+    /// The song request that put it there, if it was made through us.
+    pub request: Option<DbSongRequest>,
+    /// Estimated time until the request starts playing.
+    pub starts_in: Duration,
+}
+
+/// Estimates when each queued track starts, given the seconds left of the current track and
+/// the duration of each queued track, if known.
+///
+/// Unknown durations count as zero, so estimates can only be too early.
+fn estimate_start_times(remaining: f64, durations: &[Option<f64>]) -> Vec<Duration> {
+    let mut elapsed = remaining.max(0.0);
+
+    durations
+        .iter()
+        .map(|duration| {
+            let starts_in = Duration::milliseconds((elapsed * 1000.0) as i64);
+            elapsed += duration.unwrap_or(0.0);
+            starts_in
+        })
+        .collect()
+}
+
+/// How many seconds the current track still has to play, as far as we know.
+async fn remaining_on_air(db: &PgPool, now: NaiveDateTime) -> Result<f64> {
+    let (Some(song), Some(played_at)) = (
+        DbSong::last_played_song(db).await?,
+        DbSong::last_played_at(db).await?,
+    ) else {
+        return Ok(0.0);
+    };
+
+    let elapsed = (now - played_at).num_milliseconds() as f64 / 1000.0;
+    Ok((song.duration - elapsed).max(0.0))
+}
+
+/// Lists everything in the priority and song request queues, in the order it will be played.
+pub async fn queued_requests<C>(
+    db: &PgPool,
+    comms: &LiquidsoapClient<C>,
+) -> Result<Vec<QueuedRequest>> {
+    let mut queued = vec![];
+    for queue in [RequestQueue::PriorityRequests, RequestQueue::SongRequests] {
+        for request_id in comms.queue(queue).await? {
+            queued.push((queue, request_id));
+        }
+    }
+
+    let request_ids = queued.iter().map(|(_, id)| *id as i32).collect::<Vec<_>>();
+    let mut requests: HashMap<i32, DbSongRequest> = HashMap::new();
+    for request in DbSongRequest::fetch_by_request_ids(db, &request_ids).await? {
+        if let Some(request_id) = request.request_id {
+            // rows are sorted newest first, keep the newest one per ID
+            requests.entry(request_id).or_insert(request);
+        }
+    }
+
+    // one listing and one query for all of them, instead of asking for every request
+    let filenames = comms
+        .song_requests()
+        .await?
+        .into_iter()
+        .filter_map(|item| Some((item.request_id?, item.filename)))
+        .collect::<HashMap<_, _>>();
+    let file_paths = filenames.values().cloned().collect::<Vec<_>>();
+    let songs_by_path = DbSong::fetch_by_paths(db, &file_paths)
+        .await?
+        .into_iter()
+        .map(|song| (song.file_path.clone(), song))
+        .collect::<HashMap<_, _>>();
+    let songs = queued
+        .iter()
+        .map(|(_, request_id)| {
+            filenames
+                .get(request_id)
+                .and_then(|filename| songs_by_path.get(filename))
+                .cloned()
+        })
+        .collect::<Vec<_>>();
+
+    let remaining = remaining_on_air(db, chrono::Utc::now().naive_utc()).await?;
+    let durations = songs
+        .iter()
+        .map(|song| song.as_ref().map(|s| s.duration))
+        .collect::<Vec<_>>();
+    let start_times = estimate_start_times(remaining, &durations);
+
+    let result = queued
+        .into_iter()
+        .zip(songs)
+        .zip(start_times)
+        .enumerate()
+        .map(|(i, (((queue, request_id), song), starts_in))| {
+            // request IDs are reused after a restart, so make sure it's the same song
+            let request = requests.remove(&(request_id as i32)).filter(|request| {
+                song.as_ref()
+                    .map(|song| song.file_hash == request.song_id)
+                    .unwrap_or(false)
+            });
+
+            QueuedRequest {
+                request_id,
+                queue,
+                position: i + 1,
+                song,
+                request,
+                starts_in,
+            }
+        })
+        .collect();
+
+    Ok(result)
+}
+
+/// Removes the user's most recent song request that hasn't started playing yet, lifts their
+/// request cooldown and refunds what they paid for it.
+///
+/// Dropping the request also lifts the song's cooldown. Returns the cancelled request, or `None`
+/// if the user has nothing in the queue.
+pub async fn cancel_request<C>(
+    db: &PgPool,
+    redis_pool: &RedisPool,
+    comms: &LiquidsoapClient<C>,
+    user_id: u64,
+) -> Result<Option<QueuedRequest>> {
+    let Some(queued) = queued_requests(db, comms)
+        .await?
+        .into_iter()
+        .filter(|q| {
+            q.request
+                .as_ref()
+                .map(|r| r.user_id == user_id as i64)
+                .unwrap_or(false)
+        })
+        .last()
+    else {
+        return Ok(None);
+    };
+
+    match comms.remove_request(queued.queue, queued.request_id).await {
+        Ok(()) => {}
+        // it started playing in the meantime
+        Err(JudeHarleyError::LiquidsoapProtocol { .. }) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    if let Some(request) = &queued.request {
+        if request.cancel(db).await? {
+            let key = UserCooldownKey::new(user_id as i64, USER_COOLDOWN_KEY);
+            let now = chrono::Utc::now().naive_utc();
+            // a refunded cooldown skip doesn't skip the cooldown anymore
+            match request.bypassed_cooldown_until.filter(|until| *until > now) {
+                Some(until) => {
+                    set_cooldown(redis_pool, key, (until - now).num_seconds().max(1)).await?
+                }
+                None => reset_cooldown(redis_pool, key).await?,
+            }
+        }
+    }
+
+    Ok(Some(queued))
+}
+
+/// How a song request is paid for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestKind {
+    /// Costs the policy's request price, which is free by default
+    #[default]
+    Regular,
+    /// Can be made while the user is on cooldown, at the cooldown bypass price. Costs the
+    /// regular price if they aren't.
+    BypassCooldown,
+    /// Goes to the priority queue at the premium price
+    Premium,
+}
+
+impl RequestKind {
+    pub fn queue(self) -> RequestQueue {
+        match self {
+            RequestKind::Premium => RequestQueue::PriorityRequests,
+            RequestKind::Regular | RequestKind::BypassCooldown => RequestQueue::SongRequests,
+        }
+    }
+}
+
+/// Why a song request was turned down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestDenied {
+    RequestsClosed,
+    /// The guild doesn't sell this kind of request
+    NotForSale,
+    /// The user requested a song recently and can request again at the given time
+    UserCooldown(NaiveDateTime),
+    SongNotFound,
+    /// The user already has as many requests waiting as allowed
+    TooManyPending(i32),
+    /// The song request queue is as long as allowed
+    QueueFull(i32),
+    NothingPlaying,
+    CurrentlyPlaying,
+    /// The song was requested recently and can be requested again at the given time
+    SongCooldown(NaiveDateTime),
+    /// The user has fewer Boondollars than the request costs
+    InsufficientFunds {
+        price: i32,
+        balance: i32,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum RequestOutcome {
+    Requested {
+        song: DbSong,
+        /// ID Liquidsoap gave the request
+        request_id: u32,
+        /// When the user can request again
+        next_request_at: NaiveDateTime,
+        /// Boondollars the user paid
+        price: i32,
+    },
+    /// Liquidsoap didn't confirm the request, but may well have queued it. The user keeps paying
+    /// and is on cooldown, but the request isn't recorded, so it can't be cancelled or announced.
+    Unconfirmed {
+        song: DbSong,
+        /// When the user can request again
+        next_request_at: NaiveDateTime,
+        /// Boondollars the user paid
+        price: i32,
+    },
+    Denied(RequestDenied),
+}
+
+/// Everything the request rules look at, fetched before checking them.
+#[derive(Debug, Clone, Default)]
+struct RequestState {
+    policy: DbRequestPolicy,
+    kind: RequestKind,
+    song: Option<DbSong>,
+    user_cooldown: Option<NaiveDateTime>,
+    /// Requests of the user that are still waiting in a queue
+    pending_requests: i64,
+    queue_length: usize,
+    /// Hash of the song on air
+    currently_playing: Option<String>,
+    last_requested: Option<NaiveDateTime>,
+    /// Boondollars of the user
+    balance: i32,
+}
+
+impl RequestState {
+    /// Returns the song and what it costs to request it, if the request is allowed.
+    fn check(&self, now: NaiveDateTime) -> std::result::Result<(&DbSong, i32), RequestDenied> {
+        let policy = &self.policy;
+        if !policy.requests_open {
+            return Err(RequestDenied::RequestsClosed);
+        }
+
+        let user_cooldown = self.user_cooldown.filter(|over| *over > now);
+        let price = match (self.kind, user_cooldown) {
+            (RequestKind::BypassCooldown, Some(_)) => policy.cooldown_bypass_price,
+            (_, Some(over)) => return Err(RequestDenied::UserCooldown(over)),
+            (RequestKind::Premium, None) => policy.premium_price,
+            (RequestKind::Regular | RequestKind::BypassCooldown, None) => Some(policy.price),
+        };
+        let Some(price) = price else {
+            return Err(RequestDenied::NotForSale);
+        };
+
+        let Some(song) = &self.song else {
+            return Err(RequestDenied::SongNotFound);
+        };
+
+        if let Some(max) = policy.max_pending {
+            if self.pending_requests >= max as i64 {
+                return Err(RequestDenied::TooManyPending(max));
+            }
+        }
+        // premium requests skip the song request queue
+        if let Some(max) = policy
+            .max_queue_length
+            .filter(|_| self.kind != RequestKind::Premium)
+        {
+            if self.queue_length >= max.max(0) as usize {
+                return Err(RequestDenied::QueueFull(max));
+            }
+        }
+
+        match &self.currently_playing {
+            None => return Err(RequestDenied::NothingPlaying),
+            Some(hash) if *hash == song.file_hash => return Err(RequestDenied::CurrentlyPlaying),
+            Some(_) => {}
+        }
+
+        if let Some(last_requested) = self.last_requested {
+            let over = last_requested + policy.song_cooldown(song.duration);
+            if over > now {
+                return Err(RequestDenied::SongCooldown(over));
+            }
+        }
+
+        if self.balance < price {
+            return Err(RequestDenied::InsufficientFunds {
+                price,
+                balance: self.balance,
+            });
+        }
+
+        Ok((song, price))
+    }
+}
+
+/// Where [`RequestService`] keeps what it knows about songs, users and their requests.
+#[async_trait::async_trait]
+pub trait RequestStore: Send + Sync {
+    async fn song(&self, song_hash: &str) -> Result<Option<DbSong>>;
+    async fn last_requested(&self, song: &DbSong) -> Result<NaiveDateTime>;
+    /// Requests of the user that are still waiting in a queue
+    async fn pending_requests(&self, user_id: u64) -> Result<i64>;
+    /// The song on air
+    async fn currently_playing(&self) -> Result<Option<DbSong>>;
+    /// When the user can request a song again, if they can't right now
+    async fn user_cooldown(&self, user_id: u64) -> Result<Option<NaiveDateTime>>;
+    async fn set_user_cooldown(&self, user_id: u64, seconds: i64) -> Result<()>;
+    async fn balance(&self, user_id: u64) -> Result<i32>;
+    /// Takes `price` Boondollars from the user, returns `false` if they don't have that many
+    async fn charge(&self, user_id: u64, price: i32) -> Result<bool>;
+    async fn refund(&self, user_id: u64, price: i32) -> Result<()>;
+    /// Remembers that the user requested the song, which Liquidsoap queued as `request_id`
+    #[allow(clippy::too_many_arguments)]
+    async fn record(
+        &self,
+        user_id: u64,
+        guild_id: Option<i64>,
+        song: &DbSong,
+        queue: RequestQueue,
+        request_id: u32,
+        price: i32,
+        bypassed_cooldown_until: Option<NaiveDateTime>,
+    ) -> Result<()>;
+}
+
+/// Keeps requests in Postgres and cooldowns in Redis.
+pub struct DbRequestStore<'a> {
+    db: &'a PgPool,
+    redis_pool: &'a RedisPool,
+}
+
+impl<'a> DbRequestStore<'a> {
+    pub fn new(db: &'a PgPool, redis_pool: &'a RedisPool) -> Self {
+        Self { db, redis_pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> RequestStore for DbRequestStore<'a> {
+    async fn song(&self, song_hash: &str) -> Result<Option<DbSong>> {
+        DbSong::fetch_from_hash(self.db, song_hash).await
+    }
+
+    async fn last_requested(&self, song: &DbSong) -> Result<NaiveDateTime> {
+        song.last_requested(self.db).await
+    }
+
+    async fn pending_requests(&self, user_id: u64) -> Result<i64> {
+        DbSongRequest::count_pending(self.db, user_id as i64).await
+    }
+
+    async fn currently_playing(&self) -> Result<Option<DbSong>> {
+        DbSong::last_played_song(self.db).await
+    }
+
+    async fn user_cooldown(&self, user_id: u64) -> Result<Option<NaiveDateTime>> {
+        is_on_cooldown(
+            self.redis_pool,
+            UserCooldownKey::new(user_id as i64, USER_COOLDOWN_KEY),
+        )
+        .await
+    }
+
+    async fn set_user_cooldown(&self, user_id: u64, seconds: i64) -> Result<()> {
+        set_cooldown(
+            self.redis_pool,
+            UserCooldownKey::new(user_id as i64, USER_COOLDOWN_KEY),
+            seconds,
+        )
+        .await
+    }
+
+    async fn balance(&self, user_id: u64) -> Result<i32> {
+        Ok(DbUser::fetch(self.db, user_id as i64)
+            .await?
+            .map(|user| user.boonbucks)
+            .unwrap_or_default())
+    }
+
+    async fn charge(&self, user_id: u64, price: i32) -> Result<bool> {
+        Ok(DbUser::spend_boonbucks(self.db, user_id as i64, price)
+            .await?
+            .is_some())
+    }
+
+    async fn refund(&self, user_id: u64, price: i32) -> Result<()> {
+        DbUser::refund_boonbucks(self.db, user_id as i64, price).await
+    }
+
+    async fn record(
+        &self,
+        user_id: u64,
+        guild_id: Option<i64>,
+        song: &DbSong,
+        queue: RequestQueue,
+        request_id: u32,
+        price: i32,
+        bypassed_cooldown_until: Option<NaiveDateTime>,
+    ) -> Result<()> {
+        song.request(
+            self.db,
+            user_id,
+            guild_id,
+            queue,
+            request_id,
+            price,
+            bypassed_cooldown_until,
+        )
+        .await
+    }
+}
+
+/// Applies the song request rules and queues the songs that pass them. Every way of
+/// requesting songs should go through this.
+pub struct RequestService<'a, C, S = DbRequestStore<'a>> {
+    store: S,
+    comms: &'a LiquidsoapClient<C>,
+    policy: DbRequestPolicy,
+    /// Discord guild the requests are made in
+    guild_id: Option<i64>,
+}
+
+impl<'a, C> RequestService<'a, C>
+where
+    C: LiquidsoapCommunication<Error = JudeHarleyError> + Send + 'static,
+{
+    pub fn new(db: &'a PgPool, redis_pool: &'a RedisPool, comms: &'a LiquidsoapClient<C>) -> Self {
+        Self::with_store(DbRequestStore::new(db, redis_pool), comms)
+    }
+}
+
+impl<'a, C, S> RequestService<'a, C, S>
+where
+    C: LiquidsoapCommunication<Error = JudeHarleyError> + Send + 'static,
+    S: RequestStore,
+{
+    pub fn with_store(store: S, comms: &'a LiquidsoapClient<C>) -> Self {
+        Self {
+            store,
+            comms,
+            policy: DbRequestPolicy::default(),
+            guild_id: None,
+        }
+    }
+
+    /// Applies a guild's rules instead of the default ones.
+    pub fn with_policy(mut self, policy: DbRequestPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Records the requests as made in `guild_id`, so their announcements only ping the
+    /// requester there.
+    pub fn in_guild(mut self, guild_id: Option<i64>) -> Self {
+        self.guild_id = guild_id;
+        self
+    }
+
+    pub fn policy(&self) -> &DbRequestPolicy {
+        &self.policy
+    }
+
+    /// When the user can request a song again, if they can't right now.
+    pub async fn user_cooldown(&self, user_id: u64) -> Result<Option<NaiveDateTime>> {
+        self.store.user_cooldown(user_id).await
+    }
+
+    async fn state(
+        &self,
+        user_id: u64,
+        song_hash: &str,
+        kind: RequestKind,
+    ) -> Result<RequestState> {
+        let song = self.store.song(song_hash).await?;
+        let last_requested = match &song {
+            Some(song) => Some(self.store.last_requested(song).await?),
+            None => None,
+        };
+
+        let pending_requests = match self.policy.max_pending {
+            Some(_) => self.store.pending_requests(user_id).await?,
+            None => 0,
+        };
+        let queue_length = match self.policy.max_queue_length {
+            Some(_) => self.comms.queue(RequestQueue::SongRequests).await?.len(),
+            None => 0,
+        };
+
+        Ok(RequestState {
+            policy: self.policy.clone(),
+            kind,
+            song,
+            user_cooldown: self.store.user_cooldown(user_id).await?,
+            pending_requests,
+            queue_length,
+            currently_playing: self
+                .store
+                .currently_playing()
+                .await?
+                .map(|song| song.file_hash),
+            last_requested,
+            balance: self.store.balance(user_id).await?,
+        })
+    }
+
+    /// Requests a song for the user, if the rules allow it, and puts them on cooldown.
+    pub async fn request(&self, user_id: u64, song_hash: &str) -> Result<RequestOutcome> {
+        self.request_with(user_id, song_hash, RequestKind::Regular)
+            .await
+    }
+
+    /// Like [`RequestService::request`], charging the user for the kind of request.
+    ///
+    /// The price is taken before the song is pushed to Liquidsoap and refunded if the request
+    /// can't be queued or recorded. If Liquidsoap got the push but didn't answer it, the song is
+    /// most likely queued anyway, so the price is kept and the request is
+    /// [`RequestOutcome::Unconfirmed`].
+    pub async fn request_with(
+        &self,
+        user_id: u64,
+        song_hash: &str,
+        kind: RequestKind,
+    ) -> Result<RequestOutcome> {
+        let state = self.state(user_id, song_hash, kind).await?;
+        let now = chrono::Utc::now().naive_utc();
+        let (song, price) = match state.check(now) {
+            Ok((song, price)) => (song.clone(), price),
+            Err(denied) => return Ok(RequestOutcome::Denied(denied)),
+        };
+        let bypassed_cooldown_until = state
+            .user_cooldown
+            .filter(|over| kind == RequestKind::BypassCooldown && *over > now);
+
+        if price > 0 && !self.store.charge(user_id, price).await? {
+            // they spent it somewhere else since we checked
+            return Ok(RequestOutcome::Denied(RequestDenied::InsufficientFunds {
+                price,
+                balance: self.store.balance(user_id).await?,
+            }));
+        }
+
+        let queue = kind.queue();
+        let request_id = match self.comms.push(queue, &song.file_path).await {
+            Ok(request_id) => request_id,
+            // the push never left the client, so nothing was queued
+            Err(
+                e
+                @ (JudeHarleyError::LiquidsoapReconnecting(_) | JudeHarleyError::LiquidsoapClosed),
+            ) => {
+                tracing::error!("Failed to queue {} for {}: {}", song.file_path, user_id, e);
+                self.refund(user_id, price).await;
+                return Err(e);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Liquidsoap did not confirm {} for {}: {}",
+                    song.file_path,
+                    user_id,
+                    e
+                );
+                self.start_user_cooldown(user_id).await;
+                return Ok(RequestOutcome::Unconfirmed {
+                    song,
+                    next_request_at: now + Duration::seconds(self.policy.user_cooldown as i64),
+                    price,
+                });
+            }
+        };
+        if let Err(e) = self
+            .store
+            .record(
+                user_id,
+                self.guild_id,
+                &song,
+                queue,
+                request_id,
+                price,
+                bypassed_cooldown_until,
+            )
+            .await
+        {
+            tracing::error!("Failed to record the request of {}: {}", user_id, e);
+            // it couldn't be cancelled or announced without a record
+            if let Err(e) = self.comms.remove_request(queue, request_id).await {
+                tracing::error!("Failed to remove request {}: {}", request_id, e);
+            }
+            self.refund(user_id, price).await;
+            return Err(e);
+        }
+
+        self.start_user_cooldown(user_id).await;
+
+        Ok(RequestOutcome::Requested {
+            song,
+            request_id,
+            next_request_at: now + Duration::seconds(self.policy.user_cooldown as i64),
+            price,
+        })
+    }
+
+    /// Puts the user on cooldown once their song is queued. The request stands either way, so
+    /// failures are only logged.
+    async fn start_user_cooldown(&self, user_id: u64) {
+        // Redis won't take a cooldown that's over right away
+        if self.policy.user_cooldown <= 0 {
+            return;
+        }
+
+        if let Err(e) = self
+            .store
+            .set_user_cooldown(user_id, self.policy.user_cooldown as i64)
+            .await
+        {
+            tracing::error!("Failed to put {} on cooldown: {}", user_id, e);
+        }
+    }
+
+    /// Gives back what the user paid for a request that fell through. Failures are only
+    /// logged, so they don't hide why it fell through.
+    async fn refund(&self, user_id: u64, price: i32) {
+        if price == 0 {
+            return;
+        }
+
+        if let Err(e) = self.store.refund(user_id, price).await {
+            tracing::error!(
+                "Failed to refund {} Boondollars to {}: {}",
+                price,
+                user_id,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::communication::{mock::MockLiquidsoap, ByersUnixStream, ClientOptions};
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 11, 24)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn allowed_state() -> RequestState {
+        RequestState {
+            song: Some(DbSong::fixture("abc", 200.0)),
+            currently_playing: Some("def".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_song_cooldown() {
+        let policy = DbRequestPolicy::default();
+        assert_eq!(policy.song_cooldown(120.0), Duration::seconds(1800));
+        assert_eq!(policy.song_cooldown(300.0), Duration::seconds(3600));
+        assert_eq!(policy.song_cooldown(900.0), Duration::seconds(5413));
+    }
+
+    #[test]
+    fn test_request_allowed() {
+        let state = allowed_state();
+        let (song, price) = state.check(now()).unwrap();
+        assert_eq!(song.file_hash, "abc");
+        assert_eq!(price, 0);
+
+        let state = RequestState {
+            user_cooldown: Some(now() - Duration::seconds(1)),
+            last_requested: Some(now() - Duration::seconds(1800)),
+            ..allowed_state()
+        };
+        assert!(state.check(now()).is_ok());
+
+        let state = RequestState {
+            policy: DbRequestPolicy {
+                max_pending: Some(2),
+                max_queue_length: Some(10),
+                ..Default::default()
+            },
+            pending_requests: 1,
+            queue_length: 9,
+            ..allowed_state()
+        };
+        assert!(state.check(now()).is_ok());
+    }
+
+    #[test]
+    fn test_request_denied() {
+        let over = now() + Duration::minutes(5);
+        let cases = [
+            (
+                RequestState {
+                    policy: DbRequestPolicy {
+                        requests_open: false,
+                        ..Default::default()
+                    },
+                    ..allowed_state()
+                },
+                RequestDenied::RequestsClosed,
+            ),
+            (
+                RequestState {
+                    user_cooldown: Some(over),
+                    song: None,
+                    ..allowed_state()
+                },
+                RequestDenied::UserCooldown(over),
+            ),
+            (
+                RequestState {
+                    song: None,
+                    ..allowed_state()
+                },
+                RequestDenied::SongNotFound,
+            ),
+            (
+                RequestState {
+                    policy: DbRequestPolicy {
+                        max_pending: Some(2),
+                        ..Default::default()
+                    },
+                    pending_requests: 2,
+                    ..allowed_state()
+                },
+                RequestDenied::TooManyPending(2),
+            ),
+            (
+                RequestState {
+                    policy: DbRequestPolicy {
+                        max_queue_length: Some(0),
+                        ..Default::default()
+                    },
+                    ..allowed_state()
+                },
+                RequestDenied::QueueFull(0),
+            ),
+            (
+                RequestState {
+                    currently_playing: None,
+                    ..allowed_state()
+                },
+                RequestDenied::NothingPlaying,
+            ),
+            (
+                RequestState {
+                    currently_playing: Some("abc".to_string()),
+                    ..allowed_state()
+                },
+                RequestDenied::CurrentlyPlaying,
+            ),
+            (
+                RequestState {
+                    last_requested: Some(now() - Duration::seconds(1500)),
+                    ..allowed_state()
+                },
+                RequestDenied::SongCooldown(now() + Duration::seconds(300)),
+            ),
+            (
+                RequestState {
+                    policy: DbRequestPolicy {
+                        short_song_cooldown: 600,
+                        ..Default::default()
+                    },
+                    last_requested: Some(now() - Duration::seconds(500)),
+                    ..allowed_state()
+                },
+                RequestDenied::SongCooldown(now() + Duration::seconds(100)),
+            ),
+        ];
+
+        for (state, denied) in cases {
+            assert_eq!(state.check(now()).unwrap_err(), denied);
+        }
+    }
+
+    fn priced_policy() -> DbRequestPolicy {
+        DbRequestPolicy {
+            price: 10,
+            cooldown_bypass_price: Some(50),
+            premium_price: Some(100),
+            max_queue_length: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_request_prices() {
+        let on_cooldown = Some(now() + Duration::minutes(5));
+        let cases = [
+            (RequestKind::Regular, None, 10),
+            (RequestKind::BypassCooldown, None, 10),
+            (RequestKind::BypassCooldown, on_cooldown, 50),
+            (RequestKind::Premium, None, 100),
+        ];
+
+        for (kind, user_cooldown, price) in cases {
+            let state = RequestState {
+                policy: DbRequestPolicy {
+                    max_queue_length: None,
+                    ..priced_policy()
+                },
+                kind,
+                user_cooldown,
+                balance: 100,
+                ..allowed_state()
+            };
+            assert_eq!(state.check(now()).unwrap().1, price, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_paid_request_denied() {
+        let over = now() + Duration::minutes(5);
+        let cases = [
+            (
+                RequestState {
+                    policy: DbRequestPolicy {
+                        max_queue_length: None,
+                        ..priced_policy()
+                    },
+                    balance: 9,
+                    ..allowed_state()
+                },
+                RequestDenied::InsufficientFunds {
+                    price: 10,
+                    balance: 9,
+                },
+            ),
+            (
+                RequestState {
+                    kind: RequestKind::BypassCooldown,
+                    user_cooldown: Some(over),
+                    ..allowed_state()
+                },
+                RequestDenied::NotForSale,
+            ),
+            (
+                RequestState {
+                    kind: RequestKind::Premium,
+                    ..allowed_state()
+                },
+                RequestDenied::NotForSale,
+            ),
+            (
+                RequestState {
+                    policy: priced_policy(),
+                    kind: RequestKind::Premium,
+                    user_cooldown: Some(over),
+                    balance: 1000,
+                    ..allowed_state()
+                },
+                RequestDenied::UserCooldown(over),
+            ),
+            (
+                RequestState {
+                    policy: priced_policy(),
+                    kind: RequestKind::BypassCooldown,
+                    user_cooldown: Some(over),
+                    balance: 1000,
+                    ..allowed_state()
+                },
+                RequestDenied::QueueFull(0),
+            ),
+        ];
+
+        for (state, denied) in cases {
+            assert_eq!(state.check(now()).unwrap_err(), denied);
+        }
+
+        // the priority queue isn't limited
+        let state = RequestState {
+            policy: priced_policy(),
+            kind: RequestKind::Premium,
+            balance: 100,
+            ..allowed_state()
+        };
+        assert!(state.check(now()).is_ok());
+    }
+
+    /// Keeps everything in memory, with one user and the songs it's given.
+    #[derive(Default)]
+    struct MockStore {
+        songs: Vec<DbSong>,
+        balance: Mutex<i32>,
+        fail_record: bool,
+        user_cooldown: Mutex<Option<i64>>,
+        requests: Mutex<Vec<(String, RequestQueue, u32, i32)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RequestStore for MockStore {
+        async fn song(&self, song_hash: &str) -> Result<Option<DbSong>> {
+            Ok(self
+                .songs
+                .iter()
+                .find(|s| s.file_hash == song_hash)
+                .cloned())
+        }
+
+        async fn last_requested(&self, _: &DbSong) -> Result<NaiveDateTime> {
+            Ok(NaiveDateTime::default())
+        }
+
+        async fn pending_requests(&self, _: u64) -> Result<i64> {
+            Ok(self.requests.lock().unwrap().len() as i64)
+        }
+
+        async fn currently_playing(&self) -> Result<Option<DbSong>> {
+            Ok(Some(DbSong::fixture("on air", 200.0)))
+        }
+
+        async fn user_cooldown(&self, _: u64) -> Result<Option<NaiveDateTime>> {
+            Ok(None)
+        }
+
+        async fn set_user_cooldown(&self, _: u64, seconds: i64) -> Result<()> {
+            *self.user_cooldown.lock().unwrap() = Some(seconds);
+            Ok(())
+        }
+
+        async fn balance(&self, _: u64) -> Result<i32> {
+            Ok(*self.balance.lock().unwrap())
+        }
+
+        async fn charge(&self, _: u64, price: i32) -> Result<bool> {
+            let mut balance = self.balance.lock().unwrap();
+            if *balance < price {
+                return Ok(false);
+            }
+            *balance -= price;
+            Ok(true)
+        }
+
+        async fn refund(&self, _: u64, price: i32) -> Result<()> {
+            *self.balance.lock().unwrap() += price;
+            Ok(())
+        }
+
+        async fn record(
+            &self,
+            _: u64,
+            _: Option<i64>,
+            song: &DbSong,
+            queue: RequestQueue,
+            request_id: u32,
+            price: i32,
+            _: Option<NaiveDateTime>,
+        ) -> Result<()> {
+            if self.fail_record {
+                return Err(JudeHarleyError::Io(std::io::Error::other(
+                    "database is down",
+                )));
+            }
+            self.requests
+                .lock()
+                .unwrap()
+                .push((song.file_hash.clone(), queue, request_id, price));
+            Ok(())
+        }
+    }
+
+    fn mock_store(balance: i32) -> MockStore {
+        MockStore {
+            songs: vec![DbSong::fixture("abc", 200.0)],
+            balance: Mutex::new(balance),
+            ..Default::default()
+        }
+    }
+
+    fn service(
+        store: MockStore,
+        client: &LiquidsoapClient<ByersUnixStream>,
+    ) -> RequestService<'_, ByersUnixStream, MockStore> {
+        RequestService::with_store(store, client).with_policy(DbRequestPolicy {
+            max_queue_length: None,
+            ..priced_policy()
+        })
+    }
+
+    async fn client(server: &MockLiquidsoap) -> LiquidsoapClient<ByersUnixStream> {
+        LiquidsoapClient::spawn(
+            server.connect().await.unwrap(),
+            ClientOptions {
+                command_timeout: std::time::Duration::from_millis(100),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_service_denies_request() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = client(&server).await;
+        let service = service(mock_store(5), &client);
+
+        let outcome = service
+            .request_with(1, "abc", RequestKind::Regular)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            outcome,
+            RequestOutcome::Denied(RequestDenied::InsufficientFunds {
+                price: 10,
+                balance: 5
+            })
+        ));
+        assert!(server.srq().await.is_empty());
+        assert!(service.store.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_service_queues_request() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = client(&server).await;
+        let service = service(mock_store(150), &client);
+
+        let outcome = service
+            .request_with(1, "abc", RequestKind::Premium)
+            .await
+            .unwrap();
+
+        let RequestOutcome::Requested {
+            request_id, price, ..
+        } = outcome
+        else {
+            panic!("request was denied: {:?}", outcome);
+        };
+        assert_eq!(price, 100);
+
+        let prioq = server.prioq().await;
+        assert_eq!(prioq.len(), 1);
+        assert_eq!(prioq[0].id, request_id);
+        assert_eq!(prioq[0].filename, "/music/abc.mp3");
+        assert!(server.srq().await.is_empty());
+
+        let store = &service.store;
+        assert_eq!(*store.balance.lock().unwrap(), 50);
+        assert_eq!(
+            *store.requests.lock().unwrap(),
+            vec![(
+                "abc".to_string(),
+                RequestQueue::PriorityRequests,
+                request_id,
+                100
+            )]
+        );
+        assert_eq!(*store.user_cooldown.lock().unwrap(), Some(5400));
+    }
+
+    #[tokio::test]
+    async fn test_service_without_user_cooldown() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = client(&server).await;
+        let service = service(mock_store(150), &client).with_policy(DbRequestPolicy {
+            user_cooldown: 0,
+            ..Default::default()
+        });
+
+        let outcome = service
+            .request_with(1, "abc", RequestKind::Regular)
+            .await
+            .unwrap();
+
+        assert!(matches!(outcome, RequestOutcome::Requested { .. }));
+        assert_eq!(server.srq().await.len(), 1);
+        assert_eq!(*service.store.user_cooldown.lock().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_service_refunds_failed_push() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = client(&server).await;
+        let service = service(mock_store(150), &client);
+
+        // time out a command so the push comes in while the client is still reconnecting
+        let moved = server.socket_path().with_extension("moved");
+        std::fs::rename(server.socket_path(), &moved).unwrap();
+        server
+            .set_delay(std::time::Duration::from_millis(500))
+            .await;
+        assert!(client.volume().await.is_err());
+
+        let result = service.request_with(1, "abc", RequestKind::Premium).await;
+
+        assert!(matches!(
+            result,
+            Err(JudeHarleyError::LiquidsoapReconnecting(_))
+        ));
+        assert!(server.prioq().await.is_empty());
+        assert_eq!(*service.store.balance.lock().unwrap(), 150);
+        assert!(service.store.requests.lock().unwrap().is_empty());
+        assert_eq!(*service.store.user_cooldown.lock().unwrap(), None);
+        std::fs::rename(&moved, server.socket_path()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_service_keeps_charge_for_unconfirmed_push() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = client(&server).await;
+        let service = service(mock_store(150), &client);
+        server
+            .set_delay(std::time::Duration::from_millis(500))
+            .await;
+
+        let outcome = service
+            .request_with(1, "abc", RequestKind::Premium)
+            .await
+            .unwrap();
+
+        // Liquidsoap queued it, it just didn't answer in time
+        assert!(matches!(
+            outcome,
+            RequestOutcome::Unconfirmed { price: 100, .. }
+        ));
+        assert_eq!(server.prioq().await.len(), 1);
+        assert_eq!(*service.store.balance.lock().unwrap(), 50);
+        assert!(service.store.requests.lock().unwrap().is_empty());
+        assert_eq!(*service.store.user_cooldown.lock().unwrap(), Some(5400));
+    }
+
+    #[tokio::test]
+    async fn test_service_unqueues_unrecorded_request() {
+        let server = MockLiquidsoap::start().await.unwrap();
+        let client = client(&server).await;
+        let store = MockStore {
+            fail_record: true,
+            ..mock_store(150)
+        };
+        let service = service(store, &client);
+
+        let result = service.request_with(1, "abc", RequestKind::Premium).await;
+
+        assert!(result.is_err());
+        assert!(server.prioq().await.is_empty());
+        assert_eq!(*service.store.balance.lock().unwrap(), 150);
+        assert_eq!(*service.store.user_cooldown.lock().unwrap(), None);
+    }
+
+    #[test]
+    fn test_estimate_start_times() {
+        let start_times = estimate_start_times(30.0, &[Some(120.0), None, Some(60.5)]);
+
+        assert_eq!(
+            start_times,
+            vec![
+                Duration::seconds(30),
+                Duration::seconds(150),
+                Duration::seconds(150),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_estimate_start_times_overdue() {
+        let start_times = estimate_start_times(-5.0, &[Some(10.0)]);
+
+        assert_eq!(start_times, vec![Duration::zero()]);
+    }
+}
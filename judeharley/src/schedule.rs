@@ -0,0 +1,285 @@
+//! Scheduled programming: when a slot from the `schedule` table starts, its songs are pushed
+//! to `prioq` so they play before any song request.
+
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::{
+    communication::{LiquidsoapClient, LiquidsoapCommunication, RequestQueue},
+    db::{DbScheduleSlot, DbSong},
+    prelude::*,
+};
+
+fn week() -> chrono::Duration {
+    chrono::Duration::weeks(1)
+}
+
+fn length(slot: &DbScheduleSlot) -> chrono::Duration {
+    chrono::Duration::seconds(slot.length as i64)
+}
+
+/// The start of the slot's latest occurrence at `now`, if it has started at all.
+pub fn latest_occurrence(slot: &DbScheduleSlot, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    if now < slot.starts_at {
+        return None;
+    }
+    if !slot.repeats_weekly {
+        return Some(slot.starts_at);
+    }
+
+    let weeks = (now - slot.starts_at).num_seconds() / week().num_seconds();
+    Some(slot.starts_at + week() * weeks as i32)
+}
+
+/// The start of the occurrence that is on air at `now` or, if none is, the next one.
+pub fn upcoming_occurrence(slot: &DbScheduleSlot, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    match latest_occurrence(slot, now) {
+        None => Some(slot.starts_at),
+        Some(latest) if now < latest + length(slot) => Some(latest),
+        Some(latest) if slot.repeats_weekly => Some(latest + week()),
+        Some(_) => None,
+    }
+}
+
+/// The start of the occurrence that should be queued at `now`. An occurrence stays due until
+/// it is over, so slots still play what's left of them if Byers was down when they started.
+pub fn due_occurrence(slot: &DbScheduleSlot, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    let latest = latest_occurrence(slot, now)?;
+    if now >= latest + length(slot) {
+        return None;
+    }
+    if slot.last_played_at.is_some_and(|played| played >= latest) {
+        return None;
+    }
+
+    Some(latest)
+}
+
+/// Seconds of the occurrence starting at `occurrence` that are left at `now`.
+fn remaining(slot: &DbScheduleSlot, occurrence: NaiveDateTime, now: NaiveDateTime) -> f64 {
+    let left = occurrence + length(slot) - now.max(occurrence);
+    left.num_seconds() as f64
+}
+
+/// Whether the slots would ever be on air at the same time.
+pub fn overlaps(a: &DbScheduleSlot, b: &DbScheduleSlot) -> bool {
+    let week = week().num_seconds();
+    if a.repeats_weekly && b.repeats_weekly {
+        let offset = (b.starts_at - a.starts_at).num_seconds().rem_euclid(week);
+        return offset < a.length as i64 || week - offset < b.length as i64;
+    }
+
+    // slots are shorter than a week, so only the occurrence of the other slot that is on air
+    // when the one-off slot starts, or the one after it, can overlap it
+    let (other, once) = if b.repeats_weekly { (b, a) } else { (a, b) };
+    upcoming_occurrence(other, once.starts_at)
+        .is_some_and(|start| start < once.starts_at + length(once))
+}
+
+/// Takes songs in order until they fill `length` seconds. The last song may run over.
+fn fill(songs: Vec<DbSong>, length: f64) -> Vec<DbSong> {
+    let mut total = 0.0;
+    songs
+        .into_iter()
+        .take_while(|song| {
+            let fits = total < length;
+            total += song.duration;
+            fits
+        })
+        .collect()
+}
+
+pub struct Scheduler<'a, C> {
+    db: &'a PgPool,
+    comms: &'a LiquidsoapClient<C>,
+    interval: Duration,
+}
+
+impl<'a, C> Scheduler<'a, C>
+where
+    C: LiquidsoapCommunication<Error = JudeHarleyError> + Send + 'static,
+{
+    pub fn new(db: &'a PgPool, comms: &'a LiquidsoapClient<C>, interval: Duration) -> Self {
+        Self {
+            db,
+            comms,
+            interval,
+        }
+    }
+
+    /// Checks for slots that are due every `interval`, forever.
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.tick().await {
+                error!("Failed to run the schedule: {}", e);
+            }
+        }
+    }
+
+    /// Queues the songs of every slot that is due and returns how many slots started.
+    pub async fn tick(&self) -> Result<usize> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut started = 0;
+        for slot in DbScheduleSlot::fetch_all(self.db).await? {
+            let Some(occurrence) = due_occurrence(&slot, now) else {
+                continue;
+            };
+
+            let left = remaining(&slot, occurrence, now);
+            let songs = fill(slot.songs(self.db).await?, left);
+            // marked first, so songs that were pushed aren't pushed again if a later one fails
+            slot.mark_played(self.db, occurrence).await?;
+            if songs.is_empty() {
+                warn!("Scheduled slot {} has no songs to play", slot.name);
+            } else if left < slot.length as f64 {
+                info!(
+                    "Starting scheduled slot {} late with {} songs for the {} minutes left",
+                    slot.name,
+                    songs.len(),
+                    (left / 60.0).ceil()
+                );
+            } else {
+                info!(
+                    "Starting scheduled slot {} with {} songs",
+                    slot.name,
+                    songs.len()
+                );
+            }
+            for song in &songs {
+                self.comms
+                    .push(RequestQueue::PriorityRequests, &song.file_path)
+                    .await?;
+            }
+
+            started += 1;
+        }
+
+        Ok(started)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::db::ScheduleSlotKind;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 11, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn slot(starts_at: NaiveDateTime, repeats_weekly: bool) -> DbScheduleSlot {
+        DbScheduleSlot {
+            id: 1,
+            name: "Album Hour".to_string(),
+            kind: ScheduleSlotKind::Album,
+            album: Some("Homestuck Vol. 5".to_string()),
+            tag: None,
+            tag_value: None,
+            playlist_id: None,
+            playlist_name: None,
+            starts_at,
+            length: 3600,
+            repeats_weekly,
+            last_played_at: None,
+            created_at: starts_at,
+        }
+    }
+
+    #[test]
+    fn test_one_off_occurrences() {
+        let slot = slot(at(10, 20), false);
+
+        assert_eq!(latest_occurrence(&slot, at(10, 19)), None);
+        assert_eq!(upcoming_occurrence(&slot, at(10, 19)), Some(at(10, 20)));
+        assert_eq!(due_occurrence(&slot, at(10, 19)), None);
+
+        assert_eq!(due_occurrence(&slot, at(10, 20)), Some(at(10, 20)));
+        assert_eq!(upcoming_occurrence(&slot, at(10, 20)), Some(at(10, 20)));
+
+        assert_eq!(due_occurrence(&slot, at(10, 21)), None);
+        assert_eq!(upcoming_occurrence(&slot, at(10, 21)), None);
+    }
+
+    #[test]
+    fn test_weekly_occurrences() {
+        let mut slot = slot(at(3, 20), true);
+
+        assert_eq!(latest_occurrence(&slot, at(12, 8)), Some(at(10, 20)));
+        assert_eq!(upcoming_occurrence(&slot, at(12, 8)), Some(at(17, 20)));
+        assert_eq!(due_occurrence(&slot, at(12, 8)), None);
+
+        assert_eq!(due_occurrence(&slot, at(17, 20)), Some(at(17, 20)));
+        slot.last_played_at = Some(at(17, 20));
+        assert_eq!(due_occurrence(&slot, at(17, 20)), None);
+        assert_eq!(upcoming_occurrence(&slot, at(17, 20)), Some(at(17, 20)));
+        assert_eq!(due_occurrence(&slot, at(24, 20)), Some(at(24, 20)));
+    }
+
+    #[test]
+    fn test_remaining() {
+        let slot = slot(at(10, 20), false);
+
+        assert_eq!(remaining(&slot, at(10, 20), at(10, 20)), 3600.0);
+        assert_eq!(
+            remaining(
+                &slot,
+                at(10, 20),
+                at(10, 20) + chrono::Duration::minutes(45)
+            ),
+            900.0
+        );
+        assert_eq!(remaining(&slot, at(10, 20), at(10, 19)), 3600.0);
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let once = slot(at(10, 20), false);
+        assert!(overlaps(&once, &slot(at(10, 20), false)));
+        assert!(overlaps(
+            &once,
+            &slot(at(10, 19) + chrono::Duration::minutes(30), false)
+        ));
+        assert!(!overlaps(&once, &slot(at(10, 21), false)));
+        assert!(!overlaps(&once, &slot(at(10, 19), false)));
+
+        let weekly = slot(at(3, 20), true);
+        assert!(overlaps(&weekly, &once));
+        assert!(overlaps(&once, &weekly));
+        assert!(!overlaps(&weekly, &slot(at(10, 21), false)));
+        // it only repeats after it first started
+        assert!(!overlaps(&slot(at(17, 20), true), &once));
+
+        assert!(overlaps(
+            &weekly,
+            &slot(at(24, 20) + chrono::Duration::minutes(59), true)
+        ));
+        assert!(overlaps(
+            &weekly,
+            &slot(at(10, 19) + chrono::Duration::minutes(1), true)
+        ));
+        assert!(!overlaps(&weekly, &slot(at(24, 21), true)));
+        assert!(!overlaps(&weekly, &slot(at(3, 19), true)));
+    }
+
+    #[test]
+    fn test_fill() {
+        let songs = (0..5)
+            .map(|i| DbSong::fixture(&i.to_string(), 1000.0))
+            .collect::<Vec<_>>();
+
+        assert_eq!(fill(songs.clone(), 3600.0).len(), 4);
+        assert_eq!(fill(songs.clone(), 3000.0).len(), 3);
+        assert_eq!(fill(songs, 0.0).len(), 0);
+    }
+}
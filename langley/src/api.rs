@@ -0,0 +1,196 @@
+use std::{collections::HashMap, convert::Infallible};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{
+        sse::{Event, KeepAlive},
+        IntoResponse, Response, Sse,
+    },
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures::{future, stream, Stream, StreamExt};
+use judeharley::{
+    db::{DbPlayedSong, DbSong},
+    events::{self, TrackStarted},
+    metrics, JudeHarleyError, PgPool,
+};
+use serde::{Deserialize, Serialize};
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::error;
+
+use crate::AppState;
+
+const DEFAULT_HISTORY_LIMIT: i64 = 20;
+const MAX_HISTORY_LIMIT: i64 = 100;
+
+pub struct ApiError(JudeHarleyError);
+
+impl From<JudeHarleyError> for ApiError {
+    fn from(value: JudeHarleyError) -> Self {
+        Self(value)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: &'static str,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        error!("API request failed: {}", self.0);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "internal server error",
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Fetches the plays before `before` as track events, newest first.
+pub async fn fetch_history(
+    db: &PgPool,
+    limit: i64,
+    before: Option<DateTime<Utc>>,
+) -> Result<Vec<TrackStarted>, JudeHarleyError> {
+    let played_songs =
+        DbPlayedSong::fetch_history(db, limit, before.map(|b| b.naive_utc())).await?;
+
+    let hashes = played_songs
+        .iter()
+        .map(|p| p.song_id.clone())
+        .collect::<Vec<_>>();
+    let songs = DbSong::fetch_by_hashes(db, &hashes)
+        .await?
+        .into_iter()
+        .map(|s| (s.file_hash.clone(), s))
+        .collect::<HashMap<_, _>>();
+
+    Ok(played_songs
+        .iter()
+        .filter_map(|p| songs.get(&p.song_id).map(|song| TrackStarted::new(song, p)))
+        .collect())
+}
+
+#[derive(Serialize)]
+pub struct NowPlaying {
+    #[serde(flatten)]
+    track: TrackStarted,
+    /// Seconds since the track started
+    elapsed: f64,
+    /// Seconds until the track ends, `None` if we don't know its duration
+    remaining: Option<f64>,
+}
+
+impl NowPlaying {
+    fn new(track: TrackStarted, now: DateTime<Utc>) -> Self {
+        let elapsed = ((now - track.started_at).num_milliseconds() as f64 / 1000.0).max(0.0);
+        let remaining = track.duration.map(|d| (d - elapsed).max(0.0));
+
+        Self {
+            track,
+            elapsed,
+            remaining,
+        }
+    }
+}
+
+/// `GET /now-playing`, `null` if nothing has been played yet
+pub async fn now_playing(State(app_state): State<AppState>) -> Json<Option<NowPlaying>> {
+    let track = app_state.now_playing.read().await.clone();
+
+    Json(track.map(|t| NowPlaying::new(t, Utc::now())))
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    limit: Option<i64>,
+    /// Only return tracks that started before this, for paging with the last `started_at`
+    before: Option<DateTime<Utc>>,
+}
+
+/// `GET /history?limit=&before=`
+pub async fn history(
+    State(app_state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<TrackStarted>>, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .clamp(1, MAX_HISTORY_LIMIT);
+
+    let history = fetch_history(&app_state.db, limit, query.before).await?;
+
+    Ok(Json(history))
+}
+
+/// `GET /events`, a Server-Sent Events stream that starts with the current track and then sends
+/// a `track_started` event with a versioned [`TrackStarted`] on every track change.
+pub async fn track_events(
+    State(app_state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = app_state.tracks.subscribe();
+    let current = app_state.now_playing.read().await.clone();
+    let mut shutdown = app_state.shutdown.clone();
+
+    // slow clients that lag behind just miss the tracks they couldn't keep up with
+    let tracks = stream::iter(current)
+        .chain(BroadcastStream::new(receiver).filter_map(|track| future::ready(track.ok())))
+        .filter_map(|track| {
+            let event = events::to_json(&track)
+                .ok()
+                .map(|data| Ok(Event::default().event("track_started").data(data)));
+            future::ready(event)
+        })
+        .take_until(async move {
+            let _ = shutdown.wait_for(|shutting_down| *shutting_down).await;
+        });
+
+    Sse::new(tracks).keep_alive(KeepAlive::default())
+}
+
+/// `GET /metrics`, in the Prometheus text format
+pub async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        metrics::render(&metrics::LANGLEY_METRICS),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn track(duration: Option<f64>) -> TrackStarted {
+        TrackStarted {
+            song_id: Some("abc".to_string()),
+            title: "Showtime".to_string(),
+            artist: "Toby Fox".to_string(),
+            album: "Homestuck Vol. 1".to_string(),
+            duration,
+            started_at: Utc.with_ymd_and_hms(2023, 11, 22, 18, 0, 0).unwrap(),
+            source: "playlist".to_string(),
+            played_song_id: Some(1),
+        }
+    }
+
+    #[test]
+    fn test_now_playing_times() {
+        let now = Utc.with_ymd_and_hms(2023, 11, 22, 18, 1, 0).unwrap();
+
+        let playing = NowPlaying::new(track(Some(92.5)), now);
+        assert_eq!(playing.elapsed, 60.0);
+        assert_eq!(playing.remaining, Some(32.5));
+
+        let overdue = NowPlaying::new(track(Some(30.0)), now);
+        assert_eq!(overdue.remaining, Some(0.0));
+
+        assert_eq!(NowPlaying::new(track(None), now).remaining, None);
+    }
+}
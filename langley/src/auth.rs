@@ -0,0 +1,67 @@
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use judeharley::metrics::LANGLEY_REJECTED_REQUESTS;
+use tracing::warn;
+
+use crate::AppState;
+
+/// Compares in constant time, so the secret can't be guessed byte by byte from response times.
+fn secrets_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Rejects requests that don't carry `Authorization: Bearer <LANGLEY_SECRET>`.
+///
+/// Lets everything through only if Langley was started with `LANGLEY_INSECURE=true` and no secret.
+pub async fn require_secret<B>(
+    State(app_state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(secret) = app_state.secret.as_deref() else {
+        return next.run(request).await;
+    };
+
+    let given = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match given {
+        Some(given) if secrets_match(secret, given) => next.run(request).await,
+        _ => {
+            LANGLEY_REJECTED_REQUESTS.inc();
+            let rejected = LANGLEY_REJECTED_REQUESTS.get();
+            warn!(
+                "Rejected unauthenticated {} {} ({} rejected so far)",
+                request.method(),
+                request.uri().path(),
+                rejected
+            );
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_match() {
+        assert!(secrets_match("hunter2", "hunter2"));
+        assert!(!secrets_match("hunter2", "hunter3"));
+        assert!(!secrets_match("hunter2", "hunter"));
+        assert!(!secrets_match("hunter2", ""));
+    }
+}
@@ -1,20 +1,42 @@
-use axum::extract::State;
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::extract::{FromRef, State};
 use axum::http::StatusCode;
 use axum::Json;
 use fred::pool::RedisPool;
-use fred::prelude::PubsubInterface;
 use fred::types::{PerformanceConfig, ReconnectPolicy, RedisConfig};
+use judeharley::{
+    communication::RequestQueue,
+    db::{DbPlayedSong, DbSong, DbSongRequest, DbUnknownTrack},
+    events::{self, SongRequestStarted, TrackStarted},
+    health,
+    metrics::{LANGLEY_TRACKS_PLAYED, LANGLEY_UNKNOWN_FILES},
+    JudeHarleyError,
+};
 
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use tracing::{debug, info};
+use tokio::sync::{broadcast, watch, RwLock};
+use tracing::{debug, error, info, warn};
+
+mod api;
+mod auth;
 
 #[derive(Deserialize, Debug)]
 struct Song {
     filename: String,
+    #[serde(default)]
     title: String,
+    #[serde(default)]
     artist: String,
+    #[serde(default)]
     album: String,
+    /// ID of the Liquidsoap source that played the track, e.g. `srq` or `prioq`
+    #[serde(default)]
+    source: String,
+    /// Liquidsoap request ID
+    #[serde(default)]
+    rid: String,
 }
 
 #[derive(Serialize, Debug)]
@@ -22,6 +44,57 @@ struct SongResponse {
     success: bool,
 }
 
+/// What we recorded about a track that started playing.
+struct Played {
+    song: DbSong,
+    played_song: DbPlayedSong,
+    /// The song request that was fulfilled, if any
+    request: Option<DbSongRequest>,
+}
+
+/// Records the play in the database. Returns `None` if the file isn't indexed, in which case
+/// it is quarantined in `unknown_tracks` instead.
+async fn record_play(
+    db: &PgPool,
+    song: &Song,
+    queue: Option<RequestQueue>,
+    request_id: Option<i32>,
+) -> Result<Option<Played>, JudeHarleyError> {
+    let Some(db_song) = DbSong::fetch(db, &song.filename).await? else {
+        let unknown =
+            DbUnknownTrack::record(db, &song.filename, &song.title, &song.artist, &song.album)
+                .await?;
+        LANGLEY_UNKNOWN_FILES.inc();
+        warn!(
+            "Played unindexed file {} ({} plays so far)",
+            unknown.file_path, unknown.play_count
+        );
+        return Ok(None);
+    };
+
+    let source = queue.map(|q| q.id()).unwrap_or("playlist");
+    let played_song = DbPlayedSong::insert(db, &db_song.file_hash, source, request_id).await?;
+
+    let mut request = None;
+    if let (Some(queue), Some(request_id)) = (queue, request_id) {
+        request = DbSongRequest::fulfil(db, queue.id(), request_id, &played_song).await?;
+
+        if let Some(request) = &request {
+            let dropped = DbSongRequest::drop_stale(db, queue.id(), request.created_at).await?;
+            debug!(
+                "Fulfilled song request {} by {}, dropped {} stale requests",
+                request.id, request.user_id, dropped
+            );
+        }
+    }
+
+    Ok(Some(Played {
+        song: db_song,
+        played_song,
+        request,
+    }))
+}
+
 async fn played(
     State(app_state): State<AppState>,
     Json(song): Json<Song>,
@@ -33,39 +106,95 @@ async fn played(
         );
     }
 
-    let db_song = sqlx::query!(
-        "SELECT file_hash FROM songs WHERE file_path = $1",
-        song.filename
-    )
-    .fetch_one(&app_state.db)
-    .await
-    .expect("Failed to query database");
-
-    sqlx::query!(
-        "INSERT INTO played_songs (song_id) VALUES ($1)",
-        db_song.file_hash
-    )
-    .execute(&app_state.db)
-    .await
-    .expect("Failed to query database");
-
-    let _ = app_state
-        .redis_pool
-        .publish::<i32, _, _>(
-            "byers:status",
-            format!("{} - {} - {}", song.album, song.artist, song.title),
-        )
-        .await;
+    let queue = RequestQueue::from_id(&song.source);
+    let request_id = song.rid.parse::<i32>().ok();
+    LANGLEY_TRACKS_PLAYED
+        .with_label_values(&[queue.map(|q| q.id()).unwrap_or("playlist")])
+        .inc();
+    let result = record_play(&app_state.db, &song, queue, request_id).await;
 
-    debug!("Played song: {}", song.filename);
+    // publish even if recording failed, so listeners don't get stuck on the previous track
+    let event = match &result {
+        Ok(Some(played)) => TrackStarted::new(&played.song, &played.played_song),
+        _ => TrackStarted::unindexed(
+            &song.title,
+            &song.artist,
+            &song.album,
+            queue.map(|q| q.id()).unwrap_or("playlist"),
+        ),
+    };
+    if let Err(e) = events::publish(&app_state.redis_pool, &event).await {
+        warn!("Failed to publish track event: {}", e);
+    }
+    *app_state.now_playing.write().await = Some(event.clone());
+    // nobody listening is fine
+    let _ = app_state.tracks.send(event);
+
+    if let (Ok(Some(played)), Some(queue)) = (&result, queue) {
+        if let Some(request) = &played.request {
+            let event = SongRequestStarted::new(&played.song, queue, request);
+            if let Err(e) = events::publish(&app_state.redis_pool, &event).await {
+                warn!("Failed to publish song request event: {}", e);
+            }
+        }
+    }
+
+    if let Err(e) = result {
+        error!("Failed to record play of {}: {}", song.filename, e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(SongResponse { success: false }),
+        );
+    }
+
+    debug!(
+        "Played song: {} - {} - {} ({})",
+        song.album, song.artist, song.title, song.filename
+    );
 
     (StatusCode::OK, Json(SongResponse { success: true }))
 }
 
-#[derive(Clone)]
-struct AppState {
+#[derive(FromRef, Clone)]
+pub struct AppState {
     redis_pool: RedisPool,
     db: PgPool,
+    /// The track on air, as far as we know
+    now_playing: Arc<RwLock<Option<TrackStarted>>>,
+    /// Every track that starts playing, for the event stream
+    tracks: broadcast::Sender<TrackStarted>,
+    /// Shared secret Liquidsoap has to send to `/played`
+    secret: Option<Arc<str>>,
+    /// Flips to `true` when we're shutting down, to end the event streams
+    shutdown: watch::Receiver<bool>,
+}
+
+/// Resolves on SIGINT or SIGTERM and tells the event streams to close, so the graceful shutdown
+/// doesn't wait for them forever.
+async fn shutdown_signal(shutdown: watch::Sender<bool>) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutting down...");
+    let _ = shutdown.send(true);
 }
 
 #[tokio::main]
@@ -86,15 +215,79 @@ async fn main() {
         .await
         .expect("Failed to connect to database");
 
-    let app_state = AppState { redis_pool, db };
+    let now_playing = api::fetch_history(&db, 1, None)
+        .await
+        .expect("Failed to query database")
+        .pop();
+    let (tracks, _) = broadcast::channel(16);
+
+    let secret = std::env::var("LANGLEY_SECRET")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .map(Arc::from);
+    let insecure = std::env::var("LANGLEY_INSECURE")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    match (&secret, insecure) {
+        (Some(_), _) => {}
+        (None, true) => warn!("LANGLEY_SECRET is not set, anybody can report played songs"),
+        (None, false) => {
+            error!("LANGLEY_SECRET must be set, or LANGLEY_INSECURE=true to accept anybody's played songs");
+            std::process::exit(1);
+        }
+    }
+
+    let bind_address = std::env::var("BIND_ADDRESS")
+        .map(|address| {
+            address
+                .parse::<SocketAddr>()
+                .expect("BIND_ADDRESS must be an address like 0.0.0.0:8000")
+        })
+        .unwrap_or_else(|_| health::default_bind_address());
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let app_state = AppState {
+        redis_pool: redis_pool.clone(),
+        db: db.clone(),
+        now_playing: Arc::new(RwLock::new(now_playing)),
+        tracks,
+        secret,
+        shutdown: shutdown_rx,
+    };
 
     let app = axum::Router::new()
-        .route("/played", axum::routing::post(played))
+        .route(
+            "/played",
+            axum::routing::post(played).route_layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                auth::require_secret,
+            )),
+        )
+        .route("/now-playing", axum::routing::get(api::now_playing))
+        .route("/history", axum::routing::get(api::history))
+        .route("/events", axum::routing::get(api::track_events))
+        .route("/healthz", axum::routing::get(health::healthz))
+        .route("/readyz", axum::routing::get(health::readyz))
+        .route("/metrics", axum::routing::get(api::metrics))
         .with_state(app_state);
 
-    info!("Listening on 0.0.0.0:8000");
-    axum::Server::bind(&"0.0.0.0:8000".parse().unwrap())
+    let server = match axum::Server::try_bind(&bind_address) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", bind_address, e);
+            std::process::exit(1);
+        }
+    };
+
+    info!("Listening on {}", bind_address);
+    if let Err(e) = server
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal(shutdown_tx))
         .await
-        .unwrap();
+    {
+        error!("Server error: {}", e);
+    }
+
+    redis_pool.quit_pool().await;
+    db.close().await;
 }